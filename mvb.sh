@@ -15,7 +15,7 @@ else
 fi
 
 # Run all programs with all simulators
-for sim in scalar pipelined outoforder
+for sim in scalar pipelined outoforder m0 fivestage
 do
   $CMD cargo run -- -s ${sim} programs/test1.elf
   $CMD cargo run -- -s ${sim} programs/test2.elf
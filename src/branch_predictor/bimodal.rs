@@ -0,0 +1,26 @@
+use crate::branch_predictor::{table_index, update_counter, DirectionPredictor};
+
+// A table of 2 bit saturating counters indexed by branch address
+pub struct Bimodal {
+    bits: u32,
+    table: Vec<u8>,
+}
+
+impl Bimodal {
+    pub fn new(bits: u32) -> Self {
+        Self {
+            bits,
+            table: vec![1; 1 << bits],
+        }
+    }
+}
+
+impl DirectionPredictor for Bimodal {
+    fn predict(&self, address: u32, _target: u32) -> bool {
+        self.table[table_index(address, self.bits)] >= 2
+    }
+
+    fn update(&mut self, address: u32, taken: bool) {
+        update_counter(&mut self.table[table_index(address, self.bits)], taken);
+    }
+}
@@ -0,0 +1,44 @@
+use crate::branch_predictor::BranchKind;
+
+#[derive(Clone, Copy)]
+pub struct BtbEntry {
+    pub address: u32,
+    pub target: u32,
+    pub kind: BranchKind,
+}
+
+// Direct mapped cache of the targets of previously taken branches
+pub struct BranchTargetBuffer {
+    entries: Vec<Option<BtbEntry>>,
+}
+
+impl BranchTargetBuffer {
+    pub fn new(size: usize) -> Self {
+        Self {
+            entries: vec![None; size],
+        }
+    }
+
+    fn index(&self, address: u32) -> usize {
+        (address >> 1) as usize % self.entries.len()
+    }
+
+    pub fn lookup(&self, address: u32) -> Option<BtbEntry> {
+        if self.entries.is_empty() {
+            return None;
+        }
+        self.entries[self.index(address)].filter(|e| e.address == address)
+    }
+
+    pub fn insert(&mut self, address: u32, target: u32, kind: BranchKind) {
+        if self.entries.is_empty() {
+            return;
+        }
+        let index = self.index(address);
+        self.entries[index] = Some(BtbEntry {
+            address,
+            target,
+            kind,
+        });
+    }
+}
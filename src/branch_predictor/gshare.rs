@@ -0,0 +1,35 @@
+use crate::branch_predictor::{table_index, update_counter, DirectionPredictor};
+
+// 2 bit saturating counters indexed by the branch address XOR the global branch history
+// https://people.eecs.berkeley.edu/~kubitron/courses/cs252-S09/handouts/papers/mcfarling-combining.pdf
+pub struct GShare {
+    bits: u32,
+    history: u32,
+    table: Vec<u8>,
+}
+
+impl GShare {
+    pub fn new(bits: u32) -> Self {
+        Self {
+            bits,
+            history: 0,
+            table: vec![1; 1 << bits],
+        }
+    }
+
+    fn index(&self, address: u32) -> usize {
+        table_index(address ^ (self.history << 1), self.bits)
+    }
+}
+
+impl DirectionPredictor for GShare {
+    fn predict(&self, address: u32, _target: u32) -> bool {
+        self.table[self.index(address)] >= 2
+    }
+
+    fn update(&mut self, address: u32, taken: bool) {
+        let index = self.index(address);
+        update_counter(&mut self.table[index], taken);
+        self.history = ((self.history << 1) | taken as u32) & ((1 << self.bits) - 1);
+    }
+}
@@ -0,0 +1,250 @@
+mod bimodal;
+mod btb;
+mod gshare;
+mod one_bit;
+mod ras;
+mod static_;
+mod tournament;
+
+use crate::instructions::Instruction;
+use crate::registers::ids::PC;
+use btb::BranchTargetBuffer;
+use capstone::arch::arm::ArmCC;
+use ras::ReturnAddressStack;
+use serde::Serialize;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BranchKind {
+    Conditional,
+    Unconditional,
+    Call,
+    Return,
+}
+
+impl BranchKind {
+    pub fn classify(instruction: &dyn Instruction, cc: ArmCC) -> Option<Self> {
+        if instruction.is_call() {
+            Some(BranchKind::Call)
+        } else if instruction.is_return() {
+            Some(BranchKind::Return)
+        } else if !instruction.dest_registers().contains(&PC) {
+            None
+        } else if let ArmCC::ARM_CC_AL = cc {
+            Some(BranchKind::Unconditional)
+        } else {
+            Some(BranchKind::Conditional)
+        }
+    }
+}
+
+pub trait DirectionPredictor: Send + Sync {
+    // Predict whether the conditional branch at this address will be taken
+    fn predict(&self, address: u32, target: u32) -> bool;
+
+    // Train the predictor with the resolved outcome of a conditional branch
+    fn update(&mut self, address: u32, taken: bool);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum PredictorType {
+    NotTaken,
+    BackwardTaken,
+    OneBit,
+    TwoBit,
+    GShare,
+    Tournament,
+}
+
+impl FromStr for PredictorType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "nottaken" => Ok(Self::NotTaken),
+            "btfn" => Ok(Self::BackwardTaken),
+            "onebit" => Ok(Self::OneBit),
+            "twobit" => Ok(Self::TwoBit),
+            "gshare" => Ok(Self::GShare),
+            "tournament" => Ok(Self::Tournament),
+            _ => Err("Couldn't match PredictorType".to_string()),
+        }
+    }
+}
+
+// Number of entries in the pattern history tables (as a power of 2)
+const TABLE_BITS: u32 = 10;
+
+// Thumb instructions are halfword aligned so ignore the lowest bit
+fn table_index(address: u32, bits: u32) -> usize {
+    ((address >> 1) & ((1 << bits) - 1)) as usize
+}
+
+// 2 bit saturating counter, values 2 and 3 predict taken
+fn update_counter(counter: &mut u8, taken: bool) {
+    if taken {
+        *counter = (*counter + 1).min(3);
+    } else {
+        *counter = counter.saturating_sub(1);
+    }
+}
+
+// Combines a direction predictor, branch target buffer and return address stack
+// to predict the next fetch address
+pub struct BranchPredictor {
+    predictor_type: PredictorType,
+    direction: Box<dyn DirectionPredictor>,
+    btb: BranchTargetBuffer,
+    ras: ReturnAddressStack,
+}
+
+impl BranchPredictor {
+    pub fn new(predictor_type: PredictorType, btb_entries: usize, ras_depth: usize) -> Self {
+        let direction: Box<dyn DirectionPredictor> = match predictor_type {
+            PredictorType::NotTaken => Box::new(static_::Static::NotTaken),
+            PredictorType::BackwardTaken => Box::new(static_::Static::BackwardTaken),
+            PredictorType::OneBit => Box::new(one_bit::OneBit::new(TABLE_BITS)),
+            PredictorType::TwoBit => Box::new(bimodal::Bimodal::new(TABLE_BITS)),
+            PredictorType::GShare => Box::new(gshare::GShare::new(TABLE_BITS)),
+            PredictorType::Tournament => Box::new(tournament::Tournament::new(TABLE_BITS)),
+        };
+        Self {
+            predictor_type,
+            direction,
+            btb: BranchTargetBuffer::new(btb_entries),
+            ras: ReturnAddressStack::new(ras_depth),
+        }
+    }
+
+    pub fn predictor_type(&self) -> PredictorType {
+        self.predictor_type
+    }
+
+    // The address to fetch after the instruction at `address`
+    pub fn predict(&self, address: u32, length: u32) -> u32 {
+        if let Some(entry) = self.btb.lookup(address) {
+            match entry.kind {
+                BranchKind::Conditional => {
+                    if self.direction.predict(address, entry.target) {
+                        return entry.target;
+                    }
+                }
+                BranchKind::Unconditional | BranchKind::Call => return entry.target,
+                BranchKind::Return => return self.ras.peek().unwrap_or(entry.target),
+            }
+        }
+        address + length
+    }
+
+    // Whether the prediction for the instruction at `address` comes from the return address stack
+    pub fn predicts_return(&self, address: u32) -> bool {
+        let is_return = matches!(self.btb.lookup(address), Some(e) if e.kind == BranchKind::Return);
+        is_return && self.ras.peek().is_some()
+    }
+
+    // Speculatively update the return address stack when a call or return is fetched
+    pub fn fetched(&mut self, address: u32, length: u32) {
+        if let Some(entry) = self.btb.lookup(address) {
+            match entry.kind {
+                BranchKind::Call => self.ras.push(address + length),
+                BranchKind::Return => self.ras.pop(),
+                _ => {}
+            }
+        }
+    }
+
+    // Train with the outcome of a branch once it has been resolved
+    pub fn update(
+        &mut self,
+        address: u32,
+        length: u32,
+        kind: BranchKind,
+        taken: bool,
+        target: u32,
+    ) {
+        match kind {
+            BranchKind::Conditional => self.direction.update(address, taken),
+            BranchKind::Call => self.ras.commit_push(address + length),
+            BranchKind::Return => self.ras.commit_pop(),
+            BranchKind::Unconditional => {}
+        }
+        // Predicting everything as not taken only needs the targets of calls and returns
+        let needs_target = match self.predictor_type {
+            PredictorType::NotTaken => matches!(kind, BranchKind::Call | BranchKind::Return),
+            _ => true,
+        };
+        if taken && needs_target {
+            self.btb.insert(address, target, kind);
+        }
+    }
+
+    // The pipeline has been flushed after a misprediction
+    pub fn recover(&mut self) {
+        self.ras.recover();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::tests::sum_program;
+    use crate::{SimulationBuilder, SimulatorType};
+
+    #[test]
+    fn learns_loop_branch() {
+        for t in &["onebit", "twobit", "gshare", "tournament"] {
+            let mut predictor = BranchPredictor::new(t.parse().unwrap(), 16, 0);
+            assert_eq!(predictor.predict(0x1009, 2), 0x100B);
+            for _ in 0..20 {
+                predictor.update(0x1009, 2, BranchKind::Conditional, true, 0x1001);
+            }
+            assert_eq!(predictor.predict(0x1009, 2), 0x1001, "{}", t);
+        }
+    }
+
+    #[test]
+    fn static_predictors() {
+        let mut predictor = BranchPredictor::new(PredictorType::NotTaken, 16, 0);
+        predictor.update(0x1009, 2, BranchKind::Unconditional, true, 0x1001);
+        assert_eq!(predictor.predict(0x1009, 2), 0x100B);
+        // Calls and returns are kept for the return address stack
+        predictor.update(0x2001, 4, BranchKind::Call, true, 0x3001);
+        assert_eq!(predictor.predict(0x2001, 4), 0x3001);
+
+        let mut predictor = BranchPredictor::new(PredictorType::BackwardTaken, 16, 0);
+        predictor.update(0x1009, 2, BranchKind::Conditional, true, 0x1001);
+        predictor.update(0x2001, 2, BranchKind::Conditional, true, 0x2011);
+        assert_eq!(predictor.predict(0x1009, 2), 0x1001);
+        assert_eq!(predictor.predict(0x2001, 2), 0x2003);
+    }
+
+    #[test]
+    fn return_predictions() {
+        let mut predictor = BranchPredictor::new(PredictorType::NotTaken, 16, 4);
+        predictor.update(0x2001, 2, BranchKind::Return, true, 0x1005);
+        // Nothing has been pushed, so the target comes from the branch target buffer
+        assert_eq!(predictor.predict(0x2001, 2), 0x1005);
+        assert!(!predictor.predicts_return(0x2001));
+
+        predictor.update(0x1011, 4, BranchKind::Call, true, 0x2001);
+        predictor.fetched(0x1011, 4);
+        assert_eq!(predictor.predict(0x2001, 2), 0x1015);
+        assert!(predictor.predicts_return(0x2001));
+    }
+
+    #[test]
+    fn branch_prediction() {
+        let baseline = SimulationBuilder::new(SimulatorType::Pipelined)
+            .run(sum_program())
+            .unwrap();
+        assert_eq!(baseline.branch_mispredictions, 9);
+        let predicted = SimulationBuilder::new(SimulatorType::Pipelined)
+            .predictor(PredictorType::TwoBit)
+            .run(sum_program())
+            .unwrap();
+        assert_eq!(predicted.instructions_executed, 32);
+        // Mispredicted the first time the branch is taken, and when exiting the loop
+        assert_eq!(predicted.branch_mispredictions, 2);
+        assert!(predicted.total_cycles < baseline.total_cycles);
+    }
+}
@@ -0,0 +1,26 @@
+use crate::branch_predictor::{table_index, DirectionPredictor};
+
+// Predicts the same outcome as the last time the branch was executed
+pub struct OneBit {
+    bits: u32,
+    table: Vec<bool>,
+}
+
+impl OneBit {
+    pub fn new(bits: u32) -> Self {
+        Self {
+            bits,
+            table: vec![false; 1 << bits],
+        }
+    }
+}
+
+impl DirectionPredictor for OneBit {
+    fn predict(&self, address: u32, _target: u32) -> bool {
+        self.table[table_index(address, self.bits)]
+    }
+
+    fn update(&mut self, address: u32, taken: bool) {
+        self.table[table_index(address, self.bits)] = taken;
+    }
+}
@@ -0,0 +1,104 @@
+// Predicts the target of function returns, by pushing the return address of each call
+pub struct ReturnAddressStack {
+    depth: usize,
+    speculative: Vec<u32>, // Updated as calls and returns are fetched
+    committed: Vec<u32>,   // Updated as calls and returns are resolved
+}
+
+impl ReturnAddressStack {
+    pub fn new(depth: usize) -> Self {
+        Self {
+            depth,
+            speculative: Vec::with_capacity(depth),
+            committed: Vec::with_capacity(depth),
+        }
+    }
+
+    pub fn peek(&self) -> Option<u32> {
+        self.speculative.last().copied()
+    }
+
+    pub fn push(&mut self, return_address: u32) {
+        Self::push_bounded(&mut self.speculative, self.depth, return_address);
+    }
+
+    pub fn pop(&mut self) {
+        self.speculative.pop();
+    }
+
+    pub fn commit_push(&mut self, return_address: u32) {
+        Self::push_bounded(&mut self.committed, self.depth, return_address);
+    }
+
+    pub fn commit_pop(&mut self) {
+        self.committed.pop();
+    }
+
+    // Discard the effects of calls and returns that were fetched down a mispredicted path
+    pub fn recover(&mut self) {
+        self.speculative = self.committed.clone();
+    }
+
+    // When the stack overflows the oldest return address is lost
+    fn push_bounded(stack: &mut Vec<u32>, depth: usize, return_address: u32) {
+        if depth == 0 {
+            return;
+        }
+        if stack.len() == depth {
+            stack.remove(0);
+        }
+        stack.push(return_address);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::tests::call_program;
+    use crate::registers::ids::R5;
+    use crate::{PredictorType, SimulationBuilder, SimulatorType};
+
+    #[test]
+    fn overflow_and_recover() {
+        let mut ras = ReturnAddressStack::new(2);
+        ras.push(1);
+        ras.commit_push(1);
+        ras.push(2);
+        ras.push(3);
+        assert_eq!(ras.peek(), Some(3));
+        ras.pop();
+        assert_eq!(ras.peek(), Some(2));
+        ras.pop();
+        assert_eq!(ras.peek(), None);
+        ras.recover();
+        assert_eq!(ras.peek(), Some(1));
+    }
+
+    #[test]
+    fn return_address_stack() {
+        for sim in &[SimulatorType::Pipelined, SimulatorType::OutOfOrder] {
+            let builder = SimulationBuilder::new(*sim).predictor(PredictorType::TwoBit);
+            let mut simulation = builder.build(call_program()).unwrap();
+            simulation.run_until(&[]);
+            assert_eq!(simulation.state.registers.read_by_id(R5), 60, "{:?}", sim);
+            // Only the very first return is missing from the branch target buffer
+            assert_eq!(simulation.stats.return_stack_hits, 9, "{:?}", sim);
+            assert_eq!(simulation.stats.return_stack_misses, 1, "{:?}", sim);
+
+            // The branch target buffer alone always predicts the previous return address
+            let without = builder.return_stack_depth(0).run(call_program()).unwrap();
+            assert_eq!(without.return_stack_misses, 10, "{:?}", sim);
+            assert_eq!(
+                without.branch_mispredictions,
+                simulation.stats.branch_mispredictions + 9
+            );
+        }
+
+        // The default not taken predictor still remembers calls and returns
+        let stats = SimulationBuilder::new(SimulatorType::Pipelined)
+            .run(call_program())
+            .unwrap();
+        assert_eq!(stats.return_stack_hits, 9);
+        assert_eq!(stats.return_stack_misses, 1);
+    }
+}
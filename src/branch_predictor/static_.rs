@@ -0,0 +1,18 @@
+use crate::branch_predictor::DirectionPredictor;
+
+pub enum Static {
+    NotTaken,
+    // Backward taken, forward not taken - loops are usually taken
+    BackwardTaken,
+}
+
+impl DirectionPredictor for Static {
+    fn predict(&self, address: u32, target: u32) -> bool {
+        match self {
+            Static::NotTaken => false,
+            Static::BackwardTaken => target < address,
+        }
+    }
+
+    fn update(&mut self, _address: u32, _taken: bool) {}
+}
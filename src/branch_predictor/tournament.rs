@@ -0,0 +1,46 @@
+use crate::branch_predictor::bimodal::Bimodal;
+use crate::branch_predictor::gshare::GShare;
+use crate::branch_predictor::{table_index, update_counter, DirectionPredictor};
+
+// Chooses between a local (bimodal) and global (gshare) predictor per branch
+// Based on the Alpha 21264 https://ieeexplore.ieee.org/document/755465
+pub struct Tournament {
+    bits: u32,
+    local: Bimodal,
+    global: GShare,
+    chooser: Vec<u8>, // Values 2 and 3 prefer the global predictor
+}
+
+impl Tournament {
+    pub fn new(bits: u32) -> Self {
+        Self {
+            bits,
+            local: Bimodal::new(bits),
+            global: GShare::new(bits),
+            chooser: vec![1; 1 << bits],
+        }
+    }
+}
+
+impl DirectionPredictor for Tournament {
+    fn predict(&self, address: u32, target: u32) -> bool {
+        if self.chooser[table_index(address, self.bits)] >= 2 {
+            self.global.predict(address, target)
+        } else {
+            self.local.predict(address, target)
+        }
+    }
+
+    fn update(&mut self, address: u32, taken: bool) {
+        let local_correct = self.local.predict(address, 0) == taken;
+        let global_correct = self.global.predict(address, 0) == taken;
+        if local_correct != global_correct {
+            update_counter(
+                &mut self.chooser[table_index(address, self.bits)],
+                global_correct,
+            );
+        }
+        self.local.update(address, taken);
+        self.global.update(address, taken);
+    }
+}
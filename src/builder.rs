@@ -0,0 +1,608 @@
+use crate::branch_predictor::{BranchPredictor, PredictorType};
+use crate::cache::hierarchy::MemoryHierarchy;
+use crate::cache::main_memory::{MainMemory, MemoryRegion, MemoryTiming};
+use crate::cache::{Cache, CacheConfig};
+use crate::cpu_state::exceptions::Exceptions;
+use crate::cpu_state::functional_units::{FunctionalUnits, Multiplier};
+use crate::cpu_state::rename::PhysicalRegisterFile;
+use crate::cpu_state::reorder_buffer::ReorderBuffer;
+use crate::cpu_state::{CpuComponents, CpuConfig, CpuState};
+use crate::error::{Fault, SimulationError};
+use crate::instructions::Latencies;
+use crate::memory::MemoryAccessError;
+use crate::program::Program;
+use crate::registers::RegisterFile;
+use crate::simulators::cortex_m0::CortexM0Simulator;
+use crate::simulators::five_stage::{BranchStage, FiveStageSimulator, Forwarding};
+use crate::simulators::non_pipelined::NonPipelinedSimulator;
+use crate::simulators::out_of_order::OutOfOrderSimulator;
+use crate::simulators::pipelined::PipelinedSimulator;
+use crate::simulators::simulation::Simulation;
+use crate::simulators::{SimulationStats, Simulator};
+use crate::{DebugLevel, SimulatorType, DEFAULT_STACK_SIZE, DEFAULT_STACK_TOP};
+use serde::Serialize;
+
+// Configures and runs a simulation of a loaded Program
+#[derive(Clone, Serialize)]
+pub struct SimulationBuilder {
+    simulator_type: SimulatorType,
+    units: usize,
+    stack_top: u32,
+    stack_size: u32,
+    vector_table: Option<u32>,
+    debug_level: DebugLevel,
+    predictor: PredictorType,
+    btb_entries: usize,
+    return_stack_depth: usize,
+    rob_entries: usize,
+    physical_registers: usize,
+    instruction_cache: Option<CacheConfig>,
+    data_cache: Option<CacheConfig>,
+    l2_cache: Option<CacheConfig>,
+    memory_timing: MemoryTiming,
+    flash_timing: Option<MemoryTiming>,
+    memory_regions: Vec<MemoryRegion>,
+    decoded_queue_capacity: usize,
+    fetch_width: usize,
+    decode_width: usize,
+    issue_width: usize,
+    commit_width: Option<usize>,
+    latencies: Latencies,
+    functional_units: Option<FunctionalUnits>,
+    multiplier: Multiplier,
+    forwarding: Forwarding,
+    branch_stage: BranchStage,
+}
+
+impl Default for SimulationBuilder {
+    fn default() -> Self {
+        Self {
+            simulator_type: SimulatorType::OutOfOrder,
+            units: 4,
+            stack_top: DEFAULT_STACK_TOP,
+            stack_size: DEFAULT_STACK_SIZE,
+            vector_table: None,
+            debug_level: DebugLevel::Off,
+            predictor: PredictorType::NotTaken,
+            btb_entries: 64,
+            return_stack_depth: 8,
+            rob_entries: 32,
+            physical_registers: 64,
+            instruction_cache: None,
+            data_cache: None,
+            l2_cache: None,
+            memory_timing: MemoryTiming::Fixed(1),
+            flash_timing: None,
+            memory_regions: vec![],
+            decoded_queue_capacity: 6,
+            fetch_width: 1,
+            decode_width: 1,
+            issue_width: 1,
+            commit_width: None,
+            latencies: Latencies::default(),
+            functional_units: None,
+            multiplier: Multiplier::Iterative,
+            forwarding: Forwarding {
+                ex_to_ex: true,
+                mem_to_ex: true,
+            },
+            branch_stage: BranchStage::Execute,
+        }
+    }
+}
+
+impl SimulationBuilder {
+    pub fn new(simulator_type: SimulatorType) -> Self {
+        Self {
+            simulator_type,
+            ..Default::default()
+        }
+    }
+
+    pub fn simulator(mut self, simulator_type: SimulatorType) -> Self {
+        self.simulator_type = simulator_type;
+        self
+    }
+
+    // Number of reservation stations / execution units (out of order simulator only)
+    pub fn units(mut self, units: usize) -> Self {
+        self.units = units;
+        self
+    }
+
+    pub fn stack_top(mut self, stack_top: u32) -> Self {
+        self.stack_top = stack_top;
+        self
+    }
+
+    pub fn stack_size(mut self, stack_size: u32) -> Self {
+        self.stack_size = stack_size;
+        self
+    }
+
+    /*
+    Start from the reset vector and initial stack pointer in the program's vector table, and
+    enter exception handlers for faults and SVCs, like a Cortex-M0 does
+     */
+    pub fn vector_table(mut self, vector_table: Option<u32>) -> Self {
+        self.vector_table = vector_table;
+        self
+    }
+
+    pub fn debug_level(mut self, debug_level: DebugLevel) -> Self {
+        self.debug_level = debug_level;
+        self
+    }
+
+    pub fn predictor(mut self, predictor: PredictorType) -> Self {
+        self.predictor = predictor;
+        self
+    }
+
+    // Number of branch target buffer entries
+    pub fn btb_entries(mut self, btb_entries: usize) -> Self {
+        self.btb_entries = btb_entries;
+        self
+    }
+
+    // Number of return address stack entries, 0 disables it
+    pub fn return_stack_depth(mut self, return_stack_depth: usize) -> Self {
+        self.return_stack_depth = return_stack_depth;
+        self
+    }
+
+    // Number of reorder buffer entries, limits how many instructions can be in flight
+    pub fn rob_entries(mut self, rob_entries: usize) -> Self {
+        self.rob_entries = rob_entries;
+        self
+    }
+
+    // Size of the physical register file that architectural registers are renamed onto
+    pub fn physical_registers(mut self, physical_registers: usize) -> Self {
+        self.physical_registers = physical_registers;
+        self
+    }
+
+    // L1 instruction cache, None means fetch never misses
+    pub fn instruction_cache(mut self, instruction_cache: Option<CacheConfig>) -> Self {
+        self.instruction_cache = instruction_cache;
+        self
+    }
+
+    // L1 data cache, None means loads never miss
+    pub fn data_cache(mut self, data_cache: Option<CacheConfig>) -> Self {
+        self.data_cache = data_cache;
+        self
+    }
+
+    // Unified L2 cache behind both L1 caches
+    pub fn l2_cache(mut self, l2_cache: Option<CacheConfig>) -> Self {
+        self.l2_cache = l2_cache;
+        self
+    }
+
+    // Latency of main memory at addresses not covered by any other region
+    pub fn memory_timing(mut self, memory_timing: MemoryTiming) -> Self {
+        self.memory_timing = memory_timing;
+        self
+    }
+
+    // Latency of the program's executable segments, e.g. flash wait states
+    pub fn flash_timing(mut self, flash_timing: Option<MemoryTiming>) -> Self {
+        self.flash_timing = flash_timing;
+        self
+    }
+
+    // Give an address range its own latency, takes priority over the flash and default timings
+    pub fn memory_region(mut self, region: MemoryRegion) -> Self {
+        self.memory_regions.push(region);
+        self
+    }
+
+    // Number of decoded instructions that can wait to be issued
+    pub fn decoded_queue_capacity(mut self, decoded_queue_capacity: usize) -> Self {
+        self.decoded_queue_capacity = decoded_queue_capacity;
+        self
+    }
+
+    // Instructions fetched per cycle, a power of two, from an aligned block of 4 bytes per instruction (out of order simulator only)
+    pub fn fetch_width(mut self, fetch_width: usize) -> Self {
+        self.fetch_width = fetch_width;
+        self
+    }
+
+    // Instructions decoded per cycle (out of order simulator only)
+    pub fn decode_width(mut self, decode_width: usize) -> Self {
+        self.decode_width = decode_width;
+        self
+    }
+
+    // Instructions issued to stations per cycle (out of order simulator only)
+    pub fn issue_width(mut self, issue_width: usize) -> Self {
+        self.issue_width = issue_width;
+        self
+    }
+
+    // Instructions committed per cycle, None means one per station
+    pub fn commit_width(mut self, commit_width: Option<usize>) -> Self {
+        self.commit_width = commit_width;
+        self
+    }
+
+    // Cycles taken by data processing instructions other than MUL
+    pub fn alu_latency(mut self, alu: u32) -> Self {
+        self.latencies.alu = alu;
+        self
+    }
+
+    // Total cycles taken by MUL
+    pub fn multiply_latency(mut self, multiply: u32) -> Self {
+        self.latencies.multiply = multiply;
+        self
+    }
+
+    // Cycles taken by branches to resolve
+    pub fn branch_latency(mut self, branch: u32) -> Self {
+        self.latencies.branch = branch;
+        self
+    }
+
+    // Cycles taken by loads and stores after accessing memory
+    pub fn load_store_latency(mut self, load_store: u32) -> Self {
+        self.latencies.load_store = load_store;
+        self
+    }
+
+    // Cycles LDR / STR spend calculating the address before accessing memory
+    pub fn address_latency(mut self, address: u32) -> Self {
+        self.latencies.address = address;
+        self
+    }
+
+    // Bind the stations to typed functional units, replacing the generic units (out of order simulator only)
+    pub fn functional_units(mut self, functional_units: Option<FunctionalUnits>) -> Self {
+        self.functional_units = functional_units;
+        self
+    }
+
+    // Whether the multiplier can start another MUL before the previous one has finished
+    pub fn multiplier(mut self, multiplier: Multiplier) -> Self {
+        self.multiplier = multiplier;
+        self
+    }
+
+    // Forwarding paths of the five stage pipeline
+    pub fn forwarding(mut self, forwarding: Forwarding) -> Self {
+        self.forwarding = forwarding;
+        self
+    }
+
+    // Stage of the five stage pipeline that branches are resolved in
+    pub fn branch_stage(mut self, branch_stage: BranchStage) -> Self {
+        self.branch_stage = branch_stage;
+        self
+    }
+
+    pub fn predictor_type(&self) -> PredictorType {
+        self.predictor
+    }
+
+    // Check the configuration can be simulated, so that mistakes are reported before running
+    pub fn validate(&self) -> Result<(), String> {
+        let at_least = |name: &str, value: usize, min: usize| {
+            if value < min {
+                Err(format!("{} must be at least {}, not {}", name, min, value))
+            } else {
+                Ok(())
+            }
+        };
+        at_least("units", self.units, 1)?;
+        at_least("rob entries", self.rob_entries, 1)?;
+        at_least(
+            "physical registers",
+            self.physical_registers,
+            PhysicalRegisterFile::minimum_size(),
+        )?;
+        at_least("decoded queue capacity", self.decoded_queue_capacity, 1)?;
+        at_least("fetch width", self.fetch_width, 1)?;
+        if !self.fetch_width.is_power_of_two() {
+            return Err(format!(
+                "fetch width must be a power of two, not {}",
+                self.fetch_width
+            ));
+        }
+        at_least("decode width", self.decode_width, 1)?;
+        at_least("issue width", self.issue_width, 1)?;
+        at_least("commit width", self.commit_width.unwrap_or(1), 1)?;
+        at_least("alu latency", self.latencies.alu as usize, 1)?;
+        at_least("multiply latency", self.latencies.multiply as usize, 1)?;
+        at_least("branch latency", self.latencies.branch as usize, 1)?;
+        at_least("load/store latency", self.latencies.load_store as usize, 1)?;
+        if let Some(units) = &self.functional_units {
+            at_least("alu units", units.alu, 1)?;
+            at_least("multiply units", units.multiply, 1)?;
+            at_least("branch units", units.branch, 1)?;
+            at_least("load/store units", units.load_store, 1)?;
+        }
+        if self.stack_size > self.stack_top {
+            return Err(format!(
+                "Stack of {} bytes does not fit below {:#X}",
+                self.stack_size, self.stack_top
+            ));
+        }
+        let caches = [
+            ("instruction cache", &self.instruction_cache),
+            ("data cache", &self.data_cache),
+            ("l2 cache", &self.l2_cache),
+        ];
+        for (name, cache) in caches.iter() {
+            if let Some(cache) = cache {
+                cache
+                    .validate()
+                    .map_err(|e| format!("Invalid {}: {}", name, e))?;
+            }
+        }
+        for region in &self.memory_regions {
+            if region.start >= region.end {
+                return Err(format!(
+                    "Memory region {:#X}-{:#X} is empty",
+                    region.start, region.end
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn build_simulator(&self) -> Box<dyn Simulator> {
+        match self.simulator_type {
+            SimulatorType::Scalar => Box::new(NonPipelinedSimulator::new()),
+            SimulatorType::Pipelined => Box::new(PipelinedSimulator::new()),
+            SimulatorType::OutOfOrder => Box::new(OutOfOrderSimulator::new(
+                self.functional_units.map_or(self.units, |u| u.total()),
+            )),
+            SimulatorType::CortexM0 => Box::new(CortexM0Simulator::new(self.multiplier)),
+            SimulatorType::FiveStage => {
+                Box::new(FiveStageSimulator::new(self.forwarding, self.branch_stage))
+            }
+        }
+    }
+
+    // Map the stack into the program's memory, ready to be stepped through
+    pub fn build(&self, program: Program) -> Result<Simulation, SimulationError> {
+        let reset_pc = program.entry & 0xFFFFFFFE;
+        let invalid = |reason: String| {
+            SimulationError::new(
+                Fault::InvalidConfiguration(reason),
+                reset_pc,
+                "(reset)",
+                &RegisterFile::new(self.stack_top),
+            )
+        };
+        let stack_error = |e: MemoryAccessError| match e {
+            MemoryAccessError::Overlap(address) => invalid(format!(
+                "The stack overlaps the program's memory at {:#X}",
+                address
+            )),
+            e => invalid(format!("Failed to map the stack: {:?}", e)),
+        };
+        self.validate().map_err(invalid)?;
+        let mut memory = program.memory;
+        let mut entry = program.entry;
+        let mut stack_top = self.stack_top;
+        let mut bad_vector_table = None;
+        if let Some(vector_table) = self.vector_table {
+            let initial_sp = memory.read_u32(vector_table);
+            let reset = memory.read_u32(vector_table.wrapping_add(4));
+            match (initial_sp, reset) {
+                (Ok(initial_sp), Ok(reset)) => {
+                    entry = reset;
+                    stack_top = initial_sp;
+                    // Firmware usually places its stack in a RAM segment of its own
+                    if memory.read_u32(stack_top.wrapping_sub(4)).is_err() {
+                        memory
+                            .mmap(
+                                stack_top.saturating_sub(self.stack_size),
+                                vec![0; self.stack_size.min(stack_top) as usize],
+                                true,
+                            )
+                            .map_err(stack_error)?;
+                    }
+                }
+                _ => bad_vector_table = Some(Fault::BadAddress(vector_table)),
+            }
+        } else {
+            let stack_base = self.stack_top.checked_sub(self.stack_size).ok_or_else(|| {
+                invalid(format!(
+                    "Stack of {} bytes does not fit below {:#X}",
+                    self.stack_size, self.stack_top
+                ))
+            })?;
+            memory
+                .mmap(stack_base, vec![0; self.stack_size as usize], true)
+                .map_err(stack_error)?;
+        }
+        let mut regions = self.memory_regions.clone();
+        if let Some(timing) = self.flash_timing {
+            for (start, end) in &program.code_segments {
+                regions.push(MemoryRegion {
+                    start: *start,
+                    end: *end,
+                    timing,
+                });
+            }
+        }
+        let memory_hierarchy = MemoryHierarchy {
+            instruction_cache: self.instruction_cache.map(Cache::new),
+            data_cache: self.data_cache.map(Cache::new),
+            l2_cache: self.l2_cache.map(Cache::new),
+            main_memory: MainMemory::new(&regions, self.memory_timing),
+        };
+        let simulator = self.build_simulator();
+        // The in-order simulators handle one instruction at a time
+        let superscalar = |width| match self.simulator_type {
+            SimulatorType::OutOfOrder => width,
+            _ => 1,
+        };
+        let mut state = CpuState::new(
+            memory,
+            entry,
+            stack_top,
+            CpuComponents {
+                stations: match (self.simulator_type, self.functional_units) {
+                    (SimulatorType::OutOfOrder, Some(units)) => units.stations(),
+                    _ => vec![None; simulator.stations()],
+                },
+                branch_predictor: BranchPredictor::new(
+                    self.predictor,
+                    self.btb_entries,
+                    self.return_stack_depth,
+                ),
+                reorder_buffer: ReorderBuffer::new(self.rob_entries),
+                memory_hierarchy,
+            },
+            CpuConfig {
+                decoded_queue_capacity: self.decoded_queue_capacity,
+                fetch_width: superscalar(self.fetch_width),
+                decode_width: superscalar(self.decode_width),
+                issue_width: superscalar(self.issue_width),
+                commit_width: self.commit_width.unwrap_or_else(|| simulator.stations()),
+                physical_registers: self.physical_registers,
+                latencies: self.latencies,
+                multiplier: self.multiplier,
+            },
+        );
+        state.debug_info = program.debug_info;
+        state.exceptions = self.vector_table.map(Exceptions::new);
+        if let Some(fault) = bad_vector_table {
+            let pc = state.next_instr_addr & 0xFFFFFFFE;
+            state.error = Some(SimulationError::new(fault, pc, "(reset)", &state.registers));
+            state.should_terminate = true;
+        }
+        Ok(Simulation::new(simulator, state, self.debug_level))
+    }
+
+    // Run the program to completion, or until it faults
+    pub fn run(&self, program: Program) -> Result<SimulationStats, SimulationError> {
+        self.build(program)?.run()
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::memory::Memory;
+
+    // Thumb code loaded at 0x1000, which is also the entry point
+    pub fn program_from(code: &[u16]) -> Program {
+        let bytes = code.iter().flat_map(|h| h.to_le_bytes().to_vec()).collect();
+        let mut memory = Memory::default();
+        memory.mmap(0x1000, bytes, false).unwrap();
+        Program::new(memory, 0x1001)
+    }
+
+    /*
+        movs r0, #0
+        movs r1, #10
+    loop:
+        adds r0, r0, r1
+        subs r1, #1
+        bne loop
+        svc #1
+        nop         (fetch always reads 4 bytes)
+    */
+    pub fn sum_program() -> Program {
+        program_from(&[0x2000, 0x210A, 0x1840, 0x3901, 0xD1FC, 0xDF01, 0xBF00])
+    }
+
+    /*
+        movs r4, #5
+        movs r5, #0
+    loop:
+        movs r0, r4
+        bl square
+        adds r5, r5, r0
+        movs r0, #1
+        bl square
+        adds r5, r5, r0
+        subs r4, #1
+        bne loop
+        movs r0, r5
+        svc #1
+    square:
+        push {lr}
+        muls r0, r0
+        pop {pc}
+        nop
+    */
+    pub fn call_program() -> Program {
+        program_from(&[
+            0x2405, 0x2500, 0x1C20, 0xF000, 0xF809, 0x182D, 0x2001, 0xF000, 0xF805, 0x182D, 0x3C01,
+            0xD1F5, 0x1C28, 0xDF01, 0xB500, 0x4340, 0xBD00, 0xBF00,
+        ])
+    }
+
+    /*
+        movs r0, #0
+        movs r1, #10
+        mov r2, sp
+    loop:
+        subs r2, #4
+        str r1, [r2]
+        ldr r3, [r2]
+        adds r0, r0, r3
+        subs r1, #1
+        bne loop
+        svc #1
+        nop
+    */
+    pub fn memory_program() -> Program {
+        program_from(&[
+            0x2000, 0x210A, 0x466A, 0x3A04, 0x6011, 0x6813, 0x18C0, 0x3901, 0xD1F9, 0xDF01, 0xBF00,
+        ])
+    }
+
+    #[test]
+    fn run_each_simulator() {
+        for sim in &["scalar", "pipelined", "outoforder", "m0", "fivestage"] {
+            let stats = SimulationBuilder::new(sim.parse().unwrap())
+                .run(sum_program())
+                .unwrap();
+            assert_eq!(stats.instructions_executed, 32, "{}", sim);
+            assert_eq!(stats.instructions_skipped, 1, "{}", sim);
+            assert_eq!(stats.branches_taken, 9, "{}", sim);
+            assert_eq!(stats.branches_not_taken, 1, "{}", sim);
+        }
+    }
+
+    #[test]
+    fn invalid_configuration() {
+        let error = SimulationBuilder::new(SimulatorType::Scalar)
+            .units(0)
+            .run(sum_program())
+            .unwrap_err();
+        assert_eq!(
+            error.fault,
+            Fault::InvalidConfiguration("units must be at least 1, not 0".to_string())
+        );
+        let error = SimulationBuilder::new(SimulatorType::OutOfOrder)
+            .fetch_width(3)
+            .run(sum_program())
+            .unwrap_err();
+        assert_eq!(
+            error.fault,
+            Fault::InvalidConfiguration("fetch width must be a power of two, not 3".to_string())
+        );
+        let error = SimulationBuilder::new(SimulatorType::Scalar)
+            .stack_top(0x1100)
+            .stack_size(0x200)
+            .run(sum_program())
+            .unwrap_err();
+        assert_eq!(
+            error.fault,
+            Fault::InvalidConfiguration(
+                "The stack overlaps the program's memory at 0x1000".to_string()
+            )
+        );
+        assert_eq!(error.pc, 0x1000);
+    }
+}
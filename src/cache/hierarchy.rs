@@ -0,0 +1,168 @@
+use crate::cache::main_memory::MainMemory;
+use crate::cache::{Cache, CacheAccess, CacheStats};
+use serde::Serialize;
+
+#[derive(Default, Debug, Clone, Copy, Serialize)]
+pub struct HierarchyStats {
+    pub instruction_cache: CacheStats,
+    pub data_cache: CacheStats,
+    pub l2_cache: CacheStats,
+    pub memory_accesses: u64,
+    pub row_buffer_hits: u64,
+}
+
+impl HierarchyStats {
+    pub fn add(&mut self, other: &HierarchyStats) {
+        self.instruction_cache.add(&other.instruction_cache);
+        self.data_cache.add(&other.data_cache);
+        self.l2_cache.add(&other.l2_cache);
+        self.memory_accesses = self.memory_accesses + other.memory_accesses;
+        self.row_buffer_hits = self.row_buffer_hits + other.row_buffer_hits;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AccessKind {
+    Fetch,
+    Load,
+    Store,
+}
+
+/*
+Split L1 instruction / data caches, each optional, in front of an optional unified L2 and then
+main memory. Only timing is modelled, the data always comes from Memory.
+*/
+pub struct MemoryHierarchy {
+    pub instruction_cache: Option<Cache>,
+    pub data_cache: Option<Cache>,
+    pub l2_cache: Option<Cache>,
+    pub main_memory: MainMemory,
+}
+
+impl MemoryHierarchy {
+    fn l1(&self, kind: AccessKind) -> Option<&Cache> {
+        match kind {
+            AccessKind::Fetch => self.instruction_cache.as_ref(),
+            _ => self.data_cache.as_ref(),
+        }
+    }
+
+    // Cycles a read would take, without changing the state of any level
+    pub fn latency(&self, kind: AccessKind, address: u32, length: u32) -> u32 {
+        match self.l1(kind) {
+            Some(l1) => {
+                let line_size = l1.config().line_size;
+                l1.config().hit_latency
+                    + l1.missing_lines(address, length)
+                        .into_iter()
+                        .map(|line| self.next_level_latency(line, line_size))
+                        .sum::<u32>()
+            }
+            None => self.next_level_latency(address, length),
+        }
+    }
+
+    fn next_level_latency(&self, address: u32, length: u32) -> u32 {
+        match &self.l2_cache {
+            Some(l2) => {
+                l2.config().hit_latency
+                    + l2.missing_lines(address, length)
+                        .into_iter()
+                        .map(|line| self.main_memory.latency(line))
+                        .sum::<u32>()
+            }
+            None => self.main_memory.latency(address),
+        }
+    }
+
+    pub fn access(
+        &mut self,
+        kind: AccessKind,
+        address: u32,
+        length: u32,
+        stats: &mut HierarchyStats,
+    ) {
+        let write = kind == AccessKind::Store;
+        let (l1, l1_stats) = match kind {
+            AccessKind::Fetch => (&mut self.instruction_cache, &mut stats.instruction_cache),
+            _ => (&mut self.data_cache, &mut stats.data_cache),
+        };
+        match l1 {
+            Some(l1) => {
+                let line_size = l1.config().line_size;
+                let access = l1.access(address, length, write);
+                l1_stats.add(&access.stats);
+                self.next_level_access(access, line_size, stats);
+            }
+            None => {
+                let access = CacheAccess {
+                    fills: if write { vec![] } else { vec![address] },
+                    writes: if write { vec![address] } else { vec![] },
+                    ..Default::default()
+                };
+                self.next_level_access(access, length, stats);
+            }
+        }
+    }
+
+    fn next_level_access(&mut self, access: CacheAccess, length: u32, stats: &mut HierarchyStats) {
+        let lines = access
+            .fills
+            .into_iter()
+            .map(|a| (a, false))
+            .chain(access.writes.into_iter().map(|a| (a, true)));
+        for (address, write) in lines {
+            let memory_addresses = match &mut self.l2_cache {
+                Some(l2) => {
+                    let l2_access = l2.access(address, length, write);
+                    stats.l2_cache.add(&l2_access.stats);
+                    l2_access
+                        .fills
+                        .into_iter()
+                        .chain(l2_access.writes)
+                        .collect()
+                }
+                None => vec![address],
+            };
+            for a in memory_addresses {
+                stats.memory_accesses = stats.memory_accesses + 1;
+                if self.main_memory.access(a) {
+                    stats.row_buffer_hits = stats.row_buffer_hits + 1;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builder::tests::memory_program;
+    use crate::cache::main_memory::MemoryTiming;
+    use crate::cache::CacheConfig;
+    use crate::{SimulationBuilder, SimulatorType};
+
+    #[test]
+    fn memory_hierarchy() {
+        let run = |builder: SimulationBuilder| {
+            let mut program = memory_program();
+            program.code_segments = vec![(0x1000, 0x1016)];
+            builder.run(program).unwrap()
+        };
+        let pipelined = || SimulationBuilder::new(SimulatorType::Pipelined);
+        let sram = run(pipelined());
+        // Only code is fetched from flash, the stack is still single cycle
+        let flash = run(pipelined().flash_timing(Some(MemoryTiming::Fixed(3))));
+        assert!(flash.total_cycles > sram.total_cycles);
+
+        // The loop spans both lines, so they evict each other from a single line cache
+        let l1: CacheConfig = "16,1,16".parse().unwrap();
+        let slow_flash = || pipelined().flash_timing(Some(MemoryTiming::Fixed(10)));
+        let l1_only = run(slow_flash().instruction_cache(Some(l1)));
+        let with_l2 = run(slow_flash()
+            .instruction_cache(Some(l1))
+            .l2_cache(Some("1024,4,16,hit=2".parse().unwrap())));
+        // Without a data cache the stack is also read through the L2
+        assert_eq!(with_l2.caches.l2_cache.misses, 2 + 3);
+        assert!(with_l2.total_cycles < l1_only.total_cycles);
+    }
+}
@@ -0,0 +1,175 @@
+use crate::parse_number;
+use serde::Serialize;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum MemoryTiming {
+    // Every access takes the same number of cycles, e.g. flash wait states or SRAM
+    Fixed(u32),
+    // DRAM, an access to the row already open in its bank is faster than opening a new one
+    RowBuffer {
+        banks: u32,
+        row_size: u32, // Bytes
+        row_hit: u32,  // Cycles
+        row_miss: u32, // Cycles
+    },
+}
+
+// Parsed from "cycles" or "banks,row_size,row_hit,row_miss"
+impl FromStr for MemoryTiming {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let numbers = s
+            .split(',')
+            .map(|p| parse_number(p.trim()))
+            .collect::<Result<Vec<u32>, String>>()?;
+        match numbers.as_slice() {
+            [cycles] => Ok(Self::Fixed(*cycles)),
+            [banks, row_size, row_hit, row_miss] if *banks > 0 && *row_size > 0 => {
+                Ok(Self::RowBuffer {
+                    banks: *banks,
+                    row_size: *row_size,
+                    row_hit: *row_hit,
+                    row_miss: *row_miss,
+                })
+            }
+            _ => Err("Memory timing must be cycles or banks,row_size,row_hit,row_miss".to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct MemoryRegion {
+    pub start: u32,
+    pub end: u32, // Exclusive
+    pub timing: MemoryTiming,
+}
+
+// Parsed from "start-end=timing", for example "0x20000000-0x20008000=2"
+impl FromStr for MemoryRegion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || "Memory region must be given as start-end=timing".to_string();
+        let (range, timing) = split_once(s, '=').ok_or_else(err)?;
+        let (start, end) = split_once(range, '-').ok_or_else(err)?;
+        Ok(Self {
+            start: parse_number(start.trim())?,
+            end: parse_number(end.trim())?,
+            timing: timing.parse()?,
+        })
+    }
+}
+
+fn split_once(s: &str, c: char) -> Option<(&str, &str)> {
+    let i = s.find(c)?;
+    Some((&s[..i], &s[i + 1..]))
+}
+
+struct Region {
+    start: u32,
+    end: u32,
+    timing: MemoryTiming,
+    open_rows: Vec<Option<u32>>, // For each bank
+}
+
+impl Region {
+    fn bank_and_row(&self, address: u32) -> Option<(usize, u32)> {
+        match self.timing {
+            MemoryTiming::Fixed(_) => None,
+            MemoryTiming::RowBuffer {
+                banks, row_size, ..
+            } => {
+                let row = (address - self.start) / row_size;
+                Some(((row % banks) as usize, row / banks))
+            }
+        }
+    }
+}
+
+// The memory behind the caches, with a different latency for each address range
+pub struct MainMemory {
+    regions: Vec<Region>, // Searched in order, the last region covers every address
+}
+
+impl MainMemory {
+    pub fn new(regions: &[MemoryRegion], default: MemoryTiming) -> Self {
+        let all = MemoryRegion {
+            start: 0,
+            end: u32::MAX,
+            timing: default,
+        };
+        let regions = regions
+            .iter()
+            .chain(std::iter::once(&all))
+            .map(|r| Region {
+                start: r.start,
+                end: r.end,
+                timing: r.timing,
+                open_rows: match r.timing {
+                    MemoryTiming::Fixed(_) => vec![],
+                    MemoryTiming::RowBuffer { banks, .. } => vec![None; banks as usize],
+                },
+            })
+            .collect();
+        Self { regions }
+    }
+
+    fn region(&self, address: u32) -> usize {
+        self.regions
+            .iter()
+            .position(|r| address >= r.start && address < r.end)
+            .unwrap_or(self.regions.len() - 1)
+    }
+
+    // Cycles an access would take, without opening any rows
+    pub fn latency(&self, address: u32) -> u32 {
+        let region = &self.regions[self.region(address)];
+        match region.timing {
+            MemoryTiming::Fixed(cycles) => cycles,
+            MemoryTiming::RowBuffer {
+                row_hit, row_miss, ..
+            } => {
+                let (bank, row) = region.bank_and_row(address).unwrap();
+                if region.open_rows[bank] == Some(row) {
+                    row_hit
+                } else {
+                    row_miss
+                }
+            }
+        }
+    }
+
+    // Returns whether the access hit an open row
+    pub fn access(&mut self, address: u32) -> bool {
+        let i = self.region(address);
+        let region = &mut self.regions[i];
+        match region.bank_and_row(address) {
+            Some((bank, row)) => region.open_rows[bank].replace(row) == Some(row),
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn regions_and_row_buffers() {
+        let flash: MemoryRegion = "0x1000-0x2000=3".parse().unwrap();
+        let dram: MemoryRegion = "0x10000-0x20000=2,256,2,10".parse().unwrap();
+        let mut memory = MainMemory::new(&[flash, dram], MemoryTiming::Fixed(1));
+        assert_eq!(memory.latency(0x1800), 3);
+        assert_eq!(memory.latency(0x80000), 1);
+
+        assert_eq!(memory.latency(0x10000), 10);
+        assert!(!memory.access(0x10000));
+        assert_eq!(memory.latency(0x100FF), 2);
+        // The next row is in the other bank, so both stay open
+        memory.access(0x10100);
+        assert!(memory.access(0x10004));
+        assert_eq!(memory.latency(0x10200), 10);
+    }
+}
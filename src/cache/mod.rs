@@ -0,0 +1,338 @@
+pub mod hierarchy;
+pub mod main_memory;
+
+use serde::Serialize;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum ReplacementPolicy {
+    Lru,
+    Fifo,
+    Random,
+}
+
+impl FromStr for ReplacementPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "lru" => Ok(Self::Lru),
+            "fifo" => Ok(Self::Fifo),
+            "random" => Ok(Self::Random),
+            _ => Err("Couldn't match ReplacementPolicy".to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct CacheConfig {
+    pub size: u32, // Bytes
+    pub associativity: u32,
+    pub line_size: u32, // Bytes
+    pub replacement: ReplacementPolicy,
+    pub write_back: bool, // Otherwise every write is passed straight through to the next level
+    pub write_allocate: bool, // Whether a write miss fetches the line into the cache
+    pub hit_latency: u32, // Cycles, a miss must also wait for the next level
+}
+
+impl CacheConfig {
+    pub fn new(size: u32, associativity: u32, line_size: u32) -> Self {
+        Self {
+            size,
+            associativity,
+            line_size,
+            replacement: ReplacementPolicy::Lru,
+            write_back: true,
+            write_allocate: true,
+            hit_latency: 1,
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.line_size.is_power_of_two() {
+            return Err("Line size must be a power of 2".to_string());
+        }
+        if self.associativity == 0 {
+            return Err("Associativity must be at least 1".to_string());
+        }
+        let lines = self.size / self.line_size;
+        if lines == 0 || lines % self.associativity != 0 {
+            return Err("Cache size must be a multiple of associativity * line size".to_string());
+        }
+        Ok(())
+    }
+}
+
+/*
+Parsed from "size,associativity,line_size" followed by any of the options:
+lru / fifo / random, wb / wt (write-back / write-through), wa / nwa (write-allocate or not),
+hit=cycles. For example "4096,2,32,fifo,wt,hit=2"
+*/
+impl FromStr for CacheConfig {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split(',').map(|p| p.trim()).collect();
+        if parts.len() < 3 {
+            return Err("Cache must be given as size,associativity,line_size".to_string());
+        }
+        let number = |p: &str| {
+            p.parse::<u32>()
+                .map_err(|_| format!("Invalid cache parameter {}", p))
+        };
+        let mut config = Self::new(number(parts[0])?, number(parts[1])?, number(parts[2])?);
+        for option in &parts[3..] {
+            match *option {
+                "wb" => config.write_back = true,
+                "wt" => config.write_back = false,
+                "wa" => config.write_allocate = true,
+                "nwa" => config.write_allocate = false,
+                _ if option.starts_with("hit=") => config.hit_latency = number(&option[4..])?,
+                _ => config.replacement = option.parse()?,
+            }
+        }
+        Ok(config)
+    }
+}
+
+#[derive(Default, Debug, Clone, Copy, Serialize)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub writebacks: u64, // Writes passed on to the next level
+}
+
+impl CacheStats {
+    pub fn add(&mut self, other: &CacheStats) {
+        self.hits = self.hits + other.hits;
+        self.misses = self.misses + other.misses;
+        self.writebacks = self.writebacks + other.writebacks;
+    }
+
+    pub fn miss_rate(&self) -> f64 {
+        self.misses as f64 / (self.hits + self.misses).max(1) as f64
+    }
+}
+
+#[derive(Default, Clone, Copy)]
+struct CacheLine {
+    valid: bool,
+    dirty: bool,
+    tag: u32,
+    last_used: u64,
+    filled: u64,
+}
+
+// What an access needs from the next level down
+#[derive(Default, Debug)]
+pub struct CacheAccess {
+    pub stats: CacheStats,
+    pub fills: Vec<u32>,  // Addresses of lines read from the next level
+    pub writes: Vec<u32>, // Addresses of lines written to the next level
+}
+
+// Only the tags are modelled, the data itself is always read from / written to Memory
+pub struct Cache {
+    config: CacheConfig,
+    sets: Vec<Vec<CacheLine>>,
+    time: u64,
+    random_state: u32, // So that random replacement is repeatable
+}
+
+impl Cache {
+    pub fn new(config: CacheConfig) -> Self {
+        if let Err(e) = config.validate() {
+            panic!("{}", e);
+        }
+        let sets = config.size / config.line_size / config.associativity;
+        Self {
+            config,
+            sets: vec![vec![CacheLine::default(); config.associativity as usize]; sets as usize],
+            time: 0,
+            random_state: 0x12345678,
+        }
+    }
+
+    pub fn config(&self) -> &CacheConfig {
+        &self.config
+    }
+
+    // The line numbers covered by an access
+    fn lines(&self, address: u32, length: u32) -> std::ops::RangeInclusive<u32> {
+        let last = address + length.max(1) - 1;
+        (address / self.config.line_size)..=(last / self.config.line_size)
+    }
+
+    fn set_and_tag(&self, line: u32) -> (usize, u32) {
+        let sets = self.sets.len() as u32;
+        ((line % sets) as usize, line / sets)
+    }
+
+    fn find(&self, line: u32) -> Option<usize> {
+        let (set, tag) = self.set_and_tag(line);
+        self.sets[set].iter().position(|l| l.valid && l.tag == tag)
+    }
+
+    // Addresses of the lines a read would miss, without changing the state of the cache
+    pub fn missing_lines(&self, address: u32, length: u32) -> Vec<u32> {
+        self.lines(address, length)
+            .filter(|l| self.find(*l).is_none())
+            .map(|l| l * self.config.line_size)
+            .collect()
+    }
+
+    pub fn access(&mut self, address: u32, length: u32, write: bool) -> CacheAccess {
+        let mut access = CacheAccess::default();
+        let sets = self.sets.len() as u32;
+        for line in self.lines(address, length) {
+            self.time = self.time + 1;
+            let line_address = line * self.config.line_size;
+            let (set, tag) = self.set_and_tag(line);
+            let way = match self.find(line) {
+                Some(way) => {
+                    access.stats.hits = access.stats.hits + 1;
+                    way
+                }
+                None => {
+                    access.stats.misses = access.stats.misses + 1;
+                    if write && !self.config.write_allocate {
+                        access.stats.writebacks = access.stats.writebacks + 1;
+                        access.writes.push(line_address);
+                        continue;
+                    }
+                    let way = self.victim(set);
+                    let victim = &mut self.sets[set][way];
+                    if victim.valid && victim.dirty {
+                        access.stats.writebacks = access.stats.writebacks + 1;
+                        let victim_line = victim.tag * sets + set as u32;
+                        access.writes.push(victim_line * self.config.line_size);
+                    }
+                    access.fills.push(line_address);
+                    *victim = CacheLine {
+                        valid: true,
+                        dirty: false,
+                        tag,
+                        last_used: self.time,
+                        filled: self.time,
+                    };
+                    way
+                }
+            };
+            let l = &mut self.sets[set][way];
+            l.last_used = self.time;
+            if write {
+                if self.config.write_back {
+                    l.dirty = true;
+                } else {
+                    access.stats.writebacks = access.stats.writebacks + 1;
+                    access.writes.push(line_address);
+                }
+            }
+        }
+        access
+    }
+
+    fn victim(&mut self, set: usize) -> usize {
+        let lines = &self.sets[set];
+        if let Some(way) = lines.iter().position(|l| !l.valid) {
+            return way;
+        }
+        let oldest =
+            |key: fn(&CacheLine) -> u64| (0..lines.len()).min_by_key(|w| key(&lines[*w])).unwrap();
+        match self.config.replacement {
+            ReplacementPolicy::Lru => oldest(|l| l.last_used),
+            ReplacementPolicy::Fifo => oldest(|l| l.filled),
+            ReplacementPolicy::Random => {
+                // xorshift32
+                let mut x = self.random_state;
+                x ^= x << 13;
+                x ^= x >> 17;
+                x ^= x << 5;
+                self.random_state = x;
+                (x % self.config.associativity) as usize
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::tests::memory_program;
+    use crate::cache::main_memory::MemoryTiming;
+    use crate::registers::ids::R0;
+    use crate::{SimulationBuilder, SimulatorType};
+
+    #[test]
+    fn replacement_policies() {
+        // 2 sets of 2 ways, lines 0, 2 and 4 all map to set 0
+        let config: CacheConfig = "64,2,16".parse().unwrap();
+        let mut lru = Cache::new(config);
+        let mut fifo = Cache::new(CacheConfig {
+            replacement: ReplacementPolicy::Fifo,
+            ..config
+        });
+        for cache in [&mut lru, &mut fifo].iter_mut() {
+            assert_eq!(cache.missing_lines(14, 4), vec![0, 16]);
+            assert_eq!(cache.access(0, 4, false).fills, vec![0]);
+            cache.access(32, 4, false);
+            assert!(cache.missing_lines(0, 4).is_empty());
+            cache.access(0, 4, false);
+            cache.access(64, 4, false);
+        }
+        // LRU evicted line 2, FIFO evicted line 0 as it was filled first
+        assert!(lru.missing_lines(0, 4).is_empty());
+        assert_eq!(lru.missing_lines(32, 4), vec![32]);
+        assert_eq!(fifo.missing_lines(0, 4), vec![0]);
+        assert!(fifo.missing_lines(32, 4).is_empty());
+    }
+
+    #[test]
+    fn write_policies() {
+        let mut write_back = Cache::new("32,1,16".parse().unwrap());
+        assert!(write_back.access(4, 4, true).writes.is_empty());
+        // Evicting the dirty line writes it back
+        let evicted = write_back.access(32, 4, false);
+        assert_eq!(evicted.writes, vec![0]);
+        assert_eq!(evicted.fills, vec![32]);
+
+        let mut write_through = Cache::new("32,1,16,wt,nwa".parse().unwrap());
+        let around = write_through.access(0, 4, true);
+        assert_eq!((around.stats.writebacks, around.fills.len()), (1, 0));
+        assert_eq!(write_through.missing_lines(0, 4), vec![0]);
+        write_through.access(0, 4, false);
+        assert_eq!(write_through.access(0, 4, true).writes, vec![0]);
+        assert!(write_through.access(32, 4, false).writes.is_empty());
+    }
+
+    #[test]
+    fn caches() {
+        let cache: CacheConfig = "256,2,16".parse().unwrap();
+        for sim in &[
+            SimulatorType::Scalar,
+            SimulatorType::Pipelined,
+            SimulatorType::OutOfOrder,
+        ] {
+            let perfect = SimulationBuilder::new(*sim).run(memory_program()).unwrap();
+            let mut simulation = SimulationBuilder::new(*sim)
+                .instruction_cache(Some(cache))
+                .data_cache(Some(cache))
+                .memory_timing(MemoryTiming::Fixed(20))
+                .build(memory_program())
+                .unwrap();
+            simulation.run_until(&[]);
+            assert_eq!(simulation.state.registers.read_by_id(R0), 55, "{:?}", sim);
+            let stats = simulation.stats.caches;
+            // The code spans two lines
+            assert_eq!(stats.instruction_cache.misses, 2, "{:?}", sim);
+            // The stack spans three lines, which miss on the first store, after which loads hit
+            assert_eq!(stats.data_cache.misses, 3, "{:?}", sim);
+            assert_eq!(stats.data_cache.hits, 17, "{:?}", sim);
+            assert!(
+                simulation.stats.total_cycles > perfect.total_cycles,
+                "{:?}",
+                sim
+            );
+        }
+    }
+}
@@ -0,0 +1,298 @@
+use crate::SimulationBuilder;
+use anyhow::Context;
+use serde::Deserialize;
+use std::path::Path;
+use std::str::FromStr;
+
+/*
+A machine description loaded from a TOML file, every key is optional and anything left out
+keeps the builder's value. Values with their own syntax (caches, memory timings, regions)
+are written the same way as on the command line, for example:
+
+    simulator = "outoforder"
+    units = 4
+    functional_units = "alu=2,mul=1,branch=1,lsu=1"
+    multiplier = "pipelined"
+    forwarding = "full"
+    branch_stage = "ex"
+    predictor = "twobit"
+
+    [queues]
+    decoded = 6
+    rob = 32
+    physical_registers = 64
+    btb = 64
+    return_stack = 8
+
+    [widths]
+    fetch = 2
+    decode = 2
+    issue = 2
+    commit = 4
+
+    [latencies]
+    alu = 1
+    multiply = 8
+    branch = 1
+    load_store = 1
+    address = 1
+
+    [memory]
+    stack_top = 0x80000
+    stack_size = 4096
+    vector_table = 0x0
+    timing = "4,1024,2,10"
+    flash = "3"
+    icache = "4096,2,32"
+    dcache = "4096,2,32,wt"
+    l2 = "65536,8,32,hit=4"
+    regions = ["0x20000000-0x20008000=1"]
+*/
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct MachineConfig {
+    pub simulator: Option<String>,
+    pub units: Option<usize>,
+    pub functional_units: Option<String>,
+    pub multiplier: Option<String>,
+    pub forwarding: Option<String>,
+    pub branch_stage: Option<String>,
+    pub predictor: Option<String>,
+    pub queues: Queues,
+    pub widths: Widths,
+    pub latencies: LatencyConfig,
+    pub memory: MemoryMap,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Queues {
+    pub decoded: Option<usize>,
+    pub rob: Option<usize>,
+    pub physical_registers: Option<usize>,
+    pub btb: Option<usize>,
+    pub return_stack: Option<usize>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Widths {
+    pub fetch: Option<usize>,
+    pub decode: Option<usize>,
+    pub issue: Option<usize>,
+    pub commit: Option<usize>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct LatencyConfig {
+    pub alu: Option<u32>,
+    pub multiply: Option<u32>,
+    pub branch: Option<u32>,
+    pub load_store: Option<u32>,
+    pub address: Option<u32>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct MemoryMap {
+    pub stack_top: Option<u32>,
+    pub stack_size: Option<u32>,
+    pub vector_table: Option<u32>,
+    pub timing: Option<String>,
+    pub flash: Option<String>,
+    pub icache: Option<String>,
+    pub dcache: Option<String>,
+    pub l2: Option<String>,
+    pub regions: Vec<String>,
+}
+
+impl FromStr for MachineConfig {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        toml::from_str(s).map_err(|e| e.to_string())
+    }
+}
+
+// Parse a value that uses the command line syntax, naming the key if it is invalid
+fn parse<T: FromStr<Err = String>>(key: &str, value: &Option<String>) -> Result<Option<T>, String> {
+    value
+        .as_ref()
+        .map(|v| v.parse().map_err(|e| format!("{}: {}", key, e)))
+        .transpose()
+}
+
+impl MachineConfig {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let contents = std::fs::read_to_string(path.as_ref())
+            .with_context(|| format!("Reading config {}", path.as_ref().display()))?;
+        contents
+            .parse()
+            .map_err(|e| anyhow::anyhow!("Invalid config {}: {}", path.as_ref().display(), e))
+    }
+
+    // Override the builder with every key that was set, see SimulationBuilder::validate
+    pub fn apply(&self, mut builder: SimulationBuilder) -> Result<SimulationBuilder, String> {
+        if let Some(simulator) = parse("simulator", &self.simulator)? {
+            builder = builder.simulator(simulator);
+        }
+        if let Some(units) = self.units {
+            builder = builder.units(units);
+        }
+        if self.functional_units.is_some() {
+            builder = builder.functional_units(parse("functional_units", &self.functional_units)?);
+        }
+        if let Some(multiplier) = parse("multiplier", &self.multiplier)? {
+            builder = builder.multiplier(multiplier);
+        }
+        if let Some(forwarding) = parse("forwarding", &self.forwarding)? {
+            builder = builder.forwarding(forwarding);
+        }
+        if let Some(branch_stage) = parse("branch_stage", &self.branch_stage)? {
+            builder = builder.branch_stage(branch_stage);
+        }
+        if let Some(predictor) = parse("predictor", &self.predictor)? {
+            builder = builder.predictor(predictor);
+        }
+
+        let queues = &self.queues;
+        if let Some(decoded) = queues.decoded {
+            builder = builder.decoded_queue_capacity(decoded);
+        }
+        if let Some(rob) = queues.rob {
+            builder = builder.rob_entries(rob);
+        }
+        if let Some(physical_registers) = queues.physical_registers {
+            builder = builder.physical_registers(physical_registers);
+        }
+        if let Some(btb) = queues.btb {
+            builder = builder.btb_entries(btb);
+        }
+        if let Some(return_stack) = queues.return_stack {
+            builder = builder.return_stack_depth(return_stack);
+        }
+
+        if let Some(fetch) = self.widths.fetch {
+            builder = builder.fetch_width(fetch);
+        }
+        if let Some(decode) = self.widths.decode {
+            builder = builder.decode_width(decode);
+        }
+        if let Some(issue) = self.widths.issue {
+            builder = builder.issue_width(issue);
+        }
+        if self.widths.commit.is_some() {
+            builder = builder.commit_width(self.widths.commit);
+        }
+
+        if let Some(alu) = self.latencies.alu {
+            builder = builder.alu_latency(alu);
+        }
+        if let Some(multiply) = self.latencies.multiply {
+            builder = builder.multiply_latency(multiply);
+        }
+        if let Some(branch) = self.latencies.branch {
+            builder = builder.branch_latency(branch);
+        }
+        if let Some(load_store) = self.latencies.load_store {
+            builder = builder.load_store_latency(load_store);
+        }
+        if let Some(address) = self.latencies.address {
+            builder = builder.address_latency(address);
+        }
+
+        let memory = &self.memory;
+        if let Some(stack_top) = memory.stack_top {
+            builder = builder.stack_top(stack_top);
+        }
+        if let Some(stack_size) = memory.stack_size {
+            builder = builder.stack_size(stack_size);
+        }
+        if memory.vector_table.is_some() {
+            builder = builder.vector_table(memory.vector_table);
+        }
+        if let Some(timing) = parse("memory.timing", &memory.timing)? {
+            builder = builder.memory_timing(timing);
+        }
+        if memory.flash.is_some() {
+            builder = builder.flash_timing(parse("memory.flash", &memory.flash)?);
+        }
+        if memory.icache.is_some() {
+            builder = builder.instruction_cache(parse("memory.icache", &memory.icache)?);
+        }
+        if memory.dcache.is_some() {
+            builder = builder.data_cache(parse("memory.dcache", &memory.dcache)?);
+        }
+        if memory.l2.is_some() {
+            builder = builder.l2_cache(parse("memory.l2", &memory.l2)?);
+        }
+        for region in &memory.regions {
+            let region = region
+                .parse()
+                .map_err(|e| format!("memory.regions: {}", e))?;
+            builder = builder.memory_region(region);
+        }
+
+        Ok(builder)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::tests::{call_program, sum_program};
+    use crate::registers::ids::SP;
+
+    #[test]
+    fn machine_config() {
+        let config: MachineConfig = r#"
+            simulator = "pipelined"
+            [latencies]
+            multiply = 2
+            [memory]
+            stack_top = 0x40000
+            icache = "64,1,16"
+        "#
+        .parse()
+        .unwrap();
+        let builder = config.apply(SimulationBuilder::default()).unwrap();
+        let simulation = builder.build(sum_program()).unwrap();
+        assert_eq!(simulation.state.registers.read_by_id(SP), 0x40000);
+        assert_eq!(simulation.state.config.latencies.multiply, 2);
+        let stats = simulation.run().unwrap();
+        assert_eq!(stats.caches.instruction_cache.misses, 1);
+
+        let unknown = "[queues]\nrobs = 4".parse::<MachineConfig>().unwrap_err();
+        assert!(unknown.contains("robs"), "{}", unknown);
+        let invalid: MachineConfig = "[memory]\ndcache = \"100,4,16\"".parse().unwrap();
+        let invalid = invalid.apply(SimulationBuilder::default()).unwrap();
+        let invalid = invalid.validate().unwrap_err();
+        assert!(invalid.contains("data cache"), "{}", invalid);
+        let small: MachineConfig = "[queues]\nphysical_registers = 4".parse().unwrap();
+        assert!(small
+            .apply(SimulationBuilder::default())
+            .unwrap()
+            .validate()
+            .is_err());
+    }
+
+    #[test]
+    fn instruction_latencies() {
+        let cycles = |latencies: &str| {
+            let config: MachineConfig =
+                format!("simulator = \"pipelined\"\n[latencies]\n{}", latencies)
+                    .parse()
+                    .unwrap();
+            let builder = config.apply(SimulationBuilder::default()).unwrap();
+            builder.run(call_program()).unwrap().total_cycles
+        };
+        let baseline = cycles("");
+        // The program has data processing, a multiply, calls and returns, and a push and pop
+        for key in &["alu", "multiply", "branch", "load_store"] {
+            let slower = cycles(&format!("{} = 12", key));
+            assert!(slower > baseline, "{}", key);
+        }
+        assert!(cycles("multiply = 1") < baseline);
+    }
+}
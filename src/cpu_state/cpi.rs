@@ -0,0 +1,162 @@
+use crate::cpu_state::station::Register;
+use crate::cpu_state::{CpuState, UpdateResult};
+use serde::Serialize;
+
+// What a cycle was spent doing, from the point of view of the oldest instruction
+#[derive(Default, Debug, Clone, Copy, PartialEq)]
+pub enum CpiComponent {
+    #[default]
+    Base, // At least one instruction was committed
+    FetchStarved,    // The front end had nothing for the back end
+    DecodeQueueFull, // Issue was blocked for long enough to back up into fetch
+    NoFreeStation,   // The next instruction is waiting for a station, unit or physical register
+    OperandWait,     // The oldest instruction is waiting on a Register::Pending operand
+    ControlHazard,   // Refilling the pipeline after a mispredicted or taken branch
+    Flush,           // Refilling the pipeline after a load was replayed
+    Exception,       // Refilling the pipeline after entering or returning from an exception
+    MemoryLatency,   // Waiting for instruction or data memory
+    Execution,       // The oldest instruction is still executing
+}
+
+// Number of cycles attributed to each component
+#[derive(Default, Debug, Clone, Copy, Serialize)]
+pub struct CpiStack {
+    pub base: u64,
+    pub fetch_starved: u64,
+    pub decode_queue_full: u64,
+    pub no_free_station: u64,
+    pub operand_wait: u64,
+    pub control_hazard: u64,
+    pub flush: u64,
+    pub exception: u64,
+    pub memory_latency: u64,
+    pub execution: u64,
+}
+
+impl CpiStack {
+    pub fn add(&mut self, component: CpiComponent) {
+        let cycles = match component {
+            CpiComponent::Base => &mut self.base,
+            CpiComponent::FetchStarved => &mut self.fetch_starved,
+            CpiComponent::DecodeQueueFull => &mut self.decode_queue_full,
+            CpiComponent::NoFreeStation => &mut self.no_free_station,
+            CpiComponent::OperandWait => &mut self.operand_wait,
+            CpiComponent::ControlHazard => &mut self.control_hazard,
+            CpiComponent::Flush => &mut self.flush,
+            CpiComponent::Exception => &mut self.exception,
+            CpiComponent::MemoryLatency => &mut self.memory_latency,
+            CpiComponent::Execution => &mut self.execution,
+        };
+        *cycles = *cycles + 1;
+    }
+
+    pub fn components(&self) -> [(&'static str, u64); 10] {
+        [
+            ("base", self.base),
+            ("fetch starved", self.fetch_starved),
+            ("decode queue full", self.decode_queue_full),
+            ("no free station", self.no_free_station),
+            ("operand wait", self.operand_wait),
+            ("control hazard", self.control_hazard),
+            ("flush", self.flush),
+            ("exception", self.exception),
+            ("memory latency", self.memory_latency),
+            ("execution", self.execution),
+        ]
+    }
+
+    pub fn total(&self) -> u64 {
+        self.components().iter().map(|(_, cycles)| cycles).sum()
+    }
+}
+
+impl CpuState {
+    // Called at the end of apply_stages, once the cycle's instructions have been committed and issued
+    pub fn classify_cycle(&self, result: &UpdateResult) -> CpiComponent {
+        if result.instructions_executed + result.instructions_skipped > 0 {
+            return CpiComponent::Base;
+        }
+        if let Some(refilling) = self.refilling {
+            return refilling;
+        }
+        if let Some(head) = self.reorder_buffer.head() {
+            let station = self
+                .reservation_stations
+                .iter()
+                .find(|s| s.instruction.is_some() && s.rob_id == head.id);
+            return match station {
+                Some(station) => {
+                    let instr = station.instruction.as_ref().unwrap();
+                    if station
+                        .source_registers
+                        .values()
+                        .any(|r| matches!(r, Register::Pending(_, _)))
+                    {
+                        CpiComponent::OperandWait
+                    } else if instr.imp.reads_memory() || instr.imp.writes_memory() {
+                        CpiComponent::MemoryLatency
+                    } else {
+                        CpiComponent::Execution
+                    }
+                }
+                // The result is still in a multi-cycle functional unit
+                None => CpiComponent::Execution,
+            };
+        }
+        if self.fetch_stall > 0 {
+            CpiComponent::MemoryLatency
+        } else if self.decoded_instructions.len() >= self.config.decoded_queue_capacity {
+            CpiComponent::DecodeQueueFull
+        } else if !self.decoded_instructions.is_empty() {
+            CpiComponent::NoFreeStation
+        } else {
+            CpiComponent::FetchStarved
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builder::tests::{memory_program, sum_program};
+    use crate::cache::main_memory::MemoryTiming;
+    use crate::{SimulationBuilder, SimulatorType};
+
+    #[test]
+    fn cpi_stack() {
+        for sim in &["scalar", "pipelined", "outoforder", "m0", "fivestage"] {
+            let stats = SimulationBuilder::new(sim.parse().unwrap())
+                .data_cache(Some("64,1,16".parse().unwrap()))
+                .memory_timing(MemoryTiming::Fixed(10))
+                .run(memory_program())
+                .unwrap();
+            // Every cycle is accounted for exactly once
+            assert_eq!(stats.cpi_stack.total(), stats.total_cycles, "{}", sim);
+            assert!(stats.cpi_stack.base > 0, "{}", sim);
+        }
+
+        // The not taken predictor is wrong each time the loop branches back
+        let m0 = SimulationBuilder::new(SimulatorType::CortexM0)
+            .run(sum_program())
+            .unwrap();
+        assert_eq!(m0.cpi_stack.base, 33);
+        assert_eq!(m0.cpi_stack.control_hazard, 9 * 2);
+        let five_stage = SimulationBuilder::new(SimulatorType::FiveStage)
+            .run(sum_program())
+            .unwrap();
+        assert_eq!(five_stage.cpi_stack.control_hazard, 9 * 2);
+        assert_eq!(five_stage.cpi_stack.fetch_starved, 4);
+        let pipelined = SimulationBuilder::new(SimulatorType::Pipelined)
+            .run(sum_program())
+            .unwrap();
+        assert!(pipelined.cpi_stack.control_hazard >= 9 * 2);
+        assert_eq!(pipelined.cpi_stack.flush, 0);
+
+        // The loads miss in the data cache
+        let out_of_order = SimulationBuilder::new(SimulatorType::OutOfOrder)
+            .data_cache(Some("64,1,16".parse().unwrap()))
+            .memory_timing(MemoryTiming::Fixed(10))
+            .run(memory_program())
+            .unwrap();
+        assert!(out_of_order.cpi_stack.memory_latency > 0);
+    }
+}
@@ -1,7 +1,10 @@
+use crate::branch_predictor::BranchKind;
+use crate::cpu_state::fetch::FetchedInstruction;
+use crate::cpu_state::pipeline_trace::InstructionId;
 use crate::cpu_state::station::ReservationStation;
 use crate::cpu_state::CpuState;
+use crate::error::Fault;
 use crate::instructions::{decode_instruction, DecodeError, Instruction, PollResult};
-use crate::memory::MemoryAccessError;
 use crate::CAPSTONE;
 use capstone::arch::arm::{ArmCC, ArmOperand};
 use capstone::arch::ArchOperand;
@@ -9,111 +12,140 @@ use capstone::{InsnDetail, RegId};
 use std::collections::HashSet;
 
 pub struct DecodedInstruction {
+    pub id: InstructionId,
     pub imp: Box<dyn Instruction>,
     pub cc: ArmCC,
     pub string: String,
     pub length: u32,
     pub address: u32,
+    pub predicted_next: u32,
+    pub return_predicted: bool,
+    pub branch_kind: Option<BranchKind>, // Classified now, as multi-cycle instructions change state
 }
 
 pub struct DecodeResults {
-    pub instr: DecodedInstruction,
+    pub instrs: Vec<DecodedInstruction>, // In program order
 }
 
 impl CpuState {
     pub fn decode(&self) -> Option<DecodeResults> {
-        // Only if we have space to decode into
-        if !self.decoded_space() {
+        // Only as many as there is space to decode into, once the fetched instructions have arrived
+        let count = self.decode_count();
+        if count == 0 {
             return None;
         }
-        let instr = self
-            .fetched_instruction
-            .as_ref()
-            .map(|fetched_instruction| {
-                match &fetched_instruction.bytes {
-                    Ok(bytes) => {
-                        CAPSTONE.with(|capstone| {
-                            let list = capstone
-                                .disasm_all(bytes, 0x0)
-                                .expect("Invalid instruction");
-                            match list.iter().next() {
-                                // We may not get a valid instruction when speculating
-                                // An InvalidInstruction is used as a placeholder
-                                None => DecodedInstruction {
-                                    imp: Box::new(InvalidInstruction::BadData),
-                                    cc: ArmCC::ARM_CC_INVALID,
-                                    string: "Invalid".to_string(),
-                                    length: bytes.len() as u32,
-                                    address: fetched_instruction.address,
-                                },
-                                Some(instr) => {
-                                    let insn_detail: InsnDetail = capstone
-                                        .insn_detail(&instr)
-                                        .expect("Failed to get insn detail");
-                                    let arch_detail = insn_detail.arch_detail();
-                                    let operands: Vec<ArmOperand> = arch_detail
-                                        .operands()
-                                        .into_iter()
-                                        .map(|x| {
-                                            if let ArchOperand::ArmOperand(inner) = x {
-                                                return inner;
-                                            }
-                                            panic!("Unexpected ArchOperand");
-                                        })
-                                        .collect();
+        let instrs = self
+            .fetched_instructions
+            .iter()
+            .take(count)
+            .map(decode_instruction_bytes)
+            .collect();
+        Some(DecodeResults { instrs })
+    }
+}
 
-                                    let ins_name = capstone.insn_name(instr.id()).unwrap();
-                                    let arm_detail = arch_detail.arm().unwrap();
+fn decode_instruction_bytes(fetched_instruction: &FetchedInstruction) -> DecodedInstruction {
+    match &fetched_instruction.bytes {
+        Ok(bytes) => {
+            CAPSTONE.with(|capstone| {
+                let list = capstone
+                    .disasm_all(bytes, 0x0)
+                    .expect("Invalid instruction");
+                match list.iter().next() {
+                    // We may not get a valid instruction when speculating
+                    // An InvalidInstruction is used as a placeholder
+                    None => DecodedInstruction {
+                        id: fetched_instruction.id,
+                        imp: Box::new(InvalidInstruction(Fault::UndefinedInstruction)),
+                        cc: ArmCC::ARM_CC_INVALID,
+                        string: format!(".inst {}", halfwords(bytes)),
+                        length: bytes.len() as u32,
+                        address: fetched_instruction.address,
+                        predicted_next: fetched_instruction.predicted_next,
+                        return_predicted: fetched_instruction.return_predicted,
+                        branch_kind: None,
+                    },
+                    Some(instr) => {
+                        let insn_detail: InsnDetail = capstone
+                            .insn_detail(&instr)
+                            .expect("Failed to get insn detail");
+                        let arch_detail = insn_detail.arch_detail();
+                        let operands: Vec<ArmOperand> = arch_detail
+                            .operands()
+                            .into_iter()
+                            .map(|x| {
+                                if let ArchOperand::ArmOperand(inner) = x {
+                                    return inner;
+                                }
+                                panic!("Unexpected ArchOperand");
+                            })
+                            .collect();
 
-                                    let decoded = match decode_instruction(
-                                        &ins_name,
-                                        &arm_detail,
-                                        operands,
-                                    ) {
-                                        Ok(decoded) => decoded,
-                                        Err(e) => Box::new(InvalidInstruction::FailedDecode(e)),
-                                    };
+                        let ins_name = capstone.insn_name(instr.id()).unwrap();
+                        let arm_detail = arch_detail.arm().unwrap();
 
-                                    DecodedInstruction {
-                                        imp: decoded.into(),
-                                        cc: arm_detail.cc(),
-                                        string: format!(
-                                            "{} {}",
-                                            instr.mnemonic().unwrap(),
-                                            instr.op_str().unwrap_or("")
-                                        ),
-                                        length: instr.bytes().len() as u32,
-                                        address: fetched_instruction.address,
-                                    }
-                                }
+                        let decoded = match decode_instruction(&ins_name, arm_detail, operands) {
+                            Ok(decoded) => decoded,
+                            Err(DecodeError::Unimplemented(reason))
+                            | Err(DecodeError::UnsupportedInCortexM0(reason)) => {
+                                Box::new(InvalidInstruction(Fault::UnsupportedInstruction(reason)))
                             }
-                        })
+                            Err(DecodeError::Undefined) => {
+                                Box::new(InvalidInstruction(Fault::UndefinedInstruction))
+                            }
+                        };
+
+                        let branch_kind = BranchKind::classify(decoded.as_ref(), arm_detail.cc());
+                        DecodedInstruction {
+                            id: fetched_instruction.id,
+                            imp: decoded,
+                            cc: arm_detail.cc(),
+                            string: format!(
+                                "{} {}",
+                                instr.mnemonic().unwrap(),
+                                instr.op_str().unwrap_or("")
+                            ),
+                            length: instr.bytes().len() as u32,
+                            address: fetched_instruction.address,
+                            predicted_next: fetched_instruction.predicted_next,
+                            return_predicted: fetched_instruction.return_predicted,
+                            branch_kind,
+                        }
                     }
-                    Err(err) => DecodedInstruction {
-                        imp: Box::new(InvalidInstruction::BadAddress(err.clone())),
-                        cc: ArmCC::ARM_CC_INVALID,
-                        string: "Invalid".to_string(),
-                        length: 0,
-                        address: fetched_instruction.address,
-                    },
                 }
-            });
-        instr.map(|i| DecodeResults { instr: i })
+            })
+        }
+        Err(fault) => DecodedInstruction {
+            id: fetched_instruction.id,
+            imp: Box::new(InvalidInstruction(fault.clone())),
+            cc: ArmCC::ARM_CC_INVALID,
+            string: "(no instruction)".to_string(),
+            length: 0,
+            address: fetched_instruction.address,
+            predicted_next: fetched_instruction.predicted_next,
+            return_predicted: fetched_instruction.return_predicted,
+            branch_kind: None,
+        },
     }
 }
 
+// Raw instruction data, as Thumb halfwords
+fn halfwords(bytes: &[u8]) -> String {
+    bytes
+        .chunks(2)
+        .map(|h| format!("{:#06x}", u16::from_le_bytes([h[0], h[1]])))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
 // When we are speculating we may encounter an invalid instruction
-// Do not panic now, wait to see if it actually gets executed or not
+// Do not fault now, wait to see if it actually gets executed or not
 #[derive(Clone, Debug)]
-enum InvalidInstruction {
-    BadAddress(MemoryAccessError),
-    BadData,
-    FailedDecode(DecodeError),
-}
+struct InvalidInstruction(Fault);
 
 impl Instruction for InvalidInstruction {
     fn poll(&self, _: &ReservationStation) -> PollResult {
-        panic!("{:?}", self)
+        PollResult::Fault(self.0.clone())
     }
 
     fn source_registers(&self) -> HashSet<RegId> {
@@ -123,4 +155,8 @@ impl Instruction for InvalidInstruction {
     fn dest_registers(&self) -> HashSet<RegId> {
         hashset!()
     }
+
+    fn non_speculative(&self) -> bool {
+        true
+    }
 }
@@ -0,0 +1,225 @@
+use crate::cpu_state::{CpuState, UpdateResult};
+use crate::error::Fault;
+use crate::registers::ids::{CPSR, IP, LR, PC, R0, R1, R2, R3, SP};
+use std::convert::TryInto;
+
+pub const HARD_FAULT: u32 = 3;
+pub const SVCALL: u32 = 11;
+
+const EXC_RETURN_HANDLER: u32 = 0xFFFFFFF1;
+const EXC_RETURN_THREAD: u32 = 0xFFFFFFF9;
+const THUMB: u32 = 1 << 24;
+const STACK_ALIGNED: u32 = 1 << 9; // The frame was padded to keep the stack 8 byte aligned
+
+/*
+The Cortex-M0 exception model, for programs that bring their own vector table. Faults enter the
+HardFault handler and SVCs the SVCall handler, instead of stopping the simulation. Only the main
+stack pointer is modelled.
+ */
+#[derive(Debug, Clone)]
+pub struct Exceptions {
+    pub vector_table: u32,
+    pub active: Vec<u32>, // Exception numbers being handled, innermost last, empty in thread mode
+}
+
+impl Exceptions {
+    pub fn new(vector_table: u32) -> Self {
+        Self {
+            vector_table,
+            active: vec![],
+        }
+    }
+
+    // The exception number shown in the IPSR, 0 in thread mode
+    pub fn ipsr(&self) -> u32 {
+        self.active.last().copied().unwrap_or(0)
+    }
+
+    // A branch to one of these in handler mode returns from the exception
+    pub fn is_exc_return(address: u32) -> bool {
+        address & 0xFFFFFFF0 == 0xFFFFFFF0
+    }
+}
+
+impl CpuState {
+    // The handler for an exception, if the program has one
+    pub fn exception_vector(&self, number: u32) -> Option<u32> {
+        let exceptions = self.exceptions.as_ref()?;
+        let address = exceptions.vector_table + 4 * number;
+        let vector = self.memory.read().unwrap().read_u32(address).ok()?;
+        if vector == 0 {
+            return None;
+        }
+        Some(vector)
+    }
+
+    // A fault within the HardFault handler locks the M0 up, which ends the simulation
+    pub fn can_enter_exception(&self, number: u32) -> bool {
+        match &self.exceptions {
+            Some(exceptions) => {
+                !exceptions.active.contains(&HARD_FAULT) && self.exception_vector(number).is_some()
+            }
+            None => false,
+        }
+    }
+
+    // Whether an SVC should enter its handler rather than be handled by the simulator
+    pub fn takes_svc(&self) -> bool {
+        // An SVC within a handler would escalate, so those go to the simulator too
+        let thread_mode = matches!(&self.exceptions, Some(e) if e.active.is_empty());
+        thread_mode && self.can_enter_exception(SVCALL)
+    }
+
+    /*
+    Push R0-R3, R12, LR, the return address and xPSR onto the stack, then branch to the handler
+    with EXC_RETURN in LR. Fails if the frame can't be written.
+     */
+    pub fn enter_exception(
+        &mut self,
+        number: u32,
+        return_address: u32,
+        result: &mut UpdateResult,
+    ) -> Result<(), Fault> {
+        let vector = self.exception_vector(number).unwrap();
+        let exceptions = self.exceptions.as_ref().unwrap();
+        let sp = self.registers.read_by_id(SP);
+        let frame = sp.wrapping_sub(32) & 0xFFFFFFF8;
+        let mut xpsr = (self.registers.read_by_id(CPSR) & 0xF0000000) | THUMB | exceptions.ipsr();
+        if sp & 4 != 0 {
+            xpsr = xpsr | STACK_ALIGNED;
+        }
+        let exc_return = if exceptions.active.is_empty() {
+            EXC_RETURN_THREAD
+        } else {
+            EXC_RETURN_HANDLER
+        };
+        let saved = [R0, R1, R2, R3, IP, LR];
+        let words = saved
+            .iter()
+            .map(|r| self.registers.read_by_id(*r))
+            .chain(vec![return_address & 0xFFFFFFFE, xpsr]);
+        let bytes: Vec<u8> = words.flat_map(|w| w.to_le_bytes().to_vec()).collect();
+        self.memory.write().unwrap().write_bytes(frame, &bytes)?;
+
+        self.registers.write_by_id(SP, frame);
+        self.registers.write_by_id(LR, exc_return);
+        self.exceptions.as_mut().unwrap().active.push(number);
+        result.exceptions_taken = result.exceptions_taken + 1;
+        if let Some(profiler) = &mut self.profiler {
+            profiler.interrupted(vector);
+        }
+        self.redirect(vector, result);
+        Ok(())
+    }
+
+    // Restore the registers stacked on entry, and continue from the return address
+    pub fn return_from_exception(&mut self, result: &mut UpdateResult) -> Result<(), Fault> {
+        let sp = self.registers.read_by_id(SP);
+        let bytes = self.memory.read().unwrap().read_bytes(sp, 32)?;
+        let words: Vec<u32> = bytes
+            .chunks(4)
+            .map(|w| u32::from_le_bytes(w.try_into().unwrap()))
+            .collect();
+        for (r, value) in [R0, R1, R2, R3, IP, LR].iter().zip(&words) {
+            self.registers.write_by_id(*r, *value);
+        }
+        let xpsr = words[7];
+        let flags = self.registers.read_by_id(CPSR) & 0x0FFFFFFF;
+        self.registers
+            .write_by_id(CPSR, flags | (xpsr & 0xF0000000));
+        let padding = if xpsr & STACK_ALIGNED != 0 { 4 } else { 0 };
+        self.registers.write_by_id(SP, sp + 32 + padding);
+        self.exceptions.as_mut().unwrap().active.pop();
+        result.exception_returns = result.exception_returns + 1;
+        self.redirect(words[6] | 1, result);
+        Ok(())
+    }
+
+    // Everything younger was fetched from the wrong place, and renamed from registers that changed
+    fn redirect(&mut self, address: u32, result: &mut UpdateResult) {
+        self.registers.write_by_id(PC, address);
+        self.next_instr_addr = address;
+        self.physical_registers.reset(&self.registers);
+        result.pc_changed = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::tests::program_from;
+    use crate::registers::ids::{R4, R5, R6};
+    use crate::{SimulationBuilder, SimulatorType};
+
+    #[test]
+    fn svc_and_hard_fault_handlers() {
+        let mut vectors = [0u32; 16];
+        vectors[0] = 0x80000;
+        vectors[1] = 0x1041;
+        vectors[HARD_FAULT as usize] = 0x1061;
+        vectors[SVCALL as usize] = 0x1051;
+        #[rustfmt::skip]
+        let code = [
+            // Reset: movs r0, #5; svc #0; adds r0, #1; movs r1, #1; lsls r1, r1, #28; ldr r2, [r1]
+            0x2005, 0xDF00, 0x3001, 0x2101, 0x0709, 0x680A, 0xDF01, 0x2000,
+            // SVCall: adds r4, r0, r0; movs r0, #0; bx lr
+            0x1804, 0x2000, 0x4770, 0x2000, 0x2000, 0x2000, 0x2000, 0x2000,
+            // HardFault: movs r5, #7; mov r6, lr; svc #1
+            0x2507, 0x4676, 0xDF01, 0x2000, 0x2000, 0x2000, 0x2000, 0x2000,
+        ];
+        // The vector table is at the start of the program, as halfwords
+        let mut halfwords: Vec<u16> = vectors
+            .iter()
+            .flat_map(|w| vec![*w as u16, (*w >> 16) as u16])
+            .collect();
+        halfwords.extend(&code);
+
+        for sim in &["scalar", "pipelined", "outoforder", "m0", "fivestage"] {
+            let mut simulation = SimulationBuilder::new(sim.parse().unwrap())
+                .vector_table(Some(0x1000))
+                .build(program_from(&halfwords))
+                .unwrap();
+            assert_eq!(simulation.pc(), 0x1040);
+            simulation.run_until(&[]);
+            assert!(simulation.state.error.is_none(), "{}", sim);
+
+            let registers = &simulation.state.registers;
+            // R0 was restored from the stack when the SVC returned
+            assert_eq!(registers.read_by_id(R0), 6, "{}", sim);
+            assert_eq!(registers.read_by_id(R4), 10, "{}", sim);
+            assert_eq!(registers.read_by_id(R5), 7, "{}", sim);
+            assert_eq!(registers.read_by_id(R6), EXC_RETURN_THREAD, "{}", sim);
+            assert_eq!(registers.read_by_id(SP), 0x80000 - 32, "{}", sim);
+            assert_eq!(
+                simulation.state.exceptions.as_ref().unwrap().active,
+                vec![HARD_FAULT]
+            );
+            let memory = simulation.state.memory.read().unwrap();
+            // The faulting load is the return address
+            assert_eq!(memory.read_u32(0x80000 - 8).unwrap(), 0x104A, "{}", sim);
+            assert_eq!(memory.read_u32(0x80000 - 4).unwrap(), THUMB, "{}", sim);
+
+            // The bx lr to EXC_RETURN is neither a misprediction nor a branch target to remember
+            let stats = &simulation.stats;
+            assert_eq!(stats.branch_mispredictions, 0, "{}", sim);
+            assert!(stats.cpi_stack.exception > 0, "{}", sim);
+            assert_eq!(stats.cpi_stack.total(), stats.total_cycles, "{}", sim);
+            let predictor = &simulation.state.branch_predictor;
+            assert_eq!(predictor.predict(0x1055, 2), 0x1057, "{}", sim);
+        }
+
+        // An initial stack pointer just above the code, so the stack would be mapped over it
+        halfwords[0..2].copy_from_slice(&[0x1100, 0]);
+        let error = SimulationBuilder::new(SimulatorType::Scalar)
+            .vector_table(Some(0x1000))
+            .build(program_from(&halfwords))
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.fault,
+            Fault::InvalidConfiguration(
+                "The stack overlaps the program's memory at 0x1000".to_string()
+            )
+        );
+    }
+}
@@ -1,5 +1,9 @@
+use crate::branch_predictor::BranchKind;
+use crate::cpu_state::exceptions::SVCALL;
+use crate::cpu_state::execution_trace::TraceRecord;
 use crate::cpu_state::station::{Register, ReservationStation};
 use crate::cpu_state::CpuState;
+use crate::error::Fault;
 use crate::instructions::{Instruction, PollResult};
 use crate::registers::ids::{CPSR, LR, PC, R0, R8, SP};
 use crate::registers::ConditionFlag;
@@ -13,7 +17,16 @@ pub struct StationResults {
     pub did_execute_instruction: bool,
     pub did_skip_instruction: bool,
     pub instruction_is_branch: bool,
+    pub branch_kind: Option<BranchKind>,
+    pub instruction_address: u32,
+    pub instruction_length: u32,
+    pub predicted_next: u32,
+    pub return_predicted: bool,
+    pub fault: Option<(Fault, String)>, // With the disassembly, raised when the instruction commits
+    pub exception: Option<(u32, String)>, // Exception number entered when the instruction commits
+    pub debug_output: Option<String>,   // Printed when the instruction commits
     pub next_state: Option<Box<dyn Instruction>>, // None means instruction is complete
+    pub trace: Option<TraceRecord>,     // Only when the execution trace is enabled
 }
 
 impl CpuState {
@@ -22,15 +35,23 @@ impl CpuState {
         debug_level: &DebugLevel,
         station: &ReservationStation,
     ) -> Option<StationResults> {
-        if !station.ready() {
+        if !station.ready() || !self.may_execute(station) {
             return None;
         }
         let instr = station.instruction.as_ref().unwrap();
         let mut changes = StationResults::default();
         let should_execute = station.evaluate_condition_code();
         changes.instruction_is_branch = instr.imp.dest_registers().contains(&PC);
+        changes.branch_kind = instr.branch_kind;
+        changes.instruction_address = instr.address;
+        changes.instruction_length = instr.length;
+        changes.predicted_next = instr.predicted_next;
+        changes.return_predicted = instr.return_predicted;
+        if self.execution_trace.is_some() {
+            changes.trace = Some(TraceRecord::new(station, should_execute, &self.debug_info));
+        }
 
-        let print_debug = || {
+        let debug_output = || {
             if *debug_level >= DebugLevel::Minimal {
                 let mut output = String::new();
                 if should_execute {
@@ -39,7 +60,9 @@ impl CpuState {
                     output.push_str(&format!("{} (omitted)", instr.string));
                 }
                 if *debug_level >= DebugLevel::Full {
-                    let padding: String = vec![' '; 30 as usize - output.len()].iter().collect();
+                    let padding: String = vec![' '; 30usize.saturating_sub(output.len())]
+                        .iter()
+                        .collect();
                     output.push_str(&format!(
                         "{} [{}]",
                         padding,
@@ -53,16 +76,26 @@ impl CpuState {
                         })
                     ));
                 }
-                println!("{}", output);
+                if let Some(location) = self.debug_info.describe(instr.address & 0xFFFFFFFE) {
+                    output = format!("{:<32}{}", location, output);
+                }
+                return Some(output);
             }
+            None
         };
 
-        if should_execute {
+        if should_execute && instr.imp.is_supervisor_call() && self.takes_svc() {
+            // SVCs only execute once they're the oldest instruction, so the mode is up to date
+            changes.exception = Some((SVCALL, instr.string.clone()));
+            changes.register_changes = Some(vec![]);
+            changes.did_execute_instruction = true;
+            changes.debug_output = debug_output();
+        } else if should_execute {
             match instr.imp.poll(&station) {
                 PollResult::Complete(c) => {
                     changes.register_changes = Some(c);
                     changes.did_execute_instruction = true;
-                    print_debug();
+                    changes.debug_output = debug_output();
                 }
                 PollResult::Again(s) => {
                     changes.next_state = Some(s);
@@ -70,17 +103,40 @@ impl CpuState {
                 PollResult::Exception => {
                     changes.should_terminate = true;
                     changes.did_execute_instruction = true;
-                    print_debug();
+                    changes.debug_output = debug_output();
+                }
+                PollResult::Fault(e) => {
+                    changes.fault = Some((e, instr.string.clone()));
+                    changes.did_execute_instruction = true;
                 }
             }
         } else {
+            // Younger instructions may already be waiting on the destination registers,
+            // so the previous values are passed through unchanged
+            let unchanged = instr
+                .imp
+                .dest_registers()
+                .into_iter()
+                .filter(|r| *r != PC)
+                .map(|r| (r, station.read_by_id(r)))
+                .collect();
+            changes.register_changes = Some(unchanged);
             changes.did_skip_instruction = true;
-            print_debug();
+            changes.debug_output = debug_output();
         }
 
         Some(changes)
     }
 
+    fn may_execute(&self, station: &ReservationStation) -> bool {
+        let instr = station.instruction.as_ref().unwrap();
+        let head = self.reorder_buffer.head().unwrap().id;
+        if instr.imp.non_speculative() {
+            return station.rob_id == head;
+        }
+        true
+    }
+
     fn debug_string<F>(&self, f: F) -> String
     where
         F: Fn(RegId) -> u32,
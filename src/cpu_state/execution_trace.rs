@@ -0,0 +1,209 @@
+use crate::cpu_state::execute::StationResults;
+use crate::cpu_state::load_store_queue::MemoryAccess;
+use crate::cpu_state::reorder_buffer::RobId;
+use crate::cpu_state::station::ReservationStation;
+use crate::registers::ids::{CPSR, PC};
+use crate::registers::RegisterFile;
+use crate::symbols::DebugInfo;
+use capstone::arch::arm::ArmCC;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum MemoryRecord {
+    Load { address: u32, length: u32 },
+    Store { address: u32, length: u32 },
+}
+
+// One line of the execution trace, describing an instruction as it was retired
+#[derive(Debug, Clone, Serialize)]
+pub struct TraceRecord {
+    pub cycle: u64,
+    pub address: u32,
+    pub disassembly: String,
+    pub symbol: Option<String>,  // e.g. main+0x4
+    pub line: Option<String>,    // e.g. main.c:12
+    pub condition: Option<bool>, // Whether the condition of a conditional instruction passed
+    pub reads: BTreeMap<String, u32>,
+    pub writes: BTreeMap<String, u32>,
+    pub memory: Vec<MemoryRecord>,
+}
+
+impl TraceRecord {
+    // Taken when the instruction executes, as its operands are only held by the station
+    pub fn new(station: &ReservationStation, passed: bool, debug_info: &DebugInfo) -> Self {
+        let instr = station.instruction.as_ref().unwrap();
+        let mut reads = instr.imp.source_registers();
+        let conditional = !matches!(instr.cc, ArmCC::ARM_CC_AL);
+        if conditional {
+            reads.insert(CPSR);
+        }
+        let address = instr.address & 0xFFFFFFFE;
+        Self {
+            cycle: 0,
+            address,
+            disassembly: instr.string.clone(),
+            symbol: debug_info.symbols.describe(address),
+            line: debug_info
+                .lines
+                .lookup(address)
+                .map(|(file, line)| format!("{}:{}", file, line)),
+            condition: if conditional { Some(passed) } else { None },
+            reads: reads
+                .into_iter()
+                .map(|r| (RegisterFile::reg_name(r), station.read_by_id(r)))
+                .collect(),
+            writes: Default::default(),
+            memory: vec![],
+        }
+    }
+}
+
+/*
+Writes a TraceRecord as a line of JSON for every instruction retired, so that a run can be
+inspected or compared with another without the debug output mixing with the program's own.
+ */
+pub struct ExecutionTrace {
+    out: Box<dyn Write + Send + Sync>,
+    cycle: u64,
+    accesses: HashMap<RobId, Vec<MemoryRecord>>, // Made by instructions which haven't retired yet
+}
+
+impl ExecutionTrace {
+    pub fn new(out: Box<dyn Write + Send + Sync>) -> Self {
+        Self {
+            out,
+            cycle: 1,
+            accesses: Default::default(),
+        }
+    }
+
+    // Called once every cycle has been simulated
+    pub fn next_cycle(&mut self) {
+        self.cycle = self.cycle + 1;
+    }
+
+    pub fn accessed(&mut self, rob_id: RobId, access: &MemoryAccess) {
+        let record = match access {
+            MemoryAccess::Load {
+                address, length, ..
+            } => MemoryRecord::Load {
+                address: *address,
+                length: *length,
+            },
+            MemoryAccess::Store { address, bytes } => MemoryRecord::Store {
+                address: *address,
+                length: bytes.len() as u32,
+            },
+        };
+        self.accesses.entry(rob_id).or_default().push(record);
+    }
+
+    // The accesses of squashed instructions are never retired
+    pub fn squashed(&mut self) {
+        self.accesses.clear();
+    }
+
+    pub fn retired(&mut self, rob_id: RobId, execute: &mut StationResults) {
+        let mut record = match execute.trace.take() {
+            Some(record) => record,
+            None => return,
+        };
+        record.cycle = self.cycle;
+        if execute.did_execute_instruction {
+            record.writes = execute
+                .register_changes
+                .iter()
+                .flatten()
+                .map(|(r, value)| {
+                    let value = if *r == PC { value & 0xFFFFFFFE } else { *value };
+                    (RegisterFile::reg_name(*r), value)
+                })
+                .collect();
+        }
+        record.memory = self.accesses.remove(&rob_id).unwrap_or_default();
+        serde_json::to_writer(&mut self.out, &record).expect("Failed to write execution trace");
+        writeln!(self.out).expect("Failed to write execution trace");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::tests::memory_program;
+    use crate::{SimulationBuilder, SimulatorType};
+    use serde_json::Value;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn trace(simulator: SimulatorType) -> Vec<Value> {
+        let buffer = Buffer::default();
+        let mut simulation = SimulationBuilder::new(simulator)
+            .build(memory_program())
+            .unwrap();
+        simulation.state.execution_trace = Some(ExecutionTrace::new(Box::new(buffer.clone())));
+        let stats = simulation.run().unwrap();
+        let log = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let records: Vec<Value> = log
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect();
+        assert_eq!(records.last().unwrap()["cycle"], stats.total_cycles);
+        records
+    }
+
+    #[test]
+    fn execution_trace() {
+        let records = trace(SimulatorType::OutOfOrder);
+        // 3 + 10 x 6 in the loop + svc
+        assert_eq!(records.len(), 64);
+
+        // str r1, [r2] on the first iteration
+        let store = &records[4];
+        assert_eq!(store["address"], 0x1008);
+        assert_eq!(store["disassembly"], "str r1, [r2]");
+        assert_eq!(store["reads"]["R1"], 10);
+        let sp = store["reads"]["R2"].as_u64().unwrap();
+        assert_eq!(store["memory"][0]["kind"], "store");
+        assert_eq!(store["memory"][0]["address"], sp);
+        assert_eq!(records[5]["memory"][0]["kind"], "load");
+        assert_eq!(records[5]["writes"]["R3"], 10);
+
+        // bne loop is taken 9 times, then falls through
+        let branches: Vec<_> = records
+            .iter()
+            .filter(|r| r["disassembly"].as_str().unwrap().starts_with("bne"))
+            .collect();
+        assert_eq!(branches.len(), 10);
+        assert_eq!(branches[0]["condition"], true);
+        assert_eq!(branches[0]["writes"]["PC"], 0x1006);
+        assert_eq!(branches[9]["condition"], false);
+        assert!(branches[9]["writes"].as_object().unwrap().is_empty());
+        assert_eq!(records[0]["condition"], Value::Null);
+
+        // Only the cycles depend on the simulator
+        let strip = |mut r: Value| {
+            r["cycle"].take();
+            r
+        };
+        let scalar = trace(SimulatorType::Scalar);
+        assert_eq!(
+            records.into_iter().map(strip).collect::<Vec<_>>(),
+            scalar.into_iter().map(strip).collect::<Vec<_>>()
+        );
+    }
+}
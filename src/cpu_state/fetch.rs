@@ -1,20 +1,27 @@
+use crate::cache::hierarchy::AccessKind;
+use crate::cpu_state::pipeline_trace::InstructionId;
 use crate::cpu_state::CpuState;
-use crate::memory::MemoryAccessError;
+use crate::error::Fault;
 
 pub struct FetchedInstruction {
-    pub bytes: Result<Vec<u8>, MemoryAccessError>,
+    pub id: InstructionId,             // Assigned once the fetch has been applied
+    pub bytes: Result<Vec<u8>, Fault>, // The fault is raised if the instruction commits
     pub address: u32,
+    pub predicted_next: u32, // Where the branch predictor decided to fetch from next
+    pub return_predicted: bool, // The return address stack supplied predicted_next
 }
 
 pub struct FetchResults {
     pub next_addr: u32,
-    pub instr: FetchedInstruction,
+    pub instrs: Vec<FetchedInstruction>, // In program order
+    pub latency: u32,                    // Cycles until the instructions can be decoded
 }
 
 impl CpuState {
+    // Read to the end of the aligned fetch block, or until the predicted path leaves it
     pub fn fetch(&self) -> Option<FetchResults> {
-        // Only if we have space to fetch into
-        if self.fetched_instruction.is_some() && !self.decoded_space() {
+        // Only if the instructions already fetched will all be decoded this cycle
+        if self.fetch_stall > 0 || self.fetched_instructions.len() > self.decode_count() {
             return None;
         }
         /*  The Thumb instruction stream is a sequence of halfword-aligned halfwords.
@@ -24,39 +31,124 @@ impl CpuState {
            the halfword is the first halfword of a 32-bit instruction:
            0b11101 0b11110 0b11111 Otherwise, the halfword is a 16-bit instruction.
         */
-        assert_eq!(
-            self.next_instr_addr & 1,
-            1,
-            "LSB of PC must be 1 for thumb mode"
-        );
-        let addr = self.next_instr_addr & 0xFFFFFFFE; // Ignore the last bit for actual address
-        let code = self.memory.read().unwrap().read_bytes(addr, 4);
-        match code {
-            Ok(code) => {
-                let bits_15_11 = code[1] >> 3;
-                let instr_len = match bits_15_11 {
-                    0b11101 | 0b11110 | 0b11111 => 4,
-                    _ => 2,
-                };
-                assert!(instr_len == 2 || instr_len == 4);
-                Some(FetchResults {
-                    next_addr: self.next_instr_addr + instr_len,
-                    instr: FetchedInstruction {
-                        bytes: Ok(code[0..instr_len as usize].to_vec()),
-                        address: self.next_instr_addr,
-                    },
-                })
+        // The M0 only executes Thumb code, branching to an even address is an INVSTATE fault
+        if self.next_instr_addr & 1 == 0 {
+            let fault = Fault::InvalidState(self.next_instr_addr);
+            return Some(Self::fetch_error(self.next_instr_addr, fault));
+        }
+        // The width is a power of two, so blocks are aligned to their size
+        let block_size = 4 * self.config.fetch_width as u64;
+        let block_end = (self.next_instr_addr as u64 / block_size + 1) * block_size;
+        let memory = self.memory.read().unwrap();
+        let mut instrs = vec![];
+        let mut next_addr = self.next_instr_addr;
+        while instrs.len() < self.config.fetch_width {
+            let addr = next_addr & 0xFFFFFFFE; // Ignore the last bit for actual address
+            let first = match memory.read_bytes(addr, 2) {
+                Ok(first) => first,
+                Err(_) => {
+                    if instrs.is_empty() {
+                        return Some(Self::fetch_error(next_addr, Fault::BadFetch(addr)));
+                    }
+                    break;
+                }
+            };
+            let bits_15_11 = first[1] >> 3;
+            let instr_len = match bits_15_11 {
+                0b11101..=0b11111 => 4,
+                _ => 2,
+            };
+            // An instruction split across two blocks finishes the group, unless it is the first
+            if !instrs.is_empty() && (addr + instr_len) as u64 > block_end {
+                break;
             }
-            Err(e) => {
-                // Fetch can fail when reading ahead speculatively into an invalid address
-                Some(FetchResults {
-                    next_addr: self.next_instr_addr,
-                    instr: FetchedInstruction {
-                        bytes: Err(e),
-                        address: self.next_instr_addr,
-                    },
-                })
+            let bytes = match memory.read_bytes(addr, instr_len) {
+                Ok(bytes) => bytes,
+                Err(_) => {
+                    if instrs.is_empty() {
+                        return Some(Self::fetch_error(next_addr, Fault::BadFetch(addr)));
+                    }
+                    break;
+                }
+            };
+            let predicted_next = self.branch_predictor.predict(next_addr, instr_len);
+            instrs.push(FetchedInstruction {
+                id: 0,
+                bytes: Ok(bytes),
+                address: next_addr,
+                predicted_next,
+                return_predicted: self.branch_predictor.predicts_return(next_addr),
+            });
+            let sequential = next_addr.wrapping_add(instr_len);
+            next_addr = predicted_next;
+            // A predicted taken branch redirects fetch, possibly to another block
+            if predicted_next != sequential || (predicted_next & 0xFFFFFFFE) as u64 >= block_end {
+                break;
             }
         }
+        let start = self.next_instr_addr & 0xFFFFFFFE;
+        let last = instrs.last().unwrap();
+        let length =
+            (last.address & 0xFFFFFFFE) + last.bytes.as_ref().unwrap().len() as u32 - start;
+        let latency =
+            self.memory_hierarchy
+                .read()
+                .unwrap()
+                .latency(AccessKind::Fetch, start, length);
+        Some(FetchResults {
+            next_addr,
+            instrs,
+            latency,
+        })
+    }
+
+    // Fetch can fail when reading ahead speculatively into an invalid address
+    fn fetch_error(address: u32, fault: Fault) -> FetchResults {
+        FetchResults {
+            next_addr: address,
+            latency: 1,
+            instrs: vec![FetchedInstruction {
+                id: 0,
+                bytes: Err(fault),
+                address,
+                predicted_next: address,
+                return_predicted: false,
+            }],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::builder::tests::{call_program, program_from};
+    use crate::registers::ids::R5;
+    use crate::{PredictorType, SimulationBuilder, SimulatorType};
+
+    #[test]
+    fn superscalar_front_end() {
+        let builder = |width| {
+            SimulationBuilder::new(SimulatorType::OutOfOrder)
+                .predictor(PredictorType::TwoBit)
+                .fetch_width(width)
+                .decode_width(width)
+                .issue_width(4)
+        };
+        // Calls are 32-bit instructions, which may be split across fetch blocks
+        for width in &[1, 2, 4] {
+            let mut simulation = builder(*width).build(call_program()).unwrap();
+            simulation.run_until(&[]);
+            assert_eq!(simulation.state.registers.read_by_id(R5), 60, "{}", width);
+            assert_eq!(simulation.stats.instructions_executed, 73, "{}", width);
+        }
+
+        // mov r0, r8 ... mov r7, r8 twice, all independent as they don't set the flags
+        let mut code: Vec<u16> = (0..16).map(|i| 0x4640 + i % 8).collect();
+        code.extend(&[0xDF01, 0xBF00]);
+        let narrow = builder(1).run(program_from(&code)).unwrap();
+        let wide = builder(4).run(program_from(&code)).unwrap();
+        // The narrow front end can't keep up with issue
+        assert!(narrow.front_end_stalls > 10);
+        assert!(wide.front_end_stalls < 5);
+        assert!(wide.total_cycles < narrow.total_cycles - 10);
     }
 }
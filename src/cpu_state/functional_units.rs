@@ -0,0 +1,154 @@
+use crate::cpu_state::execute::StationResults;
+use crate::cpu_state::reorder_buffer::RobId;
+use crate::instructions::InstructionClass;
+use serde::Serialize;
+use std::str::FromStr;
+
+// Number of reservation stations bound to each type of functional unit
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct FunctionalUnits {
+    pub alu: usize,
+    pub multiply: usize,
+    pub branch: usize,
+    pub load_store: usize,
+}
+
+impl Default for FunctionalUnits {
+    fn default() -> Self {
+        Self {
+            alu: 1,
+            multiply: 1,
+            branch: 1,
+            load_store: 1,
+        }
+    }
+}
+
+impl FunctionalUnits {
+    pub fn counts(&self) -> [(InstructionClass, usize); 4] {
+        [
+            (InstructionClass::Alu, self.alu),
+            (InstructionClass::Multiply, self.multiply),
+            (InstructionClass::Branch, self.branch),
+            (InstructionClass::LoadStore, self.load_store),
+        ]
+    }
+
+    pub fn total(&self) -> usize {
+        self.counts().iter().map(|(_, n)| n).sum()
+    }
+
+    // The unit each station is bound to
+    pub fn stations(&self) -> Vec<Option<InstructionClass>> {
+        self.counts()
+            .iter()
+            .flat_map(|(class, n)| vec![Some(*class); *n])
+            .collect()
+    }
+}
+
+// Format: alu=2,mul=1,branch=1,lsu=2 (units left out default to 1)
+impl FromStr for FunctionalUnits {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut units = Self::default();
+        for part in s.split(',') {
+            let mut kv = part.splitn(2, '=');
+            let key = kv.next().unwrap().trim();
+            let count = kv
+                .next()
+                .and_then(|v| v.trim().parse::<usize>().ok())
+                .ok_or(format!("Expected a count for {}", key))?;
+            match key.to_lowercase().as_str() {
+                "alu" => units.alu = count,
+                "mul" => units.multiply = count,
+                "branch" => units.branch = count,
+                "lsu" => units.load_store = count,
+                _ => return Err(format!("Unknown functional unit {}", key)),
+            }
+        }
+        Ok(units)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum Multiplier {
+    Pipelined, // Starts a new multiply every cycle
+    Iterative, // Busy until the multiply has finished
+}
+
+impl FromStr for Multiplier {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "pipelined" => Ok(Self::Pipelined),
+            "iterative" => Ok(Self::Iterative),
+            _ => Err("Couldn't match Multiplier".to_string()),
+        }
+    }
+}
+
+// An instruction that has left its station, but whose result is still in the functional unit
+#[derive(Debug)]
+pub struct InFlight {
+    pub rob_id: RobId,
+    pub cycles: u32, // Until the result is written back
+    pub results: StationResults,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::tests::{call_program, program_from};
+    use crate::registers::ids::{R0, R5};
+    use crate::{PredictorType, SimulationBuilder, SimulatorType};
+
+    /*
+        movs r0, #3
+        muls r0, r0
+        muls r0, r0
+        mov r2, r8
+        ...
+        mov r7, r8
+        (the movs are repeated)
+        svc #1
+        nop
+    */
+    #[test]
+    fn functional_units() {
+        let units: FunctionalUnits = "alu=2,lsu=3".parse().unwrap();
+        assert_eq!((units.alu, units.multiply, units.load_store), (2, 1, 3));
+        assert_eq!(units.total(), 7);
+        assert!("fpu=1".parse::<FunctionalUnits>().is_err());
+
+        let typed = SimulationBuilder::new(SimulatorType::OutOfOrder)
+            .predictor(PredictorType::TwoBit)
+            .functional_units(Some(FunctionalUnits::default()));
+        let mut simulation = typed.build(call_program()).unwrap();
+        simulation.run_until(&[]);
+        assert_eq!(simulation.state.registers.read_by_id(R5), 60);
+
+        let mut code: Vec<u16> = vec![0x2003, 0x4340, 0x4340];
+        code.extend((0..16).map(|i| 0x4642 + i % 6));
+        code.extend(&[0xDF01, 0xBF00]);
+        let run = |multiplier| {
+            let mut simulation = typed
+                .clone()
+                .multiplier(multiplier)
+                .build(program_from(&code))
+                .unwrap();
+            simulation.run_until(&[]);
+            assert_eq!(simulation.state.registers.read_by_id(R0), 81);
+            simulation.stats
+        };
+        // The second multiply can't start until the iterative multiplier has finished the first
+        // holding up the moves behind it, a pipelined multiplier accepts it straight away
+        let iterative = run(Multiplier::Iterative);
+        let pipelined = run(Multiplier::Pipelined);
+        assert!(iterative.unit_stalls >= 7);
+        assert!(pipelined.unit_stalls < iterative.unit_stalls);
+        assert!(pipelined.total_cycles < iterative.total_cycles);
+    }
+}
@@ -0,0 +1,190 @@
+use crate::cpu_state::reorder_buffer::RobId;
+use std::collections::VecDeque;
+
+#[derive(Debug, Clone)]
+pub enum MemoryAccess {
+    Load {
+        address: u32,
+        length: u32,
+        forwarded: bool, // At least one byte came from an uncommitted store
+    },
+    Store {
+        address: u32,
+        bytes: Vec<u8>,
+    },
+}
+
+struct LsqEntry {
+    rob_id: RobId,
+    loads: Vec<(u32, u32)>,      // Address and length of each read
+    stores: Vec<(u32, Vec<u8>)>, // Written to memory when the instruction commits
+    violated: bool,              // Read memory before an older store had written to it
+}
+
+// Memory instructions in program order, so that stores only become visible once committed
+#[derive(Default)]
+pub struct LoadStoreQueue {
+    entries: VecDeque<LsqEntry>,
+}
+
+impl LoadStoreQueue {
+    // Must be called in program order
+    pub fn allocate(&mut self, rob_id: RobId) {
+        self.entries.push_back(LsqEntry {
+            rob_id,
+            loads: vec![],
+            stores: vec![],
+            violated: false,
+        });
+    }
+
+    // The youngest value stored to this byte by an instruction older than `rob_id`
+    pub fn forward(&self, rob_id: RobId, address: u32) -> Option<u8> {
+        self.entries
+            .iter()
+            .rev()
+            .filter(|e| e.rob_id < rob_id)
+            .flat_map(|e| e.stores.iter().rev())
+            .find(|(base, bytes)| address >= *base && address < *base + bytes.len() as u32)
+            .map(|(base, bytes)| bytes[(address - base) as usize])
+    }
+
+    pub fn record(&mut self, rob_id: RobId, access: MemoryAccess) {
+        match access {
+            MemoryAccess::Load {
+                address, length, ..
+            } => {
+                self.entry_mut(rob_id).loads.push((address, length));
+            }
+            MemoryAccess::Store { address, bytes } => {
+                /*
+                Any younger load that has already read these bytes used a stale value.
+                This is conservative, the load may have been forwarded the value from a store
+                in between the two, but that is rare enough not to be worth tracking
+                */
+                let end = address + bytes.len() as u32;
+                for e in self.entries.iter_mut().filter(|e| e.rob_id > rob_id) {
+                    if e.loads.iter().any(|(a, l)| *a < end && address < *a + *l) {
+                        e.violated = true;
+                    }
+                }
+                self.entry_mut(rob_id).stores.push((address, bytes));
+            }
+        }
+    }
+
+    pub fn violated(&self, rob_id: RobId) -> bool {
+        self.entries
+            .iter()
+            .any(|e| e.rob_id == rob_id && e.violated)
+    }
+
+    // Address and length of every read made by the instruction
+    pub fn loads(&self, rob_id: RobId) -> Vec<(u32, u32)> {
+        self.entries
+            .iter()
+            .find(|e| e.rob_id == rob_id)
+            .map(|e| e.loads.clone())
+            .unwrap_or_default()
+    }
+
+    // Remove a committed instruction, returning the stores that must now be written to memory
+    pub fn commit(&mut self, rob_id: RobId) -> Vec<(u32, Vec<u8>)> {
+        if self.entries.front().map(|e| e.rob_id) != Some(rob_id) {
+            return vec![]; // Not a memory instruction
+        }
+        self.entries.pop_front().unwrap().stores
+    }
+
+    pub fn squash(&mut self) {
+        self.entries.clear();
+    }
+
+    fn entry_mut(&mut self, rob_id: RobId) -> &mut LsqEntry {
+        self.entries
+            .iter_mut()
+            .find(|e| e.rob_id == rob_id)
+            .expect("Memory instruction has no load/store queue entry")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::tests::program_from;
+    use crate::registers::ids::{R6, R7};
+    use crate::{SimulationBuilder, SimulatorType};
+
+    #[test]
+    fn forwarding_and_violations() {
+        let mut lsq = LoadStoreQueue::default();
+        for rob_id in 0..3 {
+            lsq.allocate(rob_id);
+        }
+        lsq.record(
+            2,
+            MemoryAccess::Load {
+                address: 0x100,
+                length: 4,
+                forwarded: false,
+            },
+        );
+        lsq.record(
+            0,
+            MemoryAccess::Store {
+                address: 0x102,
+                bytes: vec![0xAA, 0xBB],
+            },
+        );
+        assert!(lsq.violated(2));
+        assert_eq!(lsq.forward(1, 0x103), Some(0xBB));
+        assert_eq!(lsq.forward(1, 0x104), None);
+        assert_eq!(lsq.forward(0, 0x103), None);
+
+        assert_eq!(lsq.commit(0), vec![(0x102, vec![0xAA, 0xBB])]);
+        assert_eq!(lsq.forward(1, 0x103), None);
+    }
+
+    /*
+        mov r2, sp
+        subs r2, #8
+        movs r3, #7
+        movs r5, #42
+        movs r0, #0
+        muls r0, r0
+        str r3, [r2]
+        ldr r7, [r2]
+        str r5, [r2, r0]
+        ldr r6, [r2]
+        svc #1
+        nop
+    */
+    #[test]
+    fn memory_disambiguation() {
+        let code = [
+            0x466A, 0x3A08, 0x2307, 0x252A, 0x2000, 0x4340, 0x6013, 0x6817, 0x5015, 0x6816, 0xDF01,
+            0xBF00,
+        ];
+        for sim in &[
+            SimulatorType::Scalar,
+            SimulatorType::Pipelined,
+            SimulatorType::OutOfOrder,
+        ] {
+            let mut simulation = SimulationBuilder::new(*sim)
+                .build(program_from(&code))
+                .unwrap();
+            simulation.run_until(&[]);
+            assert_eq!(simulation.state.registers.read_by_id(R7), 7, "{:?}", sim);
+            assert_eq!(simulation.state.registers.read_by_id(R6), 42, "{:?}", sim);
+            let stats = simulation.stats;
+            if let SimulatorType::OutOfOrder = sim {
+                // The multiply stops the first store committing, so the load is forwarded its value
+                assert!(stats.loads_forwarded > 0);
+                // The last load does not wait for the address of the second store, so is replayed
+                assert_eq!(stats.memory_order_violations, 1);
+            } else {
+                assert_eq!(stats.memory_order_violations, 0, "{:?}", sim);
+            }
+        }
+    }
+}
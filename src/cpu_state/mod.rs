@@ -1,17 +1,39 @@
+pub mod cpi;
 pub mod decode;
+pub mod exceptions;
 pub mod execute;
+pub mod execution_trace;
 pub mod fetch;
+pub mod functional_units;
+pub mod load_store_queue;
+pub mod pipeline_trace;
+pub mod profiler;
+pub mod rename;
+pub mod reorder_buffer;
 pub mod station;
 
+use crate::branch_predictor::{BranchKind, BranchPredictor};
+use crate::cache::hierarchy::{AccessKind, HierarchyStats, MemoryHierarchy};
+use crate::cpu_state::cpi::CpiComponent;
 use crate::cpu_state::decode::{DecodeResults, DecodedInstruction};
+use crate::cpu_state::exceptions::{Exceptions, HARD_FAULT};
 use crate::cpu_state::execute::StationResults;
+use crate::cpu_state::execution_trace::ExecutionTrace;
 use crate::cpu_state::fetch::{FetchResults, FetchedInstruction};
-use crate::cpu_state::station::StationId;
+use crate::cpu_state::functional_units::{InFlight, Multiplier};
+use crate::cpu_state::load_store_queue::{LoadStoreQueue, MemoryAccess};
+use crate::cpu_state::pipeline_trace::{InstructionId, PipelineTrace};
+use crate::cpu_state::profiler::Profiler;
+use crate::cpu_state::rename::PhysicalRegisterFile;
+use crate::cpu_state::reorder_buffer::{ReorderBuffer, RobEntry, RobId};
+use crate::error::{Fault, SimulationError};
+use crate::instructions::{InstructionClass, Latencies};
 use crate::memory::Memory;
 use crate::registers::ids::{CPSR, PC};
 use crate::registers::RegisterFile;
+use crate::symbols::DebugInfo;
 use capstone::arch::arm::ArmCC;
-use capstone::RegId;
+use serde::Serialize;
 use station::{Register, ReservationStation};
 use std::collections::{HashMap, VecDeque};
 use std::sync::{Arc, RwLock};
@@ -20,52 +42,225 @@ pub struct CpuState {
     pub memory: Arc<RwLock<Memory>>,
     pub registers: RegisterFile,
     pub next_instr_addr: u32, // Address of instruction waiting to be fetched
-    pub fetched_instruction: Option<FetchedInstruction>, // Instruction waiting to be decoded
+    pub fetched_instructions: VecDeque<FetchedInstruction>, // Instructions waiting to be decoded
     pub decoded_instructions: VecDeque<DecodedInstruction>, // Instructions waiting to be executed
     pub reservation_stations: Vec<ReservationStation>,
+    pub in_flight: Vec<InFlight>, // Results of multi-cycle instructions waiting to be written back
     pub should_terminate: bool,
-    pub pending_registers: HashMap<RegId, StationId>, // Stations that will produce a register value
+    pub error: Option<SimulationError>, // The fault the program was terminated by
+    pub physical_registers: PhysicalRegisterFile,
+    pub branch_predictor: BranchPredictor,
+    pub reorder_buffer: ReorderBuffer,
+    pub load_store_queue: Arc<RwLock<LoadStoreQueue>>,
+    pub memory_hierarchy: Arc<RwLock<MemoryHierarchy>>,
+    pub fetch_stall: u32, // Cycles until the fetched instruction arrives from memory
+    pub refilling: Option<CpiComponent>, // Nothing has been committed since the pipeline was flushed
+    pub next_instruction_id: InstructionId,
+    pub pipeline_trace: Option<PipelineTrace>,
+    pub execution_trace: Option<ExecutionTrace>,
+    pub profiler: Option<Profiler>,
+    pub exceptions: Option<Exceptions>, // Set when the program brings its own vector table
+    pub debug_info: DebugInfo,          // Symbols and source lines to describe addresses with
+    pub config: CpuConfig,
 }
 
 #[derive(Default)]
 pub struct UpdateResult {
-    pub pc_changed: bool,
+    pub pc_changed: bool, // The branch predictor was wrong, instructions in the pipeline must be flushed
     pub instructions_executed: u8,
     pub instructions_skipped: u8,
     pub branches_taken: u8,
     pub branches_not_taken: u8,
+    pub branch_mispredictions: u8,
+    pub return_stack_hits: u8,
+    pub return_stack_misses: u8,
+    pub instructions_squashed: u32,
+    pub rename_stalls: u8,
+    pub front_end_stalls: u8, // Issue ran out of decoded instructions
+    pub unit_stalls: u8,      // The next instruction's functional units were all busy
+    pub loads_forwarded: u8,
+    pub memory_order_violations: u8,
+    pub exceptions_taken: u8, // Exception entries and returns flush the pipeline too
+    pub exception_returns: u8,
+    pub caches: HierarchyStats,
+    pub hazards: HazardStats,
+    pub cpi: CpiComponent,                     // What the cycle was spent doing
+    pub accesses: Vec<(AccessKind, u32, u32)>, // Address and length read or written by committed instructions
 }
 
-const DECODED_QUEUE_CAPACITY: usize = 6;
+// Cycles instructions were held in a stage of the five stage pipeline, by cause
+#[derive(Default, Debug, Clone, Copy, Serialize)]
+pub struct HazardStats {
+    pub data: u64,       // Waiting for an operand to be forwarded or written back
+    pub load_use: u64,   // Waiting for an operand being loaded
+    pub structural: u64, // The next stage was still busy with a multi-cycle instruction
+    pub control: u64,    // Fetch waiting for a mispredicted branch to be resolved
+    pub exception: u64,  // Fetch waiting for an exception entry or return to redirect it
+}
+
+impl HazardStats {
+    pub fn add(&mut self, other: &HazardStats) {
+        self.data = self.data + other.data;
+        self.load_use = self.load_use + other.load_use;
+        self.structural = self.structural + other.structural;
+        self.control = self.control + other.control;
+        self.exception = self.exception + other.exception;
+    }
+
+    pub fn total(&self) -> u64 {
+        self.data + self.load_use + self.structural + self.control + self.exception
+    }
+}
+
+impl UpdateResult {
+    // Combine the results of applying several stages within the same cycle
+    pub fn merge(&mut self, other: UpdateResult) {
+        self.pc_changed = self.pc_changed || other.pc_changed;
+        self.instructions_executed = self.instructions_executed + other.instructions_executed;
+        self.instructions_skipped = self.instructions_skipped + other.instructions_skipped;
+        self.branches_taken = self.branches_taken + other.branches_taken;
+        self.branches_not_taken = self.branches_not_taken + other.branches_not_taken;
+        self.branch_mispredictions = self.branch_mispredictions + other.branch_mispredictions;
+        self.return_stack_hits = self.return_stack_hits + other.return_stack_hits;
+        self.return_stack_misses = self.return_stack_misses + other.return_stack_misses;
+        self.instructions_squashed = self.instructions_squashed + other.instructions_squashed;
+        self.rename_stalls = self.rename_stalls + other.rename_stalls;
+        self.front_end_stalls = self.front_end_stalls + other.front_end_stalls;
+        self.unit_stalls = self.unit_stalls + other.unit_stalls;
+        self.loads_forwarded = self.loads_forwarded + other.loads_forwarded;
+        self.memory_order_violations = self.memory_order_violations + other.memory_order_violations;
+        self.exceptions_taken = self.exceptions_taken + other.exceptions_taken;
+        self.exception_returns = self.exception_returns + other.exception_returns;
+        self.caches.add(&other.caches);
+        self.hazards.add(&other.hazards);
+        self.cpi = other.cpi; // Classified by the last stages to be applied
+        self.accesses.extend(other.accesses);
+    }
+}
+
+// Sizes and timings of the core that aren't owned by one of its components
+#[derive(Debug, Clone, Copy)]
+pub struct CpuConfig {
+    pub decoded_queue_capacity: usize,
+    pub fetch_width: usize, // Instructions fetched per cycle, from an aligned block of 4 bytes each
+    pub decode_width: usize,
+    pub issue_width: usize,
+    pub commit_width: usize, // Instructions committed per cycle
+    pub physical_registers: usize,
+    pub latencies: Latencies,
+    pub multiplier: Multiplier,
+}
+
+// The components of the core that are built from the machine's configuration
+pub struct CpuComponents {
+    pub stations: Vec<Option<InstructionClass>>, // The unit each station is bound to
+    pub branch_predictor: BranchPredictor,
+    pub reorder_buffer: ReorderBuffer,
+    pub memory_hierarchy: MemoryHierarchy,
+}
 
 impl CpuState {
-    pub fn new(memory: Memory, entry: u32, stations: usize) -> Self {
+    pub fn new(
+        memory: Memory,
+        entry: u32,
+        stack_pointer: u32,
+        components: CpuComponents,
+        config: CpuConfig,
+    ) -> Self {
         let memory = Arc::new(RwLock::new(memory));
-        let registers = RegisterFile::new();
-        let stations = (0..stations)
-            .map(|i| ReservationStation::new(i, memory.clone()))
+        let mut registers = RegisterFile::new(stack_pointer);
+        registers.write_by_id(PC, entry);
+        let physical_registers = PhysicalRegisterFile::new(config.physical_registers, &registers);
+        let load_store_queue = Arc::new(RwLock::new(LoadStoreQueue::default()));
+        let memory_hierarchy = Arc::new(RwLock::new(components.memory_hierarchy));
+        let stations = components
+            .stations
+            .into_iter()
+            .enumerate()
+            .map(|(i, unit)| {
+                ReservationStation::new(
+                    i,
+                    memory.clone(),
+                    load_store_queue.clone(),
+                    memory_hierarchy.clone(),
+                    config.latencies,
+                    unit,
+                )
+            })
             .collect();
         Self {
             memory,
             registers,
-            fetched_instruction: None,
+            fetched_instructions: Default::default(),
             should_terminate: false,
+            error: None,
             next_instr_addr: entry,
             reservation_stations: stations,
+            in_flight: vec![],
             decoded_instructions: Default::default(),
-            pending_registers: Default::default(),
+            physical_registers,
+            branch_predictor: components.branch_predictor,
+            reorder_buffer: components.reorder_buffer,
+            load_store_queue,
+            memory_hierarchy,
+            fetch_stall: 0,
+            refilling: None,
+            next_instruction_id: 0,
+            pipeline_trace: None,
+            execution_trace: None,
+            profiler: None,
+            exceptions: None,
+            debug_info: Default::default(),
+            config,
         }
     }
 
-    // Instructions that are already executing are allowed to continue
+    // Discard instructions fetched down a mispredicted path, the back end squashes its own
     pub fn flush_pipeline(&mut self) {
-        self.fetched_instruction = None;
+        if let Some(trace) = &mut self.pipeline_trace {
+            let fetched = self.fetched_instructions.iter().map(|i| i.id);
+            let decoded = self.decoded_instructions.iter().map(|i| i.id);
+            for id in fetched.chain(decoded) {
+                trace.flushed(id);
+            }
+        }
+        self.fetched_instructions.clear();
         self.decoded_instructions.clear();
+        self.fetch_stall = 0;
+        self.branch_predictor.recover();
     }
 
-    // If there will be space for another decoded instruction
-    pub fn decoded_space(&self) -> bool {
-        self.decoded_instructions.len() < DECODED_QUEUE_CAPACITY
+    // Discard every instruction in flight and continue from the architectural state, after a debugger changed it
+    pub fn restart(&mut self) {
+        self.reorder_buffer.squash();
+        for s in &mut self.reservation_stations {
+            s.clear();
+            s.busy = 0;
+        }
+        self.in_flight.clear();
+        self.physical_registers.reset(&self.registers);
+        self.load_store_queue.write().unwrap().squash();
+        if let Some(trace) = &mut self.pipeline_trace {
+            trace.squashed();
+        }
+        if let Some(trace) = &mut self.execution_trace {
+            trace.squashed();
+        }
+        self.flush_pipeline();
+        self.next_instr_addr = self.registers.read_by_id(PC);
+        self.refilling = None;
+    }
+
+    // Number of fetched instructions the decode stage will take this cycle
+    pub fn decode_count(&self) -> usize {
+        if self.fetch_stall > 0 {
+            return 0; // Still waiting for them to arrive from memory
+        }
+        let space = self.config.decoded_queue_capacity - self.decoded_instructions.len();
+        self.config
+            .decode_width
+            .min(space)
+            .min(self.fetched_instructions.len())
     }
 
     // Transition the state to the new state
@@ -73,151 +268,473 @@ impl CpuState {
         &mut self,
         fetch_results: Option<FetchResults>,
         decode_results: Option<DecodeResults>,
-        mut station_results: Vec<Option<StationResults>>,
+        station_results: Vec<Option<StationResults>>,
     ) -> UpdateResult {
         let mut result = UpdateResult::default();
 
-        // If we finished executing an instruction remove it from reservation stations
-        assert_eq!(station_results.len(), self.reservation_stations.len());
-        for (i, s) in station_results.iter_mut().enumerate() {
-            if let Some(s) = s {
-                let next_state = std::mem::take(&mut s.next_state);
-                match next_state {
-                    None => self.reservation_stations[i].clear(),
-                    Some(n) => {
-                        self.reservation_stations[i]
-                            .instruction
-                            .as_mut()
-                            .unwrap()
-                            .imp = n
+        /*
+        Record the memory accesses made this cycle. Loads are recorded before stores, so that
+        a store will find any younger load which executed at the same time and missed it
+        */
+        {
+            let mut lsq = self.load_store_queue.write().unwrap();
+            let accesses: Vec<_> = self
+                .reservation_stations
+                .iter_mut()
+                .flat_map(|s| {
+                    let rob_id = s.rob_id;
+                    std::mem::take(s.memory_accesses.get_mut().unwrap())
+                        .into_iter()
+                        .map(move |a| (rob_id, a))
+                })
+                .collect();
+            let (loads, stores): (Vec<_>, Vec<_>) = accesses
+                .into_iter()
+                .partition(|(_, a)| matches!(a, MemoryAccess::Load { .. }));
+            for (rob_id, access) in loads.into_iter().chain(stores) {
+                if let MemoryAccess::Load {
+                    address,
+                    length,
+                    forwarded,
+                } = access
+                {
+                    if forwarded {
+                        result.loads_forwarded = result.loads_forwarded + 1;
+                    } else {
+                        self.memory_hierarchy.write().unwrap().access(
+                            AccessKind::Load,
+                            address,
+                            length,
+                            &mut result.caches,
+                        );
                     }
                 }
+                if let Some(trace) = &mut self.execution_trace {
+                    trace.accessed(rob_id, &access);
+                }
+                lsq.record(rob_id, access);
             }
         }
 
-        // If we decoded an instruction remove it from fetched
-        if let Some(_) = &decode_results {
-            self.fetched_instruction = None;
+        // Advance the multi-cycle functional units
+        for s in &mut self.reservation_stations {
+            if s.busy > 0 {
+                s.busy = s.busy - 1;
+            }
         }
-
-        // Store the fetched instruction
-        if let Some(fetch) = fetch_results {
-            assert!(self.fetched_instruction.is_none());
-            self.fetched_instruction = Some(fetch.instr);
-            self.next_instr_addr = fetch.next_addr;
+        for i in &mut self.in_flight {
+            i.cycles = i.cycles - 1;
+        }
+        let (finished, in_flight) = std::mem::take(&mut self.in_flight)
+            .into_iter()
+            .partition(|i| i.cycles == 0);
+        self.in_flight = in_flight;
+        for i in finished {
+            self.write_back(i.rob_id, i.results);
         }
 
-        // Store the decoded instruction
-        if let Some(decode_results) = decode_results {
-            assert!(self.decoded_instructions.len() < DECODED_QUEUE_CAPACITY);
-            self.decoded_instructions.push_back(decode_results.instr);
-        }
-
-        // Deal with completed instructions
-        for (i, execute) in station_results.iter().enumerate() {
-            if let Some(execute) = execute {
-                if let Some(register_changes) = &execute.register_changes {
-                    // Write results to architectural registers
-                    for (reg_id, value) in register_changes {
-                        self.registers.write_by_id(*reg_id, *value);
-                        if *reg_id == PC {
-                            // If the PC is changed we must ensure the next fetch uses the updated PC
-                            self.next_instr_addr = *value;
-                            result.pc_changed = true;
-                        }
-                        // Indicate that we are no longer waiting on this register to compute
-                        if let Some(station_id) = self.pending_registers.get(reg_id) {
-                            if *station_id == i {
-                                self.pending_registers.remove(reg_id);
+        // If we finished executing an instruction move its results into the reorder buffer
+        assert_eq!(station_results.len(), self.reservation_stations.len());
+        for (i, s) in station_results.into_iter().enumerate() {
+            if let Some(mut s) = s {
+                let station = &mut self.reservation_stations[i];
+                if let Some(trace) = &mut self.pipeline_trace {
+                    trace.executed(station.rob_id);
+                }
+                match std::mem::take(&mut s.next_state) {
+                    Some(n) => station.instruction.as_mut().unwrap().imp = n,
+                    None => {
+                        let rob_id = station.rob_id;
+                        let class = station.instruction.as_ref().unwrap().imp.class();
+                        let latency = if s.did_skip_instruction {
+                            1
+                        } else {
+                            self.config.latencies.of(class)
+                        };
+                        station.clear();
+                        if latency > 1 {
+                            // The station is free, but the result takes longer to come out of the unit
+                            if let Multiplier::Iterative = self.config.multiplier {
+                                station.busy = latency - 1;
                             }
+                            self.in_flight.push(InFlight {
+                                rob_id,
+                                cycles: latency - 1,
+                                results: s,
+                            });
+                        } else {
+                            self.write_back(rob_id, s);
                         }
                     }
-                    // Write results to waiting stations
-                    for s in &mut self.reservation_stations {
-                        s.receive_broadcast(i, &register_changes);
-                    }
-                }
-                self.should_terminate = execute.should_terminate;
-                if execute.did_execute_instruction {
-                    result.instructions_executed = result.instructions_executed + 1;
                 }
-                if execute.did_skip_instruction {
-                    result.instructions_skipped = result.instructions_skipped + 1;
-                }
-                if execute.did_execute_instruction && execute.instruction_is_branch {
-                    result.branches_taken = result.branches_taken + 1;
+            }
+        }
+
+        // Remove the instructions we decoded from fetched
+        if let Some(decode_results) = &decode_results {
+            self.fetched_instructions
+                .drain(..decode_results.instrs.len());
+        }
+
+        if self.fetch_stall > 0 {
+            self.fetch_stall = self.fetch_stall - 1;
+        }
+
+        // Store the fetched instructions
+        if let Some(mut fetch) = fetch_results {
+            assert!(self.fetched_instructions.is_empty());
+            let mut fetched_bytes = 0;
+            for instr in &mut fetch.instrs {
+                instr.id = self.next_instruction_id;
+                self.next_instruction_id = self.next_instruction_id + 1;
+                if let Some(trace) = &mut self.pipeline_trace {
+                    trace.fetched(instr.id, instr.address);
                 }
-                if execute.did_skip_instruction && execute.instruction_is_branch {
-                    result.branches_not_taken = result.branches_not_taken + 1;
+                if let Ok(bytes) = &instr.bytes {
+                    self.branch_predictor
+                        .fetched(instr.address, bytes.len() as u32);
+                    fetched_bytes = fetched_bytes + bytes.len() as u32;
                 }
             }
+            if fetched_bytes > 0 {
+                self.memory_hierarchy.write().unwrap().access(
+                    AccessKind::Fetch,
+                    fetch.instrs[0].address & 0xFFFFFFFE,
+                    fetched_bytes,
+                    &mut result.caches,
+                );
+            }
+            self.fetch_stall = fetch.latency - 1;
+            self.fetched_instructions.extend(fetch.instrs);
+            self.next_instr_addr = fetch.next_addr;
         }
 
-        // If any stations hold an instruction that may either branch
-        // Or write to memory
-        // Or has conditional execution (may not actually produce its output values)
-        let pending_control_hazards = self
-            .reservation_stations
-            .iter()
-            .flat_map(|s| &s.instruction)
-            .find(|d| {
-                let changes_pc = d.imp.hazardous();
-                let conditional = if let ArmCC::ARM_CC_AL = d.cc {
-                    false
-                } else {
-                    true
-                };
-                changes_pc || conditional
-            })
-            .is_some();
-
-        let available_station = self
-            .reservation_stations
-            .iter()
-            .find(|r| r.instruction.is_none())
-            .is_some();
-
-        if !pending_control_hazards && available_station && !result.pc_changed {
-            // Issue an instruction
-            if let Some(instr) = self.decoded_instructions.pop_front() {
-                let mut source_registers = HashMap::new();
-                let mut required_registers = instr.imp.source_registers();
-                required_registers.insert(PC);
-                if let ArmCC::ARM_CC_AL = instr.cc {
-                } else {
-                    // A condition code means that we will need to read CPSR
-                    required_registers.insert(CPSR);
+        // Store the decoded instructions
+        if let Some(decode_results) = decode_results {
+            if let Some(trace) = &mut self.pipeline_trace {
+                for instr in &decode_results.instrs {
+                    trace.decoded(instr.id, &instr.string);
                 }
-                for r in required_registers {
-                    if r == PC {
-                        source_registers.insert(PC, Register::Ready(instr.address));
-                    } else {
-                        source_registers.insert(r, self.register_value(r));
+            }
+            self.decoded_instructions.extend(decode_results.instrs);
+            assert!(self.decoded_instructions.len() <= self.config.decoded_queue_capacity);
+        }
+
+        // Commit finished instructions in program order
+        for _ in 0..self.config.commit_width {
+            if result.pc_changed || self.should_terminate {
+                break;
+            }
+            if let Some(head) = self.reorder_buffer.head() {
+                if let Some(head_result) = &head.result {
+                    if self.load_store_queue.read().unwrap().violated(head.id) {
+                        // The load read memory before an older store wrote to it, so run it again
+                        self.next_instr_addr = head_result.instruction_address;
+                        result.pc_changed = true;
+                        result.memory_order_violations = 1;
+                        break;
                     }
                 }
-                let station = self
-                    .reservation_stations
-                    .iter_mut()
-                    .find(|r| r.instruction.is_none())
-                    .unwrap();
-                for r in instr.imp.dest_registers() {
-                    self.pending_registers.insert(r, station.id);
-                }
-                station.issue(instr, source_registers);
             }
+            match self.reorder_buffer.commit() {
+                Some(entry) => self.commit_instruction(entry, &mut result),
+                None => break,
+            };
+        }
+
+        if result.instructions_executed + result.instructions_skipped > 0 {
+            self.refilling = None;
         }
 
+        // Everything younger than a mispredicted branch was on the wrong path
         if result.pc_changed {
-            assert_eq!(result.branches_taken, 1); // Should never be more than 1
+            self.refilling = Some(if result.memory_order_violations > 0 {
+                CpiComponent::Flush
+            } else if result.exceptions_taken + result.exception_returns > 0 {
+                CpiComponent::Exception
+            } else {
+                CpiComponent::ControlHazard
+            });
+            result.instructions_squashed = self.reorder_buffer.squash() as u32;
+            if let Some(trace) = &mut self.pipeline_trace {
+                trace.squashed();
+            }
+            if let Some(trace) = &mut self.execution_trace {
+                trace.squashed();
+            }
+            for s in &mut self.reservation_stations {
+                s.clear();
+            }
+            self.in_flight.clear();
+            self.physical_registers.recover();
+            self.load_store_queue.write().unwrap().squash();
+        }
+
+        // Issue in program order, while there are free stations and room to track the instructions
+        for _ in 0..self.config.issue_width {
+            let available_station = self
+                .reservation_stations
+                .iter()
+                .any(|r| r.instruction.is_none() && r.busy == 0);
+            if !available_station || self.reorder_buffer.is_full() || result.pc_changed {
+                break;
+            }
+            let instr = match self.decoded_instructions.front() {
+                Some(instr) => instr,
+                None => {
+                    // The back end could have accepted another instruction but there was none
+                    result.front_end_stalls = 1;
+                    break;
+                }
+            };
+            let class = instr.imp.class();
+            if !self.reservation_stations.iter().any(|r| r.accepts(class)) {
+                result.unit_stalls = 1;
+                break;
+            }
+            // Each destination register of the next instruction needs a free physical register
+            let renamed_count = instr
+                .imp
+                .dest_registers()
+                .iter()
+                .filter(|r| **r != PC)
+                .count();
+            if !self.physical_registers.can_allocate(renamed_count) {
+                result.rename_stalls = 1;
+                break;
+            }
+            let instr = self.decoded_instructions.pop_front().unwrap();
+            self.issue_instruction(instr);
+        }
+
+        if result.pc_changed && result.exceptions_taken + result.exception_returns == 0 {
+            // Should never be more than 1
+            assert_eq!(
+                result.branch_mispredictions + result.memory_order_violations,
+                1
+            );
         }
 
+        result.cpi = self.classify_cycle(&result);
         result
     }
 
-    fn register_value(&self, reg_id: RegId) -> Register {
-        if let Some(station_id) = self.pending_registers.get(&reg_id) {
-            return Register::Pending(*station_id, reg_id);
+    // Rename the instruction's registers and place it in a free station
+    fn issue_instruction(&mut self, instr: DecodedInstruction) {
+        let mut source_registers = HashMap::new();
+        let mut required_registers = instr.imp.source_registers();
+        required_registers.insert(PC);
+        if let ArmCC::ARM_CC_AL = instr.cc {
+        } else {
+            // A condition code means that we will need to read CPSR
+            required_registers.insert(CPSR);
+            // And the current values in case the instruction is skipped
+            required_registers.extend(instr.imp.dest_registers());
+        }
+        for r in required_registers {
+            if r == PC {
+                source_registers.insert(PC, Register::Ready(instr.address));
+            } else {
+                source_registers.insert(r, self.physical_registers.lookup(r));
+            }
+        }
+        let renamed = instr
+            .imp
+            .dest_registers()
+            .into_iter()
+            .filter(|r| *r != PC)
+            .map(|r| {
+                let (p, previous) = self.physical_registers.allocate(r);
+                (r, p, previous)
+            })
+            .collect();
+        let rob_id = self
+            .reorder_buffer
+            .allocate(instr.imp.non_speculative(), renamed);
+        if instr.imp.reads_memory() || instr.imp.writes_memory() {
+            self.load_store_queue.write().unwrap().allocate(rob_id);
+        }
+        let class = instr.imp.class();
+        let station = self
+            .reservation_stations
+            .iter_mut()
+            .find(|r| r.accepts(class))
+            .unwrap();
+        if let Some(trace) = &mut self.pipeline_trace {
+            trace.issued(instr.id, rob_id, station.id);
+        }
+        station.issue(instr, rob_id, source_registers);
+    }
+
+    // Write to the physical registers, forward to waiting stations and mark the instruction finished
+    fn write_back(&mut self, rob_id: RobId, results: StationResults) {
+        let entry = self.reorder_buffer.get_mut(rob_id).unwrap();
+        for (reg_id, value) in results.register_changes.iter().flatten() {
+            if *reg_id == PC {
+                continue;
+            }
+            let (_, p, _) = entry
+                .renamed
+                .iter()
+                .find(|(r, _, _)| r == reg_id)
+                .expect("Instruction wrote a register not in dest_registers()");
+            self.physical_registers.write(*p, *value);
+            for s in &mut self.reservation_stations {
+                s.receive_broadcast(*p, *value);
+            }
+        }
+        entry.result = Some(results);
+        if let Some(trace) = &mut self.pipeline_trace {
+            trace.written_back(rob_id);
+        }
+    }
+
+    // Make the results of the oldest instruction architecturally visible
+    fn commit_instruction(&mut self, entry: RobEntry, result: &mut UpdateResult) {
+        let mut execute = entry.result.unwrap();
+        if let Some((fault, disassembly)) = execute.fault {
+            // Nothing more is committed, the registers are left as they were before the instruction
+            let pc = execute.instruction_address & 0xFFFFFFFE;
+            if self.can_enter_exception(HARD_FAULT)
+                && self.enter_exception(HARD_FAULT, pc, result).is_ok()
+            {
+                return;
+            }
+            self.raise(fault, pc, &disassembly);
+            return;
+        }
+        if let Some(trace) = &mut self.execution_trace {
+            trace.retired(entry.id, &mut execute);
+        }
+        if let Some(output) = &execute.debug_output {
+            println!("{}", output);
+        }
+        self.resolve_next_instruction(&execute, result);
+        if let Some(profiler) = &mut self.profiler {
+            profiler.retired(
+                execute.branch_kind,
+                execute.did_execute_instruction,
+                self.registers.read_by_id(PC),
+            );
+        }
+        if let Some(register_changes) = &execute.register_changes {
+            // Write results to architectural registers
+            for (reg_id, value) in register_changes {
+                if *reg_id == PC {
+                    continue; // Already written by resolve_next_instruction
+                }
+                self.registers.write_by_id(*reg_id, *value);
+            }
+        }
+        for (reg_id, p, previous) in entry.renamed {
+            self.physical_registers.commit(reg_id, p, previous);
+        }
+        let loads = self.load_store_queue.read().unwrap().loads(entry.id);
+        for (address, length) in loads {
+            result.accesses.push((AccessKind::Load, address, length));
+        }
+        // Stores drain from the store buffer, so their cache latency is hidden
+        let stores = self.load_store_queue.write().unwrap().commit(entry.id);
+        for (address, bytes) in stores {
+            result
+                .accesses
+                .push((AccessKind::Store, address, bytes.len() as u32));
+            let written = self.memory.write().unwrap().write_bytes(address, &bytes);
+            if let Err(e) = written {
+                let pc = execute.instruction_address & 0xFFFFFFFE;
+                self.raise(e.into(), pc, "(store)");
+                return;
+            }
+            self.memory_hierarchy.write().unwrap().access(
+                AccessKind::Store,
+                address,
+                bytes.len() as u32,
+                &mut result.caches,
+            );
+        }
+        if let Some(trace) = &mut self.pipeline_trace {
+            trace.retired(entry.id, execute.did_skip_instruction);
+        }
+        if let Some((number, disassembly)) = &execute.exception {
+            let pc = execute.instruction_address & 0xFFFFFFFE;
+            let return_address = pc + execute.instruction_length;
+            if let Err(fault) = self.enter_exception(*number, return_address, result) {
+                self.raise(fault, pc, disassembly);
+            }
+        }
+        let next = self.registers.read_by_id(PC);
+        let handler_mode = matches!(&self.exceptions, Some(e) if !e.active.is_empty());
+        if handler_mode && Exceptions::is_exc_return(next) {
+            if let Err(fault) = self.return_from_exception(result) {
+                let pc = execute.instruction_address & 0xFFFFFFFE;
+                self.raise(fault, pc, "(exception return)");
+            }
+        }
+        if execute.should_terminate {
+            self.should_terminate = true;
+        }
+        if execute.did_execute_instruction {
+            result.instructions_executed = result.instructions_executed + 1;
+        }
+        if execute.did_skip_instruction {
+            result.instructions_skipped = result.instructions_skipped + 1;
+        }
+        if execute.did_execute_instruction && execute.instruction_is_branch {
+            result.branches_taken = result.branches_taken + 1;
+        }
+        if execute.did_skip_instruction && execute.instruction_is_branch {
+            result.branches_not_taken = result.branches_not_taken + 1;
+        }
+    }
+
+    // Stop the simulation with the state of the CPU before the faulting instruction
+    fn raise(&mut self, fault: Fault, pc: u32, disassembly: &str) {
+        let mut error = SimulationError::new(fault, pc, disassembly, &self.registers);
+        error.location = self.debug_info.describe(pc);
+        self.error = Some(error);
+        self.should_terminate = true;
+    }
+
+    // Check the address that was fetched after this instruction was the correct one
+    fn resolve_next_instruction(&mut self, execute: &StationResults, result: &mut UpdateResult) {
+        let branch_target = execute
+            .register_changes
+            .iter()
+            .flatten()
+            .find(|(reg_id, _)| *reg_id == PC)
+            .map(|(_, value)| *value);
+        let next =
+            branch_target.unwrap_or(execute.instruction_address + execute.instruction_length);
+        // The architectural PC points to the next instruction in program order
+        self.registers.write_by_id(PC, next);
+        // EXC_RETURN isn't a real target, return_from_exception redirects fetch to the stacked one
+        let handler_mode = matches!(&self.exceptions, Some(e) if !e.active.is_empty());
+        if handler_mode && Exceptions::is_exc_return(next) {
+            return;
+        }
+
+        if let Some(kind) = execute.branch_kind {
+            self.branch_predictor.update(
+                execute.instruction_address,
+                execute.instruction_length,
+                kind,
+                branch_target.is_some(),
+                next,
+            );
+            if kind == BranchKind::Return {
+                // A correct target from the branch target buffer doesn't count
+                if execute.return_predicted && next == execute.predicted_next {
+                    result.return_stack_hits = result.return_stack_hits + 1;
+                } else {
+                    result.return_stack_misses = result.return_stack_misses + 1;
+                }
+            }
+        }
+        // If the front end fetched down the wrong path we must ensure the next fetch uses the correct PC
+        if next != execute.predicted_next {
+            self.next_instr_addr = next;
+            result.pc_changed = true;
+            result.branch_mispredictions = result.branch_mispredictions + 1;
         }
-        Register::Ready(self.registers.read_by_id(reg_id))
     }
 }
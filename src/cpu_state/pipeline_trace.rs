@@ -0,0 +1,227 @@
+use crate::cpu_state::reorder_buffer::RobId;
+use crate::cpu_state::station::StationId;
+use std::collections::HashMap;
+use std::io::Write;
+
+// Identifies an instruction from when it is fetched until it is retired or flushed
+pub type InstructionId = u64;
+
+enum Event {
+    Stage(&'static str),
+    Retire,
+    Flush,
+}
+
+/*
+Records the lifecycle of every instruction in the Kanata log format, so that it can be opened
+in the Konata pipeline viewer (https://github.com/shioyadan/Konata). Instructions move through
+F (fetch), Dc (decode), Is (waiting in a station), Ex (executing) and Cm (waiting to commit),
+then are either retired or flushed. Stages which begin once the current cycle's state has been
+applied, such as a station being filled, are deferred to the start of the next cycle.
+ */
+pub struct PipelineTrace {
+    out: Box<dyn Write + Send + Sync>,
+    stages: HashMap<InstructionId, &'static str>, // Current stage of each instruction
+    issued: HashMap<RobId, InstructionId>,        // The instruction in each reorder buffer entry
+    deferred: Vec<(InstructionId, Event)>,
+    retired: u64,
+}
+
+impl PipelineTrace {
+    pub fn new(out: Box<dyn Write + Send + Sync>) -> Self {
+        let mut trace = Self {
+            out,
+            stages: Default::default(),
+            issued: Default::default(),
+            deferred: vec![],
+            retired: 0,
+        };
+        trace.write("Kanata\t0004".to_string());
+        trace.write("C=\t0".to_string());
+        trace
+    }
+
+    fn write(&mut self, line: String) {
+        writeln!(self.out, "{}", line).expect("Failed to write pipeline trace");
+    }
+
+    // Called once every cycle has been simulated
+    pub fn next_cycle(&mut self) {
+        self.write("C\t1".to_string());
+        for (id, event) in std::mem::take(&mut self.deferred) {
+            match event {
+                Event::Stage(stage) => self.stage(id, stage),
+                Event::Retire => {
+                    self.end_stage(id);
+                    let retired = self.retired;
+                    self.retired = self.retired + 1;
+                    self.write(format!("R\t{}\t{}\t0", id, retired));
+                }
+                Event::Flush => {
+                    self.end_stage(id);
+                    self.write(format!("R\t{}\t0\t1", id));
+                }
+            }
+        }
+        self.out.flush().expect("Failed to write pipeline trace");
+    }
+
+    fn end_stage(&mut self, id: InstructionId) {
+        if let Some(previous) = self.stages.remove(&id) {
+            self.write(format!("E\t{}\t0\t{}", id, previous));
+        }
+    }
+
+    fn stage(&mut self, id: InstructionId, stage: &'static str) {
+        if self.stages.get(&id) == Some(&stage) {
+            return;
+        }
+        self.end_stage(id);
+        self.stages.insert(id, stage);
+        self.write(format!("S\t{}\t0\t{}", id, stage));
+    }
+
+    // Start a stage now, replacing any which were waiting for the next cycle
+    fn stage_now(&mut self, id: InstructionId, stage: &'static str) {
+        self.deferred
+            .retain(|(i, e)| *i != id || !matches!(e, Event::Stage(_)));
+        self.stage(id, stage);
+    }
+
+    pub fn fetched(&mut self, id: InstructionId, address: u32) {
+        self.write(format!("I\t{}\t{}\t0", id, id));
+        self.write(format!("L\t{}\t0\t{:08X}: ", id, address & 0xFFFFFFFE));
+        self.stage_now(id, "F");
+    }
+
+    pub fn decoded(&mut self, id: InstructionId, string: &str) {
+        self.write(format!("L\t{}\t0\t{}", id, string));
+        self.stage_now(id, "Dc");
+    }
+
+    pub fn issued(&mut self, id: InstructionId, rob_id: RobId, station: StationId) {
+        self.issued.insert(rob_id, id);
+        self.write(format!("L\t{}\t1\tstation {}, rob {}", id, station, rob_id));
+        self.deferred.push((id, Event::Stage("Is")));
+    }
+
+    pub fn executed(&mut self, rob_id: RobId) {
+        if let Some(id) = self.issued.get(&rob_id).copied() {
+            self.stage_now(id, "Ex");
+        }
+    }
+
+    pub fn written_back(&mut self, rob_id: RobId) {
+        if let Some(id) = self.issued.get(&rob_id).copied() {
+            self.deferred.push((id, Event::Stage("Cm")));
+        }
+    }
+
+    pub fn retired(&mut self, rob_id: RobId, skipped: bool) {
+        if let Some(id) = self.issued.remove(&rob_id) {
+            if skipped {
+                self.write(format!("L\t{}\t1\t (skipped)", id));
+            }
+            self.deferred.push((id, Event::Retire));
+        }
+    }
+
+    // An instruction which was fetched or decoded but never issued
+    pub fn flushed(&mut self, id: InstructionId) {
+        self.deferred.push((id, Event::Flush));
+    }
+
+    // Every instruction in the reorder buffer was squashed
+    pub fn squashed(&mut self) {
+        let ids: Vec<_> = self.issued.drain().map(|(_, id)| id).collect();
+        for id in ids {
+            self.flushed(id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::tests::sum_program;
+    use crate::{SimulationBuilder, SimulatorType};
+    use std::sync::{Arc, Mutex};
+
+    // Shares the written trace with the test
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn kanata_lifecycle() {
+        let buffer = Buffer::default();
+        let mut trace = PipelineTrace::new(Box::new(buffer.clone()));
+        trace.fetched(0, 0x1001);
+        trace.fetched(1, 0x1003);
+        trace.next_cycle();
+        trace.decoded(0, "movs r0, #0");
+        trace.issued(0, 7, 2);
+        trace.next_cycle();
+        trace.executed(7);
+        trace.written_back(7);
+        trace.retired(7, false);
+        trace.flushed(1);
+        trace.next_cycle();
+
+        let log = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let expected = [
+            "Kanata\t0004",
+            "C=\t0",
+            "I\t0\t0\t0",
+            "L\t0\t0\t00001000: ",
+            "S\t0\t0\tF",
+            "I\t1\t1\t0",
+            "L\t1\t0\t00001002: ",
+            "S\t1\t0\tF",
+            "C\t1",
+            "L\t0\t0\tmovs r0, #0",
+            "E\t0\t0\tF",
+            "S\t0\t0\tDc",
+            "L\t0\t1\tstation 2, rob 7",
+            "C\t1",
+            "E\t0\t0\tDc",
+            "S\t0\t0\tIs",
+            "E\t0\t0\tIs",
+            "S\t0\t0\tEx",
+            "C\t1",
+            "E\t0\t0\tEx",
+            "S\t0\t0\tCm",
+            "E\t0\t0\tCm",
+            "R\t0\t0\t0",
+            "E\t1\t0\tF",
+            "R\t1\t0\t1",
+        ];
+        assert_eq!(log.lines().collect::<Vec<_>>(), expected);
+
+        // Every instruction fetched is either retired or flushed
+        let buffer = Buffer::default();
+        let mut simulation = SimulationBuilder::new(SimulatorType::OutOfOrder)
+            .build(sum_program())
+            .unwrap();
+        simulation.state.pipeline_trace = Some(PipelineTrace::new(Box::new(buffer.clone())));
+        let stats = simulation.run().unwrap();
+        let log = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let count = |prefix: &str, suffix: &str| {
+            log.lines()
+                .filter(|l| l.starts_with(prefix) && l.ends_with(suffix))
+                .count() as u64
+        };
+        assert_eq!(count("R\t", "\t0"), 33);
+        assert_eq!(count("C\t", ""), stats.total_cycles);
+        assert!(count("R\t", "\t1") >= stats.instructions_squashed);
+    }
+}
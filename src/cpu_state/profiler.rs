@@ -0,0 +1,288 @@
+use crate::branch_predictor::BranchKind;
+use crate::symbols::SymbolTable;
+use std::collections::{HashMap, HashSet};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct Counts {
+    instructions: u64,
+    cycles: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FunctionProfile {
+    pub address: u32,
+    pub calls: u64,
+    pub instructions: u64, // Retired while this function was at the top of the stack
+    pub self_cycles: u64,
+    pub total_cycles: u64, // Including the functions it called
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CallEdge {
+    pub caller: u32,
+    pub callee: u32,
+    pub calls: u64,
+    pub cycles: u64, // Spent in the callee and below, when called from this caller
+}
+
+/*
+Follows calls and returns as they're retired to keep a shadow of the guest's call stack, and charges
+every cycle to the stack that was current while it passed. Stall cycles count towards the function of
+the instruction holding up the commit, which is the one at the top of the stack.
+ */
+pub struct Profiler {
+    stack: Vec<u32>, // Entry addresses of the functions being executed, outermost first
+    stacks: Vec<(Vec<u32>, Counts)>,
+    index: HashMap<Vec<u32>, usize>, // Position of each distinct stack in stacks
+    current: usize,
+    calls: HashMap<(u32, u32), u64>, // Caller and callee
+}
+
+impl Profiler {
+    // The program's entry point is the root of every stack
+    pub fn new(entry: u32) -> Self {
+        let stack = vec![entry & 0xFFFFFFFE];
+        Self {
+            stacks: vec![(stack.clone(), Counts::default())],
+            index: hashmap! {stack.clone() => 0},
+            stack,
+            current: 0,
+            calls: Default::default(),
+        }
+    }
+
+    // Called at the start of every cycle, before anything is committed
+    pub fn next_cycle(&mut self) {
+        let counts = &mut self.stacks[self.current].1;
+        counts.cycles = counts.cycles + 1;
+    }
+
+    pub fn retired(&mut self, kind: Option<BranchKind>, executed: bool, next: u32) {
+        let counts = &mut self.stacks[self.current].1;
+        counts.instructions = counts.instructions + 1;
+        if !executed {
+            return;
+        }
+        match kind {
+            Some(BranchKind::Call) => {
+                let caller = *self.stack.last().unwrap();
+                let callee = next & 0xFFFFFFFE;
+                let calls = self.calls.entry((caller, callee)).or_default();
+                *calls = *calls + 1;
+                self.stack.push(callee);
+            }
+            // Returning from the entry point leaves nothing to attribute to a caller
+            Some(BranchKind::Return) if self.stack.len() > 1 => {
+                self.stack.pop();
+            }
+            _ => return,
+        }
+        self.switch_stack();
+    }
+
+    // An exception handler runs as if it was called from whatever it interrupted
+    pub fn interrupted(&mut self, handler: u32) {
+        let caller = *self.stack.last().unwrap();
+        let handler = handler & 0xFFFFFFFE;
+        let calls = self.calls.entry((caller, handler)).or_default();
+        *calls = *calls + 1;
+        self.stack.push(handler);
+        self.switch_stack();
+    }
+
+    fn switch_stack(&mut self) {
+        self.current = match self.index.get(&self.stack) {
+            Some(i) => *i,
+            None => {
+                self.stacks.push((self.stack.clone(), Counts::default()));
+                self.index.insert(self.stack.clone(), self.stacks.len() - 1);
+                self.stacks.len() - 1
+            }
+        };
+    }
+
+    // Every function that was executed, most self cycles first
+    pub fn functions(&self) -> Vec<FunctionProfile> {
+        let mut functions: HashMap<u32, FunctionProfile> = HashMap::new();
+        for (stack, counts) in &self.stacks {
+            let top = functions.entry(*stack.last().unwrap()).or_default();
+            top.instructions = top.instructions + counts.instructions;
+            top.self_cycles = top.self_cycles + counts.cycles;
+            // Recursive functions appear more than once, but the cycles only pass once
+            for address in stack.iter().collect::<HashSet<_>>() {
+                let function = functions.entry(*address).or_default();
+                function.total_cycles = function.total_cycles + counts.cycles;
+            }
+        }
+        for ((_, callee), calls) in &self.calls {
+            let function = functions.entry(*callee).or_default();
+            function.calls = function.calls + calls;
+        }
+        let mut functions: Vec<FunctionProfile> = functions
+            .into_iter()
+            .map(|(address, f)| FunctionProfile { address, ..f })
+            .collect();
+        functions.sort_by_key(|f| (std::cmp::Reverse(f.self_cycles), f.address));
+        functions
+    }
+
+    pub fn call_graph(&self) -> Vec<CallEdge> {
+        let mut edges: Vec<CallEdge> = self
+            .calls
+            .iter()
+            .map(|((caller, callee), calls)| {
+                let cycles = self
+                    .stacks
+                    .iter()
+                    .filter(|(stack, _)| {
+                        stack
+                            .windows(2)
+                            .any(|pair| pair[0] == *caller && pair[1] == *callee)
+                    })
+                    .map(|(_, counts)| counts.cycles)
+                    .sum();
+                CallEdge {
+                    caller: *caller,
+                    callee: *callee,
+                    calls: *calls,
+                    cycles,
+                }
+            })
+            .collect();
+        edges.sort_by_key(|e| (e.caller, std::cmp::Reverse(e.cycles), e.callee));
+        edges
+    }
+
+    // The flat profile followed by the call graph
+    pub fn report(&self, symbols: &SymbolTable) -> String {
+        let functions = self.functions();
+        let total: u64 = functions.iter().map(|f| f.self_cycles).sum();
+        let mut output = String::from("Flat profile:\n");
+        output.push_str(&format!(
+            "{:>7}  {:>12}  {:>12}  {:>12}  {:>8}  {}\n",
+            "self %", "self cycles", "total cycles", "instructions", "calls", "function"
+        ));
+        for f in &functions {
+            output.push_str(&format!(
+                "{:>6.2}%  {:>12}  {:>12}  {:>12}  {:>8}  {}\n",
+                100.0 * f.self_cycles as f64 / total.max(1) as f64,
+                f.self_cycles,
+                f.total_cycles,
+                f.instructions,
+                f.calls,
+                name(symbols, f.address)
+            ));
+        }
+
+        output.push_str("\nCall graph:\n");
+        let edges = self.call_graph();
+        for f in &functions {
+            output.push_str(&format!(
+                "{} ({} cycles)\n",
+                name(symbols, f.address),
+                f.total_cycles
+            ));
+            for e in edges.iter().filter(|e| e.callee == f.address) {
+                output.push_str(&format!(
+                    "    called by {} {} times\n",
+                    name(symbols, e.caller),
+                    e.calls
+                ));
+            }
+            for e in edges.iter().filter(|e| e.caller == f.address) {
+                output.push_str(&format!(
+                    "    calls {} {} times, {} cycles\n",
+                    name(symbols, e.callee),
+                    e.calls,
+                    e.cycles
+                ));
+            }
+        }
+        output
+    }
+
+    // One line per stack with the cycles spent in it, for flamegraph.pl or inferno-flamegraph
+    pub fn folded_stacks(&self, symbols: &SymbolTable) -> String {
+        let mut lines: Vec<String> = self
+            .stacks
+            .iter()
+            .filter(|(_, counts)| counts.cycles > 0)
+            .map(|(stack, counts)| {
+                let names: Vec<String> = stack.iter().map(|a| name(symbols, *a)).collect();
+                format!("{} {}\n", names.join(";"), counts.cycles)
+            })
+            .collect();
+        lines.sort();
+        lines.concat()
+    }
+}
+
+fn name(symbols: &SymbolTable, address: u32) -> String {
+    symbols
+        .describe(address)
+        .unwrap_or_else(|| format!("{:#x}", address))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::tests::call_program;
+    use crate::symbols::Symbol;
+    use crate::{SimulationBuilder, SimulatorType};
+
+    #[test]
+    fn call_program_profile() {
+        for simulator in &[SimulatorType::Scalar, SimulatorType::OutOfOrder] {
+            let mut simulation = SimulationBuilder::new(*simulator)
+                .build(call_program())
+                .unwrap();
+            simulation.state.profiler = Some(Profiler::new(simulation.pc()));
+            simulation.run_until(&[]);
+            let profiler = simulation.state.profiler.as_ref().unwrap();
+
+            let functions = profiler.functions();
+            assert_eq!(functions.len(), 2);
+            let main = functions.iter().find(|f| f.address == 0x1000).unwrap();
+            let square = functions.iter().find(|f| f.address == 0x101C).unwrap();
+            // 2 + 5 x 8 in the loop + 2, and push, muls, pop for each of the 10 calls
+            assert_eq!(main.instructions, 44);
+            assert_eq!(square.instructions, 30);
+            assert_eq!(square.calls, 10);
+            assert_eq!(main.total_cycles, simulation.stats.total_cycles);
+            assert_eq!(main.self_cycles + square.self_cycles, main.total_cycles);
+            assert_eq!(square.total_cycles, square.self_cycles);
+            assert_eq!(
+                profiler.call_graph(),
+                vec![CallEdge {
+                    caller: 0x1000,
+                    callee: 0x101C,
+                    calls: 10,
+                    cycles: square.total_cycles,
+                }]
+            );
+
+            let symbols = SymbolTable::new(vec![
+                Symbol {
+                    name: "main".to_string(),
+                    address: 0x1000,
+                    size: 0x1C,
+                },
+                Symbol {
+                    name: "square".to_string(),
+                    address: 0x101C,
+                    size: 6,
+                },
+            ]);
+            assert_eq!(
+                profiler.folded_stacks(&symbols),
+                format!(
+                    "main {}\nmain;square {}\n",
+                    main.self_cycles, square.self_cycles
+                )
+            );
+            let report = profiler.report(&symbols);
+            assert!(report.contains("    calls square 10 times"), "{}", report);
+            assert!(report.contains("    called by main 10 times"), "{}", report);
+        }
+    }
+}
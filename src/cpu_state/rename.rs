@@ -0,0 +1,171 @@
+use crate::cpu_state::station::Register;
+use crate::registers::ids::PC;
+use crate::registers::RegisterFile;
+use capstone::RegId;
+use std::collections::{HashMap, VecDeque};
+
+pub type PhysicalRegister = usize;
+
+/*
+Architectural registers are renamed onto a larger file of physical registers, so that an
+instruction which only reuses a register name (WAR / WAW) does not have to wait.
+CPSR is renamed like any other register, the PC is not as it is known at issue.
+*/
+pub struct PhysicalRegisterFile {
+    values: Vec<u32>,
+    ready: Vec<bool>,
+    free_list: VecDeque<PhysicalRegister>,
+    speculative: HashMap<RegId, PhysicalRegister>, // Mapping used by newly issued instructions
+    committed: HashMap<RegId, PhysicalRegister>,   // Mapping of the architectural state
+}
+
+impl PhysicalRegisterFile {
+    // An instruction may write every register, there must always be enough to rename them
+    pub fn minimum_size() -> usize {
+        2 * (RegisterFile::new(0).ids().len() - 1)
+    }
+
+    pub fn new(size: usize, registers: &RegisterFile) -> Self {
+        let renamed: Vec<RegId> = registers.ids().into_iter().filter(|r| *r != PC).collect();
+        assert!(
+            size >= Self::minimum_size(),
+            "At least {} physical registers are required",
+            Self::minimum_size()
+        );
+        let mut values = vec![0; size];
+        let mut committed = HashMap::new();
+        for (p, r) in renamed.into_iter().enumerate() {
+            values[p] = registers.read_by_id(r);
+            committed.insert(r, p);
+        }
+        let mut file = Self {
+            values,
+            ready: vec![true; size],
+            free_list: VecDeque::new(),
+            speculative: committed.clone(),
+            committed,
+        };
+        file.recover();
+        file
+    }
+
+    // The value of a register as seen by the next instruction to issue
+    pub fn lookup(&self, reg_id: RegId) -> Register {
+        let p = self.speculative[&reg_id];
+        if self.ready[p] {
+            Register::Ready(self.values[p])
+        } else {
+            Register::Pending(p, reg_id)
+        }
+    }
+
+    pub fn can_allocate(&self, count: usize) -> bool {
+        self.free_list.len() >= count
+    }
+
+    // Give a register a new physical register, returning it along with the one it replaced
+    pub fn allocate(&mut self, reg_id: RegId) -> (PhysicalRegister, PhysicalRegister) {
+        let p = self
+            .free_list
+            .pop_front()
+            .expect("No free physical registers");
+        self.ready[p] = false;
+        let previous = self.speculative.insert(reg_id, p).unwrap();
+        (p, previous)
+    }
+
+    pub fn write(&mut self, p: PhysicalRegister, value: u32) {
+        self.values[p] = value;
+        self.ready[p] = true;
+    }
+
+    // Once the new value is committed nothing can read the previous physical register
+    pub fn commit(&mut self, reg_id: RegId, p: PhysicalRegister, previous: PhysicalRegister) {
+        self.committed.insert(reg_id, p);
+        self.free_list.push_back(previous);
+    }
+
+    // Start again from the architectural registers, once nothing is in flight
+    pub fn reset(&mut self, registers: &RegisterFile) {
+        *self = Self::new(self.values.len(), registers);
+    }
+
+    // Discard the mappings of every instruction that has not been committed
+    pub fn recover(&mut self) {
+        self.speculative = self.committed.clone();
+        let mut in_use = vec![false; self.values.len()];
+        for p in self.committed.values() {
+            in_use[*p] = true;
+        }
+        self.free_list = (0..self.values.len()).filter(|p| !in_use[*p]).collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::tests::program_from;
+    use crate::registers::ids::{CPSR, R0};
+    use crate::{SimulationBuilder, SimulatorType};
+
+    #[test]
+    fn rename_and_recover() {
+        let registers = RegisterFile::new(0x1000);
+        let mut prf = PhysicalRegisterFile::new(32, &registers);
+        assert!(prf.can_allocate(16) && !prf.can_allocate(17));
+
+        let (r0, r0_previous) = prf.allocate(R0);
+        assert!(matches!(prf.lookup(R0), Register::Pending(p, R0) if p == r0));
+        prf.write(r0, 5);
+        prf.commit(R0, r0, r0_previous);
+        assert!(matches!(prf.lookup(R0), Register::Ready(5)));
+
+        prf.allocate(CPSR);
+        prf.allocate(R0);
+        assert!(!prf.can_allocate(15));
+        prf.recover();
+        assert!(matches!(prf.lookup(R0), Register::Ready(5)));
+        assert!(matches!(prf.lookup(CPSR), Register::Ready(0)));
+        assert!(prf.can_allocate(16));
+    }
+
+    /*
+        movs r0, #3
+        muls r0, r0
+        muls r0, r0
+        muls r0, r0
+        mov r1, r8
+        mov r2, r8
+        ...
+        mov r7, r8
+        (the movs are repeated)
+        adds r0, r0, r1
+        svc #1
+        nop
+    */
+    #[test]
+    fn register_renaming() {
+        let movs = vec![0x4641, 0x4642, 0x4643, 0x4644, 0x4645, 0x4646, 0x4647];
+        let mut code: Vec<u16> = vec![0x2003, 0x4340, 0x4340, 0x4340];
+        for _ in 0..3 {
+            code.extend(&movs);
+        }
+        code.extend(&[0x1840, 0xDF01, 0xBF00]);
+        let run = |physical_registers| {
+            let mut simulation = SimulationBuilder::new(SimulatorType::OutOfOrder)
+                .physical_registers(physical_registers)
+                .build(program_from(&code))
+                .unwrap();
+            simulation.run_until(&[]);
+            assert_eq!(simulation.state.registers.read_by_id(R0), 6561);
+            simulation.stats
+        };
+        // The moves are independent, so issue while the multiplies are executing
+        // But they cannot commit, so hold on to their physical registers
+        let small = run(32);
+        let large = run(64);
+        assert!(small.rename_stalls > 0);
+        assert_eq!(large.rename_stalls, 0);
+        assert!(small.total_cycles > large.total_cycles);
+    }
+}
@@ -0,0 +1,132 @@
+use crate::cpu_state::execute::StationResults;
+use crate::cpu_state::rename::PhysicalRegister;
+use capstone::RegId;
+use std::collections::VecDeque;
+
+// Ids are never reused, so a result can never be confused with that of a squashed instruction
+pub type RobId = u64;
+
+pub struct RobEntry {
+    pub id: RobId,
+    pub non_speculative: bool, // Must not execute until it is the oldest instruction
+    pub renamed: Vec<(RegId, PhysicalRegister, PhysicalRegister)>, // Destination, new, previous
+    pub result: Option<StationResults>, // None means instruction has not finished yet
+}
+
+// Holds issued instructions in program order, so that they can be committed in order
+pub struct ReorderBuffer {
+    capacity: usize,
+    entries: VecDeque<RobEntry>,
+    next_id: RobId,
+}
+
+impl ReorderBuffer {
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0);
+        Self {
+            capacity,
+            entries: VecDeque::with_capacity(capacity),
+            next_id: 0,
+        }
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.entries.len() >= self.capacity
+    }
+
+    pub fn allocate(
+        &mut self,
+        non_speculative: bool,
+        renamed: Vec<(RegId, PhysicalRegister, PhysicalRegister)>,
+    ) -> RobId {
+        assert!(!self.is_full());
+        let id = self.next_id;
+        self.next_id = self.next_id + 1;
+        self.entries.push_back(RobEntry {
+            id,
+            non_speculative,
+            renamed,
+            result: None,
+        });
+        id
+    }
+
+    pub fn get(&self, id: RobId) -> Option<&RobEntry> {
+        let head = self.entries.front()?.id;
+        if id < head {
+            return None;
+        }
+        self.entries.get((id - head) as usize)
+    }
+
+    pub fn get_mut(&mut self, id: RobId) -> Option<&mut RobEntry> {
+        let head = self.entries.front()?.id;
+        if id < head {
+            return None;
+        }
+        self.entries.get_mut((id - head) as usize)
+    }
+
+    pub fn head(&self) -> Option<&RobEntry> {
+        self.entries.front()
+    }
+
+    // Remove the oldest instruction, but only once it has finished
+    pub fn commit(&mut self) -> Option<RobEntry> {
+        match self.entries.front() {
+            Some(head) if head.result.is_some() => self.entries.pop_front(),
+            _ => None,
+        }
+    }
+
+    // Discard every instruction, returning how many were squashed
+    pub fn squash(&mut self) -> usize {
+        let squashed = self.entries.len();
+        self.entries.clear();
+        squashed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::tests::memory_program;
+    use crate::registers::ids::{R0, R2};
+    use crate::{PredictorType, SimulationBuilder, SimulatorType};
+
+    #[test]
+    fn commits_in_order() {
+        let mut rob = ReorderBuffer::new(2);
+        let a = rob.allocate(false, vec![]);
+        let b = rob.allocate(true, vec![]);
+        assert!(rob.is_full());
+        rob.get_mut(b).unwrap().result = Some(StationResults::default());
+        assert!(rob.commit().is_none());
+        rob.get_mut(a).unwrap().result = Some(StationResults::default());
+        assert!(rob.commit().is_some());
+        assert_eq!(rob.head().unwrap().id, b);
+        assert!(rob.get(a).is_none());
+        assert_eq!(rob.squash(), 1);
+    }
+
+    #[test]
+    fn speculative_execution() {
+        let run = |sim| {
+            let mut simulation = SimulationBuilder::new(sim)
+                .predictor(PredictorType::TwoBit)
+                .build(memory_program())
+                .unwrap();
+            simulation.run_until(&[]);
+            assert_eq!(simulation.state.registers.read_by_id(R0), 55, "{:?}", sim);
+            assert_eq!(simulation.state.registers.read_by_id(R2), 0x80000 - 40);
+            simulation.stats
+        };
+        let scalar = run(SimulatorType::Scalar);
+        let pipelined = run(SimulatorType::Pipelined);
+        let out_of_order = run(SimulatorType::OutOfOrder);
+        assert_eq!(scalar.instructions_squashed, 0);
+        // The loop exit is mispredicted, squashing the next iteration which was already issued
+        assert!(out_of_order.instructions_squashed > 0);
+        assert!(out_of_order.total_cycles < pipelined.total_cycles);
+    }
+}
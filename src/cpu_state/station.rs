@@ -1,36 +1,68 @@
+use crate::cache::hierarchy::{AccessKind, MemoryHierarchy};
 use crate::cpu_state::decode::DecodedInstruction;
-use crate::memory::Memory;
+use crate::cpu_state::load_store_queue::{LoadStoreQueue, MemoryAccess};
+use crate::cpu_state::rename::PhysicalRegister;
+use crate::cpu_state::reorder_buffer::RobId;
+use crate::instructions::{InstructionClass, Latencies};
+use crate::memory::{byte_addresses, Memory, MemoryAccessError};
 use crate::registers::ids::{CPSR, PC};
 use crate::registers::{ConditionFlag, RegisterFile};
-use capstone::arch::arm::{ArmCC, ArmOpMem, ArmOperand, ArmOperandType, ArmShift};
+use capstone::arch::arm::{ArmCC, ArmOpMem, ArmOperand, ArmOperandType};
 use capstone::RegId;
 use std::collections::HashMap;
-use std::sync::{Arc, RwLock};
+use std::convert::TryInto;
+use std::sync::{Arc, Mutex, RwLock};
 
 pub type StationId = usize;
 
 pub enum Register {
     Ready(u32),
-    Pending(StationId, RegId),
+    Pending(PhysicalRegister, RegId), // Waiting for an instruction to write the physical register
 }
 
 pub struct ReservationStation {
     pub id: StationId,
     pub instruction: Option<DecodedInstruction>,
+    pub rob_id: RobId,
     pub source_registers: HashMap<RegId, Register>,
     pub memory: Arc<RwLock<Memory>>,
+    pub load_store_queue: Arc<RwLock<LoadStoreQueue>>,
+    pub memory_hierarchy: Arc<RwLock<MemoryHierarchy>>,
+    pub memory_accesses: Mutex<Vec<MemoryAccess>>, // Recorded in the load/store queue after each cycle
+    pub latencies: Latencies,
+    pub unit: Option<InstructionClass>, // None executes any class of instruction
+    pub busy: u32, // Cycles until an iterative unit can start another instruction
 }
 
 impl ReservationStation {
-    pub fn new(id: StationId, memory: Arc<RwLock<Memory>>) -> Self {
+    pub fn new(
+        id: StationId,
+        memory: Arc<RwLock<Memory>>,
+        load_store_queue: Arc<RwLock<LoadStoreQueue>>,
+        memory_hierarchy: Arc<RwLock<MemoryHierarchy>>,
+        latencies: Latencies,
+        unit: Option<InstructionClass>,
+    ) -> Self {
         Self {
             id,
             instruction: None,
+            rob_id: 0,
             source_registers: Default::default(),
             memory,
+            load_store_queue,
+            memory_hierarchy,
+            memory_accesses: Default::default(),
+            latencies,
+            unit,
+            busy: 0,
         }
     }
 
+    // Free, and bound to a unit which can execute the class of instruction
+    pub fn accepts(&self, class: InstructionClass) -> bool {
+        self.instruction.is_none() && self.busy == 0 && self.unit.unwrap_or(class) == class
+    }
+
     pub fn clear(&mut self) {
         self.instruction = None;
         self.source_registers.clear();
@@ -39,9 +71,11 @@ impl ReservationStation {
     pub fn issue(
         &mut self,
         instruction: DecodedInstruction,
+        rob_id: RobId,
         source_registers: HashMap<RegId, Register>,
     ) {
         self.instruction = Some(instruction);
+        self.rob_id = rob_id;
         self.source_registers = source_registers;
     }
 
@@ -58,14 +92,12 @@ impl ReservationStation {
         }
     }
 
+    // Other operands are decoded as an unsupported instruction, which faults instead of executing
     pub fn value_of_flexible_second_operand(&self, op: &ArmOperand) -> u32 {
         match op.op_type {
-            ArmOperandType::Reg(reg_id) => {
-                assert!(op.shift == ArmShift::Invalid, "Shift not supported");
-                self.read_by_id(reg_id)
-            }
+            ArmOperandType::Reg(reg_id) => self.read_by_id(reg_id),
             ArmOperandType::Imm(value) => value as u32,
-            _ => panic!("Unsupported type"),
+            _ => unreachable!("Operand was checked when the instruction was decoded"),
         }
     }
 
@@ -93,7 +125,8 @@ impl ReservationStation {
         let z = ConditionFlag::Z.read_flag(cpsr);
         let v = ConditionFlag::V.read_flag(cpsr);
         return match cc {
-            ArmCC::ARM_CC_INVALID => panic!("CC Invalid"),
+            // Instructions which couldn't be decoded have no condition, they fault when executed
+            ArmCC::ARM_CC_INVALID => true,
             ArmCC::ARM_CC_EQ => z == true,
             ArmCC::ARM_CC_NE => z == false,
             ArmCC::ARM_CC_HS => c == true,
@@ -112,15 +145,94 @@ impl ReservationStation {
         };
     }
 
-    pub fn receive_broadcast(&mut self, source_id: StationId, changes: &Vec<(RegId, u32)>) {
+    pub fn receive_broadcast(&mut self, source: PhysicalRegister, value: u32) {
         for (_, reg) in &mut self.source_registers {
-            if let Register::Pending(station_id, reg_id) = reg {
-                if *station_id == source_id {
-                    let val = changes.iter().find(|(a, _)| a == reg_id).unwrap().1;
-                    *reg = Register::Ready(val);
+            if let Register::Pending(p, _) = reg {
+                if *p == source {
+                    *reg = Register::Ready(value);
+                }
+            }
+        }
+    }
+
+    // Loads see the values of older stores that have not been committed yet
+    pub fn read_bytes(&self, address: u32, length: u32) -> Result<Vec<u8>, MemoryAccessError> {
+        let memory = self.memory.read().unwrap();
+        let lsq = self.load_store_queue.read().unwrap();
+        let mut bytes = Vec::with_capacity(length as usize);
+        let mut forwarded = false;
+        for a in byte_addresses(address, length)? {
+            match lsq.forward(self.rob_id, a) {
+                Some(b) => {
+                    bytes.push(b);
+                    forwarded = true;
                 }
+                None => bytes.push(memory.read_byte(a)?),
             }
         }
+        self.memory_accesses
+            .lock()
+            .unwrap()
+            .push(MemoryAccess::Load {
+                address,
+                length,
+                forwarded,
+            });
+        Ok(bytes)
+    }
+
+    // Cycles taken to read memory once the address is known
+    pub fn load_latency(&self, address: u32, length: u32) -> u32 {
+        let lsq = self.load_store_queue.read().unwrap();
+        let forwarded = match byte_addresses(address, length) {
+            Ok(mut addresses) => addresses.any(|a| lsq.forward(self.rob_id, a).is_some()),
+            Err(_) => false,
+        };
+        if forwarded {
+            return 1;
+        }
+        self.memory_hierarchy
+            .read()
+            .unwrap()
+            .latency(AccessKind::Load, address, length)
+    }
+
+    pub fn read_u32(&self, address: u32) -> Result<u32, MemoryAccessError> {
+        let bytes = self.read_bytes(address, 4)?;
+        let bytes = bytes
+            .as_slice()
+            .try_into()
+            .map_err(|_| MemoryAccessError::BadAddress(address))?;
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    pub fn read_u16(&self, address: u32) -> Result<u16, MemoryAccessError> {
+        let bytes = self.read_bytes(address, 2)?;
+        let bytes = bytes
+            .as_slice()
+            .try_into()
+            .map_err(|_| MemoryAccessError::BadAddress(address))?;
+        Ok(u16::from_le_bytes(bytes))
+    }
+
+    pub fn read_byte(&self, address: u32) -> Result<u8, MemoryAccessError> {
+        Ok(self.read_bytes(address, 1)?[0])
+    }
+
+    // Stores are held in the load/store queue, and only written to memory when committed
+    pub fn write_bytes(&self, address: u32, bytes: &[u8]) -> Result<(), MemoryAccessError> {
+        self.memory
+            .read()
+            .unwrap()
+            .check_write(address, bytes.len() as u32)?;
+        self.memory_accesses
+            .lock()
+            .unwrap()
+            .push(MemoryAccess::Store {
+                address,
+                bytes: bytes.to_vec(),
+            });
+        Ok(())
     }
 
     pub fn eval_ldr_str_op_mem(&self, op_mem: &ArmOpMem) -> u32 {
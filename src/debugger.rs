@@ -0,0 +1,322 @@
+use crate::cpu_state::station::Register;
+use crate::parse_number;
+use crate::registers::ids::*;
+use crate::registers::{ConditionFlag, RegisterFile};
+use crate::simulators::simulation::{Simulation, StopCondition, StopReason};
+use capstone::RegId;
+use std::io::{BufRead, Write};
+
+const HELP: &str = "\
+break <location>      Stop before the instruction at an address, symbol[+offset] or file:line is executed
+tbreak <location>     As break, but only stops once
+delete <location>     Remove a breakpoint
+continue              Run until a breakpoint or the program exits
+step [n]              Retire n instructions (default 1)
+cycle [n]             Advance n clock cycles (default 1)
+regs                  Print the architectural registers
+mem <location> [n]    Dump n bytes of memory (default 64, at most 4096)
+pipeline              Show the decoded instructions and reservation stations
+quit                  Stop the simulation
+";
+
+const MAX_DUMP: u64 = 4096; // Bytes of memory dumped at once
+
+const GENERAL_PURPOSE: [RegId; 13] = [R0, R1, R2, R3, R4, R5, R6, R7, R8, SB, SL, FP, IP];
+
+struct Breakpoint {
+    address: u32,
+    temporary: bool,
+}
+
+// A REPL over a running simulation, for poking around inside the pipeline
+pub struct Debugger {
+    pub simulation: Simulation,
+    breakpoints: Vec<Breakpoint>,
+}
+
+impl Debugger {
+    pub fn new(simulation: Simulation) -> Self {
+        Self {
+            simulation,
+            breakpoints: vec![],
+        }
+    }
+
+    // Read commands until the input is closed or the user quits, then hand the simulation back
+    pub fn run<R: BufRead>(mut self, input: R) -> Simulation {
+        println!(
+            "Stopped at {}, type help for a list of commands",
+            self.describe(self.simulation.pc())
+        );
+        print!("(sim) ");
+        std::io::stdout().flush().unwrap();
+        for line in input.lines() {
+            let line = line.expect("Failed to read command");
+            match line.trim() {
+                "quit" | "q" => break,
+                command => match self.execute(command) {
+                    Ok(output) => print!("{}", output),
+                    Err(e) => println!("{}", e),
+                },
+            }
+            print!("(sim) ");
+            std::io::stdout().flush().unwrap();
+        }
+        self.simulation
+    }
+
+    pub fn execute(&mut self, command: &str) -> Result<String, String> {
+        let mut words = command.split_whitespace();
+        let name = match words.next() {
+            Some(name) => name,
+            None => return Ok(String::new()),
+        };
+        let args: Vec<&str> = words.collect();
+        let count = |i: usize, default: u64| match args.get(i) {
+            Some(n) => parse_number(n).map(|n| n as u64),
+            None => Ok(default),
+        };
+        match name {
+            "help" | "h" => Ok(HELP.to_string()),
+            "break" | "b" | "tbreak" => {
+                let address = self.location(args.first())?;
+                self.breakpoints.retain(|b| b.address != address);
+                self.breakpoints.push(Breakpoint {
+                    address,
+                    temporary: name == "tbreak",
+                });
+                Ok(format!("Breakpoint at {}\n", self.describe(address)))
+            }
+            "delete" | "d" => {
+                let address = self.location(args.first())?;
+                let before = self.breakpoints.len();
+                self.breakpoints.retain(|b| b.address != address);
+                if self.breakpoints.len() == before {
+                    return Err(format!("No breakpoint at {:#010X}", address));
+                }
+                Ok(String::new())
+            }
+            "continue" | "c" => {
+                let conditions: Vec<StopCondition> = self
+                    .breakpoints
+                    .iter()
+                    .map(|b| StopCondition::Address(b.address))
+                    .collect();
+                let reason = self.simulation.run_until(&conditions);
+                Ok(self.stopped(reason))
+            }
+            "step" | "s" => {
+                let target = self.simulation.instructions_retired() + count(0, 1)?;
+                let reason = self
+                    .simulation
+                    .run_until(&[StopCondition::InstructionCount(target)]);
+                Ok(self.stopped(reason))
+            }
+            "cycle" => {
+                let target = self.simulation.stats.total_cycles + count(0, 1)?;
+                let reason = self.simulation.run_until(&[StopCondition::Cycle(target)]);
+                Ok(self.stopped(reason))
+            }
+            "regs" | "r" => Ok(self.registers()),
+            "mem" | "x" => {
+                let address = self.location(args.first())?;
+                self.memory(address, count(1, 64)?.min(MAX_DUMP) as u32)
+            }
+            "pipeline" | "p" => Ok(self.pipeline()),
+            _ => Err(format!("Unknown command {}, type help for a list", name)),
+        }
+    }
+
+    // An address (0x hex or decimal), symbol, symbol+offset or file:line
+    fn location(&self, arg: Option<&&str>) -> Result<u32, String> {
+        let arg = arg.ok_or("Expected an address or symbol")?;
+        match parse_number(arg) {
+            Ok(address) => Ok(address & 0xFFFFFFFE),
+            Err(_) => self
+                .simulation
+                .state
+                .debug_info
+                .location(arg)
+                .ok_or(format!("No symbol or line {}", arg)),
+        }
+    }
+
+    // The address followed by its symbol and source line, when they're known
+    fn describe(&self, address: u32) -> String {
+        match self.simulation.state.debug_info.describe(address) {
+            Some(location) => format!("{:#010X} in {}", address, location),
+            None => format!("{:#010X}", address),
+        }
+    }
+
+    fn stopped(&mut self, reason: StopReason) -> String {
+        let pc = self.simulation.pc();
+        match reason {
+            StopReason::Terminated => match &self.simulation.state.error {
+                Some(error) => error.to_string(),
+                None => "The program has exited\n".to_string(),
+            },
+            StopReason::Condition(StopCondition::Address(address)) => {
+                self.breakpoints
+                    .retain(|b| !(b.temporary && b.address == address));
+                format!("Breakpoint at {}\n", self.describe(pc))
+            }
+            StopReason::Condition(_) => format!(
+                "Stopped at {} after {} cycles\n",
+                self.describe(pc),
+                self.simulation.stats.total_cycles
+            ),
+        }
+    }
+
+    fn registers(&self) -> String {
+        let registers = &self.simulation.state.registers;
+        let mut output = String::new();
+        let special = [SP, LR, PC];
+        for (i, r) in GENERAL_PURPOSE.iter().chain(special.iter()).enumerate() {
+            let mut value = registers.read_by_id(*r);
+            if *r == PC {
+                value = value & 0xFFFFFFFE;
+            }
+            let name = format!("{}:", RegisterFile::reg_name(*r));
+            output.push_str(&format!("{:<4} {:08X}", name, value));
+            output.push(if i % 4 == 3 { '\n' } else { ' ' });
+        }
+        let cpsr = registers.read_by_id(CPSR);
+        let flags = [
+            ('N', ConditionFlag::N),
+            ('Z', ConditionFlag::Z),
+            ('C', ConditionFlag::C),
+            ('V', ConditionFlag::V),
+        ];
+        output.push_str("Flags:");
+        for (name, flag) in &flags {
+            output.push_str(&format!(" {}{}", name, flag.read_flag(cpsr) as u8));
+        }
+        output.push('\n');
+        output
+    }
+
+    // 16 bytes to a line
+    fn memory(&self, address: u32, length: u32) -> Result<String, String> {
+        let memory = self.simulation.state.memory.read().unwrap();
+        let bytes = memory
+            .read_bytes(address, length)
+            .map_err(|e| format!("{:?}", e))?;
+        let mut output = String::new();
+        for (i, line) in bytes.chunks(16).enumerate() {
+            output.push_str(&format!("{:08X}:", address + 16 * i as u32));
+            for byte in line {
+                output.push_str(&format!(" {:02X}", byte));
+            }
+            output.push('\n');
+        }
+        Ok(output)
+    }
+
+    fn pipeline(&self) -> String {
+        let state = &self.simulation.state;
+        let mut output = String::from("Decoded instructions:\n");
+        if state.decoded_instructions.is_empty() {
+            output.push_str("    (empty)\n");
+        }
+        for instr in &state.decoded_instructions {
+            output.push_str(&format!(
+                "    {:08X}  {}\n",
+                instr.address & 0xFFFFFFFE,
+                instr.string
+            ));
+        }
+        output.push_str("Reservation stations:\n");
+        for station in &state.reservation_stations {
+            let unit = match station.unit {
+                Some(unit) => format!(" ({:?})", unit),
+                None => String::new(),
+            };
+            output.push_str(&format!("    {}{}: ", station.id, unit));
+            let instr = match &station.instruction {
+                Some(instr) => instr,
+                None if station.busy > 0 => {
+                    output.push_str(&format!("busy for {} cycles\n", station.busy));
+                    continue;
+                }
+                None => {
+                    output.push_str("free\n");
+                    continue;
+                }
+            };
+            output.push_str(&format!(
+                "{:08X}  {}  (rob {})\n",
+                instr.address & 0xFFFFFFFE,
+                instr.string,
+                station.rob_id
+            ));
+            let mut operands: Vec<_> = station.source_registers.iter().collect();
+            operands.sort_by_key(|(r, _)| r.0);
+            for (r, value) in operands {
+                let value = match value {
+                    Register::Ready(value) => format!("Ready({:#X})", value),
+                    Register::Pending(p, _) => format!("Pending(p{})", p),
+                };
+                output.push_str(&format!(
+                    "        {} = {}\n",
+                    RegisterFile::reg_name(*r),
+                    value
+                ));
+            }
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::tests::sum_program;
+    use crate::symbols::{Symbol, SymbolTable};
+    use crate::{SimulationBuilder, SimulatorType};
+
+    #[test]
+    fn debugger_commands() {
+        let symbols = SymbolTable::new(vec![Symbol {
+            name: "loop".to_string(),
+            address: 0x1004,
+            size: 6,
+        }]);
+        let mut simulation = SimulationBuilder::new(SimulatorType::OutOfOrder)
+            .build(sum_program())
+            .unwrap();
+        simulation.state.debug_info.symbols = symbols;
+        let mut debugger = Debugger::new(simulation);
+        let mut run = |command: &str| debugger.execute(command).unwrap();
+
+        assert_eq!(run("mem 0x1000 4"), "00001000: 00 20 0A 21\n");
+        assert_eq!(run("mem 0X1000 0X2"), "00001000: 00 20\n");
+        assert_eq!(run("tbreak loop"), "Breakpoint at 0x00001004 in loop\n");
+        assert_eq!(run("c"), "Breakpoint at 0x00001004 in loop\n");
+        assert!(run("regs").contains("R1:  0000000A"));
+        let pipeline = run("pipeline");
+        assert!(
+            pipeline.contains("00001004  adds r0, r0, r1"),
+            "{}",
+            pipeline
+        );
+        assert!(pipeline.contains("R1 = Ready(0xA)"), "{}", pipeline);
+
+        // The temporary breakpoint was removed
+        assert_eq!(
+            run("step 2"),
+            "Stopped at 0x00001008 in loop+0x4 after 6 cycles\n"
+        );
+        assert!(run("regs").contains("Flags: N0 Z0 C1 V0"));
+        assert_eq!(run("continue"), "The program has exited\n");
+        assert!(debugger.execute("break nowhere").is_err());
+        assert!(debugger.execute("delete 0x1004").is_err());
+        // Reads past the end of the program, rather than allocating 4 GiB
+        assert!(debugger.execute("mem 0x1000 0xffffffff").is_err());
+        assert_eq!(
+            debugger.execute("b loop+2").unwrap(),
+            "Breakpoint at 0x00001006 in loop+0x2\n"
+        );
+    }
+}
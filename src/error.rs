@@ -0,0 +1,184 @@
+use crate::memory::MemoryAccessError;
+use crate::registers::ids::*;
+use crate::registers::RegisterFile;
+use capstone::RegId;
+use std::fmt::{Display, Formatter};
+
+// Something the guest program did which the simulated CPU can't continue from
+#[derive(Debug, Clone, PartialEq)]
+pub enum Fault {
+    BadFetch(u32),     // No memory to fetch the instruction from
+    InvalidState(u32), // Branched to an address with the Thumb bit clear
+    BadAddress(u32),
+    ReadOnlyWrite(u32),
+    UndefinedInstruction,
+    UnsupportedInstruction(String), // Decoded, but not implemented by the simulator or the M0
+    UnknownSvc(u32),
+    InvalidConfiguration(String), // The simulation can't be built, so no instruction has run
+}
+
+impl From<MemoryAccessError> for Fault {
+    fn from(e: MemoryAccessError) -> Self {
+        match e {
+            MemoryAccessError::BadAddress(address) => Fault::BadAddress(address),
+            MemoryAccessError::ReadOnlyAddress(address) => Fault::ReadOnlyWrite(address),
+            MemoryAccessError::Overlap(address) => Fault::BadAddress(address),
+        }
+    }
+}
+
+impl Display for Fault {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Fault::BadFetch(address) => write!(f, "Fetch from bad address {:#010X}", address),
+            Fault::InvalidState(address) => {
+                write!(f, "Branch to {:#010X} leaves Thumb state", address)
+            }
+            Fault::BadAddress(address) => {
+                write!(f, "Load or store to bad address {:#010X}", address)
+            }
+            Fault::ReadOnlyWrite(address) => {
+                write!(f, "Write to read only address {:#010X}", address)
+            }
+            Fault::UndefinedInstruction => write!(f, "Undefined instruction"),
+            Fault::UnsupportedInstruction(name) => {
+                write!(f, "Unsupported instruction {}", name)
+            }
+            Fault::UnknownSvc(id) => write!(f, "Unknown SVC {}", id),
+            Fault::InvalidConfiguration(reason) => write!(f, "Invalid configuration: {}", reason),
+        }
+    }
+}
+
+const DUMPED: [RegId; 17] = [
+    R0, R1, R2, R3, R4, R5, R6, R7, R8, SB, SL, FP, IP, SP, LR, PC, CPSR,
+];
+
+// A fault raised by an instruction which was committed, with the state of the CPU before it
+#[derive(Debug, Clone)]
+pub struct SimulationError {
+    pub fault: Fault,
+    pub pc: u32,
+    pub disassembly: String,
+    pub location: Option<String>, // Symbol and source line of the PC, when they're known
+    pub registers: Vec<(String, u32)>,
+}
+
+impl SimulationError {
+    pub fn new(fault: Fault, pc: u32, disassembly: &str, registers: &RegisterFile) -> Self {
+        Self {
+            fault,
+            pc,
+            disassembly: disassembly.to_string(),
+            location: None,
+            registers: DUMPED
+                .iter()
+                .map(|r| {
+                    let value = if *r == PC {
+                        pc
+                    } else {
+                        registers.read_by_id(*r)
+                    };
+                    (RegisterFile::reg_name(*r), value)
+                })
+                .collect(),
+        }
+    }
+}
+
+impl Display for SimulationError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at {:#010X}", self.fault, self.pc)?;
+        if let Some(location) = &self.location {
+            write!(f, " in {}", location)?;
+        }
+        writeln!(f, ": {}", self.disassembly)?;
+        for (i, (name, value)) in self.registers.iter().enumerate() {
+            let name = format!("{}:", name);
+            write!(f, "{:<5} {:08X}", name, value)?;
+            if i % 4 == 3 || i == self.registers.len() - 1 {
+                writeln!(f)?;
+            } else {
+                write!(f, " ")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for SimulationError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::tests::program_from;
+    use crate::SimulationBuilder;
+
+    fn run(simulator: &str, code: &[u16]) -> SimulationError {
+        SimulationBuilder::new(simulator.parse().unwrap())
+            .run(program_from(code))
+            .unwrap_err()
+    }
+
+    #[test]
+    fn guest_faults() {
+        for sim in &["scalar", "pipelined", "outoforder", "m0", "fivestage"] {
+            // movs r1, #1; lsls r1, r1, #28; ldr r0, [r1]
+            let error = run(sim, &[0x2101, 0x0709, 0x6808, 0xDF01]);
+            assert_eq!(error.fault, Fault::BadAddress(0x10000000), "{}", sim);
+            assert_eq!(error.pc, 0x1004);
+            assert_eq!(error.disassembly, "ldr r0, [r1]");
+            assert!(error.registers.contains(&("R1".to_string(), 0x10000000)));
+            assert!(error.registers.contains(&("PC".to_string(), 0x1004)));
+
+            // movs r2, #1; lsls r2, r2, #12; str r1, [r2]
+            let error = run(sim, &[0x2201, 0x0312, 0x6011, 0xDF01]);
+            assert_eq!(error.fault, Fault::ReadOnlyWrite(0x1000), "{}", sim);
+
+            // movs r0, #1; lsls r0, r0, #28; adds r0, #1; bx r0
+            let error = run(sim, &[0x2001, 0x0700, 0x3001, 0x4700]);
+            assert_eq!(error.fault, Fault::BadFetch(0x10000000), "{}", sim);
+            assert_eq!(error.pc, 0x10000000);
+
+            // movs r0, #1; lsls r0, r0, #12; bx r0
+            let error = run(sim, &[0x2001, 0x0300, 0x4700, 0xDF01]);
+            assert_eq!(error.fault, Fault::InvalidState(0x1000), "{}", sim);
+            assert_eq!(error.pc, 0x1000);
+
+            let error = run(sim, &[0x2000, 0xDF07]);
+            assert_eq!(error.fault, Fault::UnknownSvc(7), "{}", sim);
+            assert_eq!(error.pc, 0x1002);
+
+            // Accesses at the very top of the address space
+            // movs r1, #0; subs r1, #1; ldr r0, [r1]
+            let error = run(sim, &[0x2100, 0x3901, 0x6808, 0xDF01]);
+            assert_eq!(error.fault, Fault::BadAddress(0xFFFFFFFF), "{}", sim);
+            assert_eq!(error.pc, 0x1004);
+
+            // movs r1, #0; subs r1, #1; str r0, [r1]
+            let error = run(sim, &[0x2100, 0x3901, 0x6008, 0xDF01]);
+            assert_eq!(error.fault, Fault::BadAddress(0xFFFFFFFF), "{}", sim);
+            assert_eq!(error.pc, 0x1004);
+
+            // movs r0, #0; subs r0, #1; bx r0
+            let error = run(sim, &[0x2000, 0x3801, 0x4700, 0xDF01]);
+            assert_eq!(error.fault, Fault::BadFetch(0xFFFFFFFE), "{}", sim);
+            assert_eq!(error.pc, 0xFFFFFFFE);
+        }
+        // add.w r0, r1, r2, lsl #2
+        let error = run("pipelined", &[0xEB01, 0x0082, 0xDF01, 0xBF00]);
+        assert_eq!(
+            error.fault,
+            Fault::UnsupportedInstruction("add with a shifted register".to_string())
+        );
+        let error = run("outoforder", &[0xDE00, 0xDF01]);
+        assert_eq!(error.fault, Fault::UndefinedInstruction);
+        let message = error.to_string();
+        assert!(
+            message.starts_with("Undefined instruction at 0x00001000: udf #0\n"),
+            "{}",
+            message
+        );
+        assert!(message.contains("R0:   00000000 R1:"), "{}", message);
+    }
+}
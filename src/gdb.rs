@@ -0,0 +1,461 @@
+use crate::error::Fault;
+use crate::registers::ids::*;
+use crate::simulators::simulation::{Simulation, StopCondition, StopReason, WatchKind};
+use anyhow::Context;
+use capstone::RegId;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+
+// The registers of an M-profile core, numbered as GDB expects
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+<architecture>arm</architecture>
+<feature name="org.gnu.gdb.arm.m-profile">
+<reg name="r0" bitsize="32"/>
+<reg name="r1" bitsize="32"/>
+<reg name="r2" bitsize="32"/>
+<reg name="r3" bitsize="32"/>
+<reg name="r4" bitsize="32"/>
+<reg name="r5" bitsize="32"/>
+<reg name="r6" bitsize="32"/>
+<reg name="r7" bitsize="32"/>
+<reg name="r8" bitsize="32"/>
+<reg name="r9" bitsize="32"/>
+<reg name="r10" bitsize="32"/>
+<reg name="r11" bitsize="32"/>
+<reg name="r12" bitsize="32"/>
+<reg name="sp" bitsize="32" type="data_ptr"/>
+<reg name="lr" bitsize="32"/>
+<reg name="pc" bitsize="32" type="code_ptr"/>
+<reg name="xpsr" bitsize="32" regnum="25"/>
+</feature>
+</target>
+"#;
+
+const REGISTERS: [RegId; 16] = [
+    R0, R1, R2, R3, R4, R5, R6, R7, R8, SB, SL, FP, IP, SP, LR, PC,
+];
+const XPSR: usize = 25; // After the registers of the FPA, which GDB still numbers
+const THUMB: u32 = 1 << 24; // The T bit of the xPSR, the core is always in Thumb state
+const IPSR: u32 = 0x3F; // The exception number, kept by the exception model rather than in CPSR
+
+// Cycles to run for between checking whether GDB has interrupted
+const CHUNK: u64 = 10000;
+
+// Largest packet GDB may send or expect, replies to memory reads are two hex digits per byte
+const PACKET_SIZE: u32 = 0x4000;
+
+pub enum Reply {
+    Packet(String),
+    Resume { step: bool },
+    Detach,
+}
+
+/*
+A GDB remote serial protocol stub, so that a program running in any of the simulators can be
+debugged with arm-none-eabi-gdb. It is stopped on instruction boundaries, registers and memory
+are the architectural state, and once either has been written every instruction in flight is
+discarded before the simulation continues.
+ */
+pub struct GdbStub {
+    pub simulation: Simulation,
+    breakpoints: Vec<u32>,
+    watchpoints: Vec<StopCondition>,
+    modified: bool, // Registers or memory were written while stopped
+}
+
+impl GdbStub {
+    pub fn new(simulation: Simulation) -> Self {
+        Self {
+            simulation,
+            breakpoints: vec![],
+            watchpoints: vec![],
+            modified: false,
+        }
+    }
+
+    // Wait for GDB to connect on localhost, then serve it until it detaches or the program exits
+    pub fn serve(mut self, port: u16) -> anyhow::Result<Simulation> {
+        let listener = TcpListener::bind(("127.0.0.1", port))
+            .with_context(|| format!("Failed to listen on port {}", port))?;
+        println!("Waiting for gdb on localhost:{}", port);
+        let (stream, address) = listener.accept()?;
+        println!("gdb connected from {}", address);
+        let mut connection = Connection { stream };
+        while let Some(packet) = connection.receive()? {
+            match self.handle(&packet) {
+                Reply::Packet(reply) => connection.send(&reply)?,
+                Reply::Resume { step } => {
+                    let reply = self.resume(step, || connection.interrupted());
+                    connection.send(&reply)?;
+                }
+                Reply::Detach => {
+                    connection.send("OK")?;
+                    break;
+                }
+            }
+        }
+        Ok(self.simulation)
+    }
+
+    pub fn handle(&mut self, packet: &str) -> Reply {
+        let reply = |s: &str| Reply::Packet(s.to_string());
+        let (command, args) = packet.split_at(packet.len().min(1));
+        match command {
+            "?" => reply("S05"),
+            "q" if args.starts_with("Supported") => {
+                Reply::Packet(format!("PacketSize={:x};qXfer:features:read+", PACKET_SIZE))
+            }
+            "q" if args.starts_with("Xfer:features:read:target.xml:") => {
+                let range = &args["Xfer:features:read:target.xml:".len()..];
+                Reply::Packet(self.read_target_xml(range).unwrap_or_else(|| "E01".into()))
+            }
+            "q" if args == "Attached" => reply("1"),
+            "H" => reply("OK"),
+            "g" => {
+                let mut values: Vec<u32> = (0..REGISTERS.len())
+                    .map(|i| self.read_register(i))
+                    .collect();
+                values.push(self.read_register(XPSR));
+                Reply::Packet(values.iter().map(|v| hex(&v.to_le_bytes())).collect())
+            }
+            "G" => {
+                let values = match parse_hex(args) {
+                    Some(bytes) if bytes.len() == 4 * (REGISTERS.len() + 1) => bytes,
+                    _ => return reply("E01"),
+                };
+                for (i, value) in values.chunks(4).enumerate() {
+                    let value = u32::from_le_bytes([value[0], value[1], value[2], value[3]]);
+                    let n = if i < REGISTERS.len() { i } else { XPSR };
+                    self.write_register(n, value);
+                }
+                reply("OK")
+            }
+            "p" => match usize::from_str_radix(args, 16) {
+                Ok(n) if n < REGISTERS.len() || n == XPSR => {
+                    Reply::Packet(hex(&self.read_register(n).to_le_bytes()))
+                }
+                _ => reply("E01"),
+            },
+            "P" => {
+                let mut parts = args.splitn(2, '=');
+                let n = parts.next().and_then(|n| usize::from_str_radix(n, 16).ok());
+                let value = parts.next().and_then(parse_hex);
+                match (n, value) {
+                    (Some(n), Some(v)) if (n < REGISTERS.len() || n == XPSR) && v.len() == 4 => {
+                        self.write_register(n, u32::from_le_bytes([v[0], v[1], v[2], v[3]]));
+                        reply("OK")
+                    }
+                    _ => reply("E01"),
+                }
+            }
+            "m" => {
+                let memory = self.simulation.state.memory.read().unwrap();
+                let range = parse_range(args).map(|(a, l)| (a, l.min(PACKET_SIZE / 2)));
+                match range.map(|(a, l)| memory.read_bytes(a, l)) {
+                    Some(Ok(bytes)) => Reply::Packet(hex(&bytes)),
+                    _ => reply("E01"),
+                }
+            }
+            "M" => {
+                let mut parts = args.splitn(2, ':');
+                let range = parts.next().and_then(parse_range);
+                let bytes = parts.next().and_then(parse_hex);
+                match (range, bytes) {
+                    (Some((address, length)), Some(bytes)) if bytes.len() == length as usize => {
+                        let mut memory = self.simulation.state.memory.write().unwrap();
+                        match memory.force_write_bytes(address, &bytes) {
+                            Ok(_) => {
+                                self.modified = true;
+                                reply("OK")
+                            }
+                            Err(_) => reply("E01"),
+                        }
+                    }
+                    _ => reply("E01"),
+                }
+            }
+            "c" | "s" => {
+                // Optionally resume from a different address
+                if let Ok(address) = u32::from_str_radix(args, 16) {
+                    self.write_register(15, address);
+                }
+                Reply::Resume {
+                    step: command == "s",
+                }
+            }
+            "Z" | "z" => self.breakpoint(command == "Z", args),
+            "D" | "k" => Reply::Detach,
+            _ => reply(""),
+        }
+    }
+
+    // Format: type,address,kind
+    fn breakpoint(&mut self, insert: bool, args: &str) -> Reply {
+        let mut parts = args.split(',');
+        let kind = parts.next().unwrap_or("");
+        let address = parts.next().and_then(|a| u32::from_str_radix(a, 16).ok());
+        let length = parts.next().and_then(|l| u32::from_str_radix(l, 16).ok());
+        let (address, length) = match (address, length) {
+            (Some(address), Some(length)) => (address, length),
+            _ => return Reply::Packet("E01".into()),
+        };
+        let watch = |kind| StopCondition::Watch {
+            kind,
+            address,
+            length,
+        };
+        let condition = match kind {
+            // Hardware breakpoints are no different in a simulator
+            "0" | "1" => {
+                let address = address & 0xFFFFFFFE;
+                self.breakpoints.retain(|b| *b != address);
+                if insert {
+                    self.breakpoints.push(address);
+                }
+                return Reply::Packet("OK".into());
+            }
+            "2" => watch(WatchKind::Write),
+            "3" => watch(WatchKind::Read),
+            "4" => watch(WatchKind::Access),
+            _ => return Reply::Packet("".into()),
+        };
+        self.watchpoints.retain(|w| *w != condition);
+        if insert {
+            self.watchpoints.push(condition);
+        }
+        Reply::Packet("OK".into())
+    }
+
+    // Run until a breakpoint or watchpoint is hit, the program exits or GDB interrupts
+    pub fn resume<F: FnMut() -> bool>(&mut self, step: bool, mut interrupted: F) -> String {
+        if self.modified {
+            self.simulation.state.restart();
+            self.modified = false;
+        }
+        let mut conditions: Vec<StopCondition> = self
+            .breakpoints
+            .iter()
+            .map(|b| StopCondition::Address(*b))
+            .chain(self.watchpoints.iter().copied())
+            .collect();
+        conditions.push(StopCondition::Cycle(0));
+        let chunk = conditions.len() - 1;
+        loop {
+            let reason = if step {
+                self.simulation.step_instruction()
+            } else {
+                conditions[chunk] =
+                    StopCondition::Cycle(self.simulation.stats.total_cycles + CHUNK);
+                self.simulation.run_until(&conditions)
+            };
+            return match reason {
+                // Stopped at the faulting instruction, so the state can still be inspected
+                StopReason::Terminated => match &self.simulation.state.error {
+                    Some(error) => match error.fault {
+                        Fault::UnknownSvc(_) => "S1f".to_string(), // SIGSYS
+                        Fault::UndefinedInstruction | Fault::UnsupportedInstruction(_) => {
+                            "S04".to_string() // SIGILL
+                        }
+                        _ => "S0b".to_string(), // SIGSEGV
+                    },
+                    None => {
+                        let code = self.simulation.state.registers.read_by_id(R0);
+                        format!("W{:02x}", code & 0xFF)
+                    }
+                },
+                StopReason::Condition(StopCondition::Cycle(_)) if !step => {
+                    if interrupted() {
+                        "S02".to_string()
+                    } else {
+                        continue;
+                    }
+                }
+                StopReason::Condition(StopCondition::Watch { kind, address, .. }) => {
+                    let name = match kind {
+                        WatchKind::Write => "watch",
+                        WatchKind::Read => "rwatch",
+                        WatchKind::Access => "awatch",
+                    };
+                    format!("T05{}:{:x};", name, address)
+                }
+                StopReason::Condition(_) => "S05".to_string(),
+            };
+        }
+    }
+
+    fn read_register(&self, n: usize) -> u32 {
+        let registers = &self.simulation.state.registers;
+        match n {
+            XPSR => {
+                let ipsr = self
+                    .simulation
+                    .state
+                    .exceptions
+                    .as_ref()
+                    .map_or(0, |e| e.ipsr());
+                registers.read_by_id(CPSR) | THUMB | ipsr
+            }
+            15 => registers.read_by_id(PC) & 0xFFFFFFFE,
+            _ => registers.read_by_id(REGISTERS[n]),
+        }
+    }
+
+    fn write_register(&mut self, n: usize, value: u32) {
+        let registers = &mut self.simulation.state.registers;
+        match n {
+            XPSR => registers.write_by_id(CPSR, value & !(THUMB | IPSR)),
+            15 => registers.write_by_id(PC, value | 1),
+            _ => registers.write_by_id(REGISTERS[n], value),
+        }
+        self.modified = true;
+    }
+
+    // Format: offset,length
+    fn read_target_xml(&self, range: &str) -> Option<String> {
+        let (offset, length) = parse_range(range)?;
+        let offset = (offset as usize).min(TARGET_XML.len());
+        let end = (offset + length as usize).min(TARGET_XML.len());
+        let more = if end < TARGET_XML.len() { "m" } else { "l" };
+        Some(format!("{}{}", more, &TARGET_XML[offset..end]))
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn parse_hex(s: &str) -> Option<Vec<u8>> {
+    s.as_bytes()
+        .chunks(2)
+        .map(|pair| match pair {
+            [_, _] => u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok(),
+            _ => None,
+        })
+        .collect()
+}
+
+// Format: address,length
+fn parse_range(s: &str) -> Option<(u32, u32)> {
+    let mut parts = s.splitn(2, ',');
+    let address = u32::from_str_radix(parts.next()?, 16).ok()?;
+    let length = u32::from_str_radix(parts.next()?, 16).ok()?;
+    Some((address, length))
+}
+
+// $data#checksum
+fn packet(data: &str) -> String {
+    let checksum = data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+    format!("${}#{:02x}", data, checksum)
+}
+
+struct Connection {
+    stream: TcpStream,
+}
+
+impl Connection {
+    fn read_byte(&mut self) -> std::io::Result<Option<u8>> {
+        let mut byte = [0];
+        match self.stream.read(&mut byte)? {
+            0 => Ok(None),
+            _ => Ok(Some(byte[0])),
+        }
+    }
+
+    // The next packet, acknowledging it. None once GDB has disconnected
+    fn receive(&mut self) -> std::io::Result<Option<String>> {
+        // Skip acknowledgements, and interrupts that arrived after the target had already stopped
+        loop {
+            match self.read_byte()? {
+                None => return Ok(None),
+                Some(b'$') => break,
+                Some(_) => {}
+            }
+        }
+        let mut data = vec![];
+        loop {
+            match self.read_byte()? {
+                None => return Ok(None),
+                Some(b'#') => break,
+                Some(b) => data.push(b),
+            }
+        }
+        let mut checksum = [0; 2];
+        self.stream.read_exact(&mut checksum)?;
+        self.stream.write_all(b"+")?;
+        Ok(Some(String::from_utf8_lossy(&data).to_string()))
+    }
+
+    fn send(&mut self, data: &str) -> std::io::Result<()> {
+        self.stream.write_all(packet(data).as_bytes())
+    }
+
+    // GDB sends a single 0x03 byte to stop a running target
+    fn interrupted(&mut self) -> bool {
+        let mut byte = [0];
+        self.stream.set_nonblocking(true).unwrap();
+        let read = self.stream.read(&mut byte);
+        self.stream.set_nonblocking(false).unwrap();
+        match read {
+            Ok(1) => byte[0] == 0x03,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => false,
+            _ => true, // Disconnected
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::tests::{memory_program, sum_program};
+    use crate::SimulationBuilder;
+
+    fn send(stub: &mut GdbStub, packet: &str) -> String {
+        match stub.handle(packet) {
+            Reply::Packet(reply) => reply,
+            Reply::Resume { step } => stub.resume(step, || false),
+            Reply::Detach => "detached".to_string(),
+        }
+    }
+
+    #[test]
+    fn gdb_stub() {
+        assert_eq!(packet("OK"), "$OK#9a");
+        for sim in &["scalar", "pipelined", "outoforder", "m0", "fivestage"] {
+            let builder = SimulationBuilder::new(sim.parse().unwrap());
+            let mut stub = GdbStub::new(builder.build(sum_program()).unwrap());
+            let registers = send(&mut stub, "g");
+            assert_eq!(registers.len(), 17 * 8);
+            // pc and the T bit of xpsr
+            assert_eq!(&registers[15 * 8..], "0010000000000001");
+            assert_eq!(send(&mut stub, "m1000,4"), "00200a21");
+
+            // Stop at adds r0, r0, r1 then make it the last iteration
+            assert_eq!(send(&mut stub, "Z0,1004,2"), "OK");
+            assert_eq!(send(&mut stub, "c"), "S05", "{}", sim);
+            assert_eq!(send(&mut stub, "pf"), "04100000");
+            assert_eq!(send(&mut stub, "p1"), "0a000000");
+            assert_eq!(send(&mut stub, "s"), "S05");
+            assert_eq!(send(&mut stub, "p0"), "0a000000");
+            assert_eq!(send(&mut stub, "P1=01000000"), "OK");
+            assert_eq!(send(&mut stub, "z0,1004,2"), "OK");
+            assert_eq!(send(&mut stub, "c"), "W0a", "{}", sim);
+
+            // The str of the first iteration
+            let mut stub = GdbStub::new(builder.build(memory_program()).unwrap());
+            let watch = format!("{:x}", crate::DEFAULT_STACK_TOP - 4);
+            assert_eq!(send(&mut stub, &format!("Z2,{},4", watch)), "OK");
+            let reply = send(&mut stub, "c");
+            assert_eq!(reply, format!("T05watch:{};", watch), "{}", sim);
+            assert_eq!(send(&mut stub, "pf"), "0a100000");
+            assert_eq!(send(&mut stub, &format!("m{},4", watch)), "0a000000");
+
+            // Ranges that run past the top of the address space
+            let mut stub = GdbStub::new(builder.build(memory_program()).unwrap());
+            assert_eq!(send(&mut stub, "m0,ffffffff"), "E01");
+            assert_eq!(send(&mut stub, "Z2,fffffffc,8"), "OK");
+            assert_eq!(send(&mut stub, "c"), "W37", "{}", sim);
+        }
+    }
+}
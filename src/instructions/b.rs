@@ -1,7 +1,7 @@
 use super::Instruction;
 use crate::cpu_state::station::ReservationStation;
 use crate::instructions::util::ArmOperandExt;
-use crate::instructions::PollResult;
+use crate::instructions::{InstructionClass, PollResult};
 use crate::registers::ids::{LR, PC};
 use capstone::arch::arm::ArmOperand;
 use capstone::RegId;
@@ -54,4 +54,12 @@ impl Instruction for B {
         }
         dest
     }
+
+    fn class(&self) -> InstructionClass {
+        InstructionClass::Branch
+    }
+
+    fn is_call(&self) -> bool {
+        self.with_link
+    }
 }
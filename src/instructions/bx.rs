@@ -1,7 +1,7 @@
 use super::Instruction;
 use crate::cpu_state::station::ReservationStation;
 use crate::instructions::util::ArmOperandExt;
-use crate::instructions::PollResult;
+use crate::instructions::{InstructionClass, PollResult};
 use crate::registers::ids::{LR, PC};
 use capstone::arch::arm::ArmOperand;
 use capstone::prelude::*;
@@ -53,4 +53,16 @@ impl Instruction for BX {
         }
         dest
     }
+
+    fn class(&self) -> InstructionClass {
+        InstructionClass::Branch
+    }
+
+    fn is_call(&self) -> bool {
+        self.with_link
+    }
+
+    fn is_return(&self) -> bool {
+        !self.with_link && self.register == LR
+    }
 }
@@ -1,7 +1,7 @@
 use super::Instruction;
 use crate::cpu_state::station::ReservationStation;
 use crate::instructions::util::ArmOperandExt;
-use crate::instructions::PollResult;
+use crate::instructions::{InstructionClass, PollResult};
 use capstone::arch::arm::ArmOperand;
 use capstone::prelude::*;
 use std::collections::{HashSet, VecDeque};
@@ -14,6 +14,7 @@ pub struct LDM {
     writeback: bool,
     address: Option<u32>,
     changes: Vec<(RegId, u32)>,
+    stall: u32, // Cycles still waiting for memory
 }
 
 impl LDM {
@@ -28,6 +29,7 @@ impl LDM {
             writeback,
             address: None,
             changes: vec![],
+            stall: 0,
         }
     }
 }
@@ -36,17 +38,20 @@ impl Instruction for LDM {
     // https://keleshev.com/ldm-my-favorite-arm-instruction/
     fn poll(&self, station: &ReservationStation) -> PollResult {
         let mut clone = self.clone();
+        if clone.stall > 0 {
+            clone.stall = clone.stall - 1;
+            return PollResult::Again(Box::new(clone));
+        }
         if let None = clone.address {
             clone.address = Some(station.read_by_id(self.base_register));
         }
 
         if let Some(reg) = clone.reg_list.pop_front() {
-            let val = station
-                .memory
-                .read()
-                .unwrap()
-                .read_u32(clone.address.unwrap())
-                .unwrap();
+            let val = match station.read_u32(clone.address.unwrap()) {
+                Ok(val) => val,
+                Err(e) => return PollResult::Fault(e.into()),
+            };
+            clone.stall = station.load_latency(clone.address.unwrap(), 4) - 1;
             clone.changes.push((reg, val));
             clone.address = Some(clone.address.unwrap() + 4);
             return PollResult::Again(Box::new(clone));
@@ -74,4 +79,16 @@ impl Instruction for LDM {
         }
         list
     }
+
+    fn class(&self) -> InstructionClass {
+        InstructionClass::LoadStore
+    }
+
+    fn transfers(&self) -> u32 {
+        self.reg_list.len() as u32
+    }
+
+    fn reads_memory(&self) -> bool {
+        true
+    }
 }
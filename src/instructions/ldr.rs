@@ -2,7 +2,7 @@ use super::Instruction;
 use crate::cpu_state::station::ReservationStation;
 use crate::instructions::util::ArmOperandExt;
 use crate::instructions::util::RegisterSet;
-use crate::instructions::PollResult;
+use crate::instructions::{InstructionClass, PollResult};
 use capstone::arch::arm::{ArmOpMem, ArmOperand};
 use capstone::prelude::*;
 use std::collections::HashSet;
@@ -16,12 +16,24 @@ pub enum Mode {
     SignedByte,
 }
 
+impl Mode {
+    fn length(&self) -> u32 {
+        match self {
+            Mode::Word => 4,
+            Mode::HalfWord | Mode::SignedHalfWord => 2,
+            Mode::Byte | Mode::SignedByte => 1,
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct LDR {
     reg: RegId,
     mem: ArmOpMem,
     mode: Mode,
-    waited: bool,
+    waited: u32,
+    value: Option<u32>,
+    remaining: u32, // Cycles until the value arrives from memory
 }
 
 impl LDR {
@@ -30,33 +42,52 @@ impl LDR {
             reg: operands[0].reg_id().unwrap(),
             mem: operands[1].op_mem_value().unwrap(),
             mode,
-            waited: false,
+            waited: 0,
+            value: None,
+            remaining: 0,
         }
     }
 }
 
 impl Instruction for LDR {
     fn poll(&self, station: &ReservationStation) -> PollResult {
-        if !self.waited {
-            // Takes 2 cycles
+        if let Some(value) = self.value {
+            if self.remaining <= 1 {
+                return PollResult::Complete(vec![(self.reg, value)]);
+            }
+            let mut cloned = self.clone();
+            cloned.remaining = cloned.remaining - 1;
+            return PollResult::Again(Box::new(cloned));
+        }
+
+        if self.waited < station.latencies.address {
+            // Calculate the address, then wait however long memory takes to respond
             let mut cloned = self.clone();
-            cloned.waited = true;
+            cloned.waited = cloned.waited + 1;
             return PollResult::Again(Box::new(cloned));
         }
 
         let mem_addr = station.eval_ldr_str_op_mem(&self.mem);
         let val_at_addr = match self.mode {
-            Mode::Word => station.memory.read().unwrap().read_u32(mem_addr).unwrap(),
-            Mode::HalfWord => station.memory.read().unwrap().read_u16(mem_addr).unwrap() as u32,
-            Mode::Byte => station.memory.read().unwrap().read_byte(mem_addr).unwrap() as u32,
-            Mode::SignedHalfWord => {
-                station.memory.read().unwrap().read_u16(mem_addr).unwrap() as i32 as u32
-            }
-            Mode::SignedByte => {
-                station.memory.read().unwrap().read_byte(mem_addr).unwrap() as i32 as u32
-            }
+            Mode::Word => station.read_u32(mem_addr),
+            Mode::HalfWord => station.read_u16(mem_addr).map(|v| v as u32),
+            Mode::Byte => station.read_byte(mem_addr).map(|v| v as u32),
+            Mode::SignedHalfWord => station.read_u16(mem_addr).map(|v| v as i32 as u32),
+            Mode::SignedByte => station.read_byte(mem_addr).map(|v| v as i32 as u32),
         };
-        PollResult::Complete(vec![(self.reg, val_at_addr)])
+        match val_at_addr {
+            Ok(val_at_addr) => {
+                let latency = station.load_latency(mem_addr, self.mode.length());
+                if latency <= 1 {
+                    return PollResult::Complete(vec![(self.reg, val_at_addr)]);
+                }
+                let mut cloned = self.clone();
+                cloned.value = Some(val_at_addr);
+                cloned.remaining = latency - 1;
+                PollResult::Again(Box::new(cloned))
+            }
+            Err(e) => PollResult::Fault(e.into()),
+        }
     }
 
     fn source_registers(&self) -> HashSet<RegId> {
@@ -66,4 +97,12 @@ impl Instruction for LDR {
     fn dest_registers(&self) -> HashSet<RegId> {
         hashset![self.reg]
     }
+
+    fn class(&self) -> InstructionClass {
+        InstructionClass::LoadStore
+    }
+
+    fn reads_memory(&self) -> bool {
+        true
+    }
 }
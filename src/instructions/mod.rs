@@ -20,17 +20,63 @@ mod tst;
 mod util;
 
 use crate::cpu_state::station::ReservationStation;
-use crate::registers::ids::PC;
-use capstone::arch::arm::{ArmInsnDetail, ArmOperand};
+use crate::error::Fault;
+use capstone::arch::arm::{ArmInsnDetail, ArmOperand, ArmOperandType, ArmShift};
 use capstone::RegId;
+use serde::Serialize;
 use std::collections::HashSet;
 use std::fmt::Debug;
 
+// Cycles taken by each class of instruction once it has its operands
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Latencies {
+    pub alu: u32,
+    pub multiply: u32,
+    pub branch: u32,
+    pub load_store: u32, // After the memory access has completed
+    pub address: u32,    // Cycles LDR / STR spend calculating the address before accessing memory
+}
+
+impl Latencies {
+    // Cycles the functional unit takes to produce the result
+    pub fn of(&self, class: InstructionClass) -> u32 {
+        match class {
+            InstructionClass::Alu => self.alu,
+            InstructionClass::Multiply => self.multiply,
+            InstructionClass::Branch => self.branch,
+            InstructionClass::LoadStore => self.load_store,
+        }
+    }
+}
+
+impl Default for Latencies {
+    fn default() -> Self {
+        Self {
+            alu: 1,
+            multiply: 8,
+            branch: 1,
+            load_store: 1,
+            address: 1,
+        }
+    }
+}
+
+// The kind of functional unit an instruction is executed by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum InstructionClass {
+    Alu,
+    Multiply,
+    Branch,
+    LoadStore,
+}
+
 #[derive(Debug)]
 pub enum PollResult {
     Complete(Vec<(RegId, u32)>),
     Again(Box<dyn Instruction>),
     Exception,
+    // The instruction may be on a mispredicted path, only raised if it is committed
+    Fault(Fault),
 }
 
 pub trait Instruction: Send + Sync + Debug {
@@ -40,8 +86,42 @@ pub trait Instruction: Send + Sync + Debug {
 
     fn dest_registers(&self) -> HashSet<RegId>;
 
-    fn hazardous(&self) -> bool {
-        self.dest_registers().contains(&PC)
+    fn class(&self) -> InstructionClass {
+        InstructionClass::Alu
+    }
+
+    // Registers loaded or stored, only valid before the instruction starts executing
+    fn transfers(&self) -> u32 {
+        1
+    }
+
+    // Has side effects that cannot be undone, so must wait until it is the oldest instruction
+    fn non_speculative(&self) -> bool {
+        false
+    }
+
+    // Enters the SVCall handler when the program has a vector table
+    fn is_supervisor_call(&self) -> bool {
+        false
+    }
+
+    // Memory instructions are tracked by the load/store queue
+    fn reads_memory(&self) -> bool {
+        false
+    }
+
+    fn writes_memory(&self) -> bool {
+        false
+    }
+
+    // Branch with link, the return address is saved in LR
+    fn is_call(&self) -> bool {
+        false
+    }
+
+    // Branch to the return address of a function
+    fn is_return(&self) -> bool {
+        false
     }
 }
 
@@ -49,6 +129,7 @@ pub trait Instruction: Send + Sync + Debug {
 pub enum DecodeError {
     Unimplemented(String),
     UnsupportedInCortexM0(String),
+    Undefined, // Permanently undefined, such as udf
 }
 
 /*
@@ -62,7 +143,11 @@ pub fn decode_instruction(
 ) -> Result<Box<dyn Instruction>, DecodeError> {
     let update_flags = detail.update_flags();
     let writeback = detail.writeback();
-    return Ok(match name.to_ascii_uppercase().as_str() {
+    let upper = name.to_ascii_uppercase();
+    if FLEXIBLE_SECOND_OPERAND.contains(&upper.as_str()) {
+        check_second_operand(name, &operands)?;
+    }
+    return Ok(match upper.as_str() {
         "ADC" => Box::new(add::ADD::new(operands, update_flags, add::Mode::ADC)),
         "ADD" => Box::new(add::ADD::new(operands, update_flags, add::Mode::ADD)),
         "ADR" => Box::new(adr::ADR::new(operands)),
@@ -114,6 +199,7 @@ pub fn decode_instruction(
         "SXTB" => Box::new(extends::EXTENDS::new(operands, extends::Mode::SXTB)),
         "SXTH" => Box::new(extends::EXTENDS::new(operands, extends::Mode::SXTH)),
         "TST" => Box::new(tst::TST::new(operands)),
+        "UDF" => return Err(DecodeError::Undefined),
         "UXTB" => Box::new(extends::EXTENDS::new(operands, extends::Mode::UXTB)),
         "UXTH" => Box::new(extends::EXTENDS::new(operands, extends::Mode::UXTH)),
         "WFE" => return Err(DecodeError::Unimplemented(name.to_owned())),
@@ -122,3 +208,25 @@ pub fn decode_instruction(
         _ => return Err(DecodeError::UnsupportedInCortexM0(name.to_owned())),
     });
 }
+
+const FLEXIBLE_SECOND_OPERAND: [&str; 14] = [
+    "ADC", "ADD", "ASR", "CMN", "CMP", "LSL", "LSR", "MOV", "MVN", "ROR", "RSB", "SBC", "SUB",
+    "TST",
+];
+
+// The M0 only has registers and immediates for these, Thumb-2 can also shift the register
+fn check_second_operand(name: &str, operands: &[ArmOperand]) -> Result<(), DecodeError> {
+    match operands.last().map(|op| (&op.op_type, op.shift)) {
+        Some((ArmOperandType::Reg(_), ArmShift::Invalid)) | Some((ArmOperandType::Imm(_), _)) => {
+            Ok(())
+        }
+        Some((ArmOperandType::Reg(_), _)) => Err(DecodeError::UnsupportedInCortexM0(format!(
+            "{} with a shifted register",
+            name
+        ))),
+        _ => Err(DecodeError::Unimplemented(format!(
+            "{} with this operand type",
+            name
+        ))),
+    }
+}
@@ -2,7 +2,7 @@ use super::Instruction;
 use crate::cpu_state::station::ReservationStation;
 use crate::instructions::util::ArmOperandExt;
 use crate::instructions::PollResult;
-use crate::registers::ids::{CPSR, PC};
+use crate::registers::ids::{CPSR, LR, PC};
 use crate::registers::ConditionFlag;
 use capstone::arch::arm::{ArmOperand, ArmOperandType};
 use capstone::prelude::*;
@@ -71,4 +71,8 @@ impl Instruction for MOV {
         }
         dest
     }
+
+    fn is_return(&self) -> bool {
+        self.dest == PC && self.src.reg_id() == Some(LR)
+    }
 }
@@ -1,42 +1,29 @@
 use super::Instruction;
 use crate::cpu_state::station::ReservationStation;
 use crate::instructions::util::ArmOperandExt;
-use crate::instructions::PollResult;
+use crate::instructions::{InstructionClass, PollResult};
 use crate::registers::ids::CPSR;
 use crate::registers::ConditionFlag;
 use capstone::arch::arm::ArmOperand;
 use capstone::prelude::*;
 use std::collections::HashSet;
 
-// Make the Multiply require extra cycles to complete
-const EXTRA_CYCLES: u8 = 7;
-
 #[derive(Clone, Debug)]
 pub struct MUL {
     dest: RegId,
     val: RegId,
-    cycles: u8,
 }
 
 impl MUL {
     pub fn new(operands: Vec<ArmOperand>) -> Self {
         let dest = operands[0].reg_id().unwrap();
         let val = operands[1].reg_id().unwrap();
-        return Self {
-            dest,
-            val,
-            cycles: 0,
-        };
+        Self { dest, val }
     }
 }
 
 impl Instruction for MUL {
     fn poll(&self, station: &ReservationStation) -> PollResult {
-        if self.cycles < EXTRA_CYCLES {
-            let mut cloned = self.clone();
-            cloned.cycles = cloned.cycles + 1;
-            return PollResult::Again(Box::new(cloned));
-        }
         let dest_val = station.read_by_id(self.dest);
         let sec_val = station.read_by_id(self.val);
         let (result, unsigned_overflow) = dest_val.overflowing_mul(sec_val);
@@ -58,4 +45,9 @@ impl Instruction for MUL {
     fn dest_registers(&self) -> HashSet<RegId> {
         hashset![self.dest, CPSR]
     }
+
+    // Takes multiple cycles in the multiplier, see Latencies
+    fn class(&self) -> InstructionClass {
+        InstructionClass::Multiply
+    }
 }
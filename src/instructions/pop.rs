@@ -1,8 +1,8 @@
 use super::Instruction;
 use crate::cpu_state::station::ReservationStation;
 use crate::instructions::util::ArmOperandExt;
-use crate::instructions::PollResult;
-use crate::registers::ids::SP;
+use crate::instructions::{InstructionClass, PollResult};
+use crate::registers::ids::{PC, SP};
 use crate::registers::RegisterFile;
 use capstone::arch::arm::ArmOperand;
 use capstone::prelude::*;
@@ -14,6 +14,7 @@ pub struct POP {
     reg_list: VecDeque<RegId>,
     sp: Option<u32>,
     changes: Vec<(RegId, u32)>,
+    stall: u32, // Cycles still waiting for memory
 }
 
 impl POP {
@@ -27,6 +28,7 @@ impl POP {
             reg_list: VecDeque::from(reg_list),
             sp: None,
             changes: vec![],
+            stall: 0,
         }
     }
 }
@@ -34,20 +36,23 @@ impl POP {
 impl Instruction for POP {
     fn poll(&self, station: &ReservationStation) -> PollResult {
         let mut clone = self.clone();
+        if clone.stall > 0 {
+            clone.stall = clone.stall - 1;
+            return PollResult::Again(Box::new(clone));
+        }
         if let None = clone.sp {
             clone.sp = Some(station.read_by_id(SP));
         }
         if let Some(r) = clone.reg_list.pop_front() {
-            let read_from_stack = station
-                .memory
-                .read()
-                .unwrap()
-                .read_u32(clone.sp.unwrap())
-                .unwrap();
+            let read_from_stack = match station.read_u32(clone.sp.unwrap()) {
+                Ok(val) => val,
+                Err(e) => return PollResult::Fault(e.into()),
+            };
+            clone.stall = station.load_latency(clone.sp.unwrap(), 4) - 1;
             clone.changes.push((r, read_from_stack));
             clone.sp = Some(clone.sp.unwrap() + 4);
         }
-        if clone.reg_list.is_empty() {
+        if clone.reg_list.is_empty() && clone.stall == 0 {
             clone.changes.push((SP, clone.sp.unwrap()));
             PollResult::Complete(clone.changes)
         } else {
@@ -64,4 +69,20 @@ impl Instruction for POP {
         list.insert(SP);
         list
     }
+
+    fn class(&self) -> InstructionClass {
+        InstructionClass::LoadStore
+    }
+
+    fn transfers(&self) -> u32 {
+        self.reg_list.len() as u32
+    }
+
+    fn reads_memory(&self) -> bool {
+        true
+    }
+
+    fn is_return(&self) -> bool {
+        self.reg_list.contains(&PC)
+    }
 }
@@ -1,7 +1,7 @@
 use super::Instruction;
 use crate::cpu_state::station::ReservationStation;
 use crate::instructions::util::ArmOperandExt;
-use crate::instructions::PollResult;
+use crate::instructions::{InstructionClass, PollResult};
 use crate::registers::ids::SP;
 use crate::registers::RegisterFile;
 use capstone::arch::arm::ArmOperand;
@@ -38,12 +38,9 @@ impl Instruction for PUSH {
         if let Some(r) = clone.reg_list.pop_front() {
             clone.sp = Some(clone.sp.unwrap() - 4);
             let register_value = station.read_by_id(r).to_le_bytes();
-            station
-                .memory
-                .write()
-                .unwrap()
-                .write_bytes(clone.sp.unwrap(), &register_value)
-                .unwrap();
+            if let Err(e) = station.write_bytes(clone.sp.unwrap(), &register_value) {
+                return PollResult::Fault(e.into());
+            }
         }
         if clone.reg_list.is_empty() {
             PollResult::Complete(vec![(SP, clone.sp.unwrap())])
@@ -64,7 +61,15 @@ impl Instruction for PUSH {
         hashset![SP]
     }
 
-    fn hazardous(&self) -> bool {
-        true // Not quite sure why push causes issues with svc?
+    fn class(&self) -> InstructionClass {
+        InstructionClass::LoadStore
+    }
+
+    fn transfers(&self) -> u32 {
+        self.reg_list.len() as u32
+    }
+
+    fn writes_memory(&self) -> bool {
+        true
     }
 }
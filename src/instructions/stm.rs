@@ -1,7 +1,7 @@
 use super::Instruction;
 use crate::cpu_state::station::ReservationStation;
 use crate::instructions::util::ArmOperandExt;
-use crate::instructions::PollResult;
+use crate::instructions::{InstructionClass, PollResult};
 use capstone::arch::arm::ArmOperand;
 use capstone::prelude::*;
 use std::collections::{HashSet, VecDeque};
@@ -41,12 +41,9 @@ impl Instruction for STM {
 
         if let Some(reg) = clone.reg_list.pop_front() {
             let reg_val = station.read_by_id(reg);
-            station
-                .memory
-                .write()
-                .unwrap()
-                .write_bytes(clone.address.unwrap(), &reg_val.to_le_bytes())
-                .unwrap();
+            if let Err(e) = station.write_bytes(clone.address.unwrap(), &reg_val.to_le_bytes()) {
+                return PollResult::Fault(e.into());
+            }
             clone.address = Some(clone.address.unwrap() + 4);
             return PollResult::Again(Box::new(clone));
         }
@@ -74,4 +71,16 @@ impl Instruction for STM {
         }
         hashset![]
     }
+
+    fn class(&self) -> InstructionClass {
+        InstructionClass::LoadStore
+    }
+
+    fn transfers(&self) -> u32 {
+        self.reg_list.len() as u32
+    }
+
+    fn writes_memory(&self) -> bool {
+        true
+    }
 }
@@ -2,7 +2,7 @@ use super::Instruction;
 use crate::cpu_state::station::ReservationStation;
 use crate::instructions::util::ArmOperandExt;
 use crate::instructions::util::RegisterSet;
-use crate::instructions::PollResult;
+use crate::instructions::{InstructionClass, PollResult};
 use capstone::arch::arm::{ArmOpMem, ArmOperand};
 use capstone::prelude::*;
 use std::collections::HashSet;
@@ -19,7 +19,7 @@ pub struct STR {
     reg: RegId,
     mem: ArmOpMem,
     mode: Mode,
-    waited: bool,
+    waited: u32,
 }
 
 impl STR {
@@ -28,43 +28,31 @@ impl STR {
             reg: operands[0].reg_id().unwrap(),
             mem: operands[1].op_mem_value().unwrap(),
             mode,
-            waited: false,
+            waited: 0,
         }
     }
 }
 
 impl Instruction for STR {
     fn poll(&self, station: &ReservationStation) -> PollResult {
-        if !self.waited {
-            // Takes 2 cycles
+        if self.waited < station.latencies.address {
+            // Calculate the address before writing
             let mut cloned = self.clone();
-            cloned.waited = true;
+            cloned.waited = cloned.waited + 1;
             return PollResult::Again(Box::new(cloned));
         }
 
         let mem_addr = station.eval_ldr_str_op_mem(&self.mem);
         let reg_val = station.read_by_id(self.reg);
-        match self.mode {
-            Mode::Word => station
-                .memory
-                .write()
-                .unwrap()
-                .write_bytes(mem_addr, &reg_val.to_le_bytes())
-                .unwrap(),
-            Mode::HalfWord => station
-                .memory
-                .write()
-                .unwrap()
-                .write_bytes(mem_addr, &(reg_val as u16).to_le_bytes())
-                .unwrap(),
-            Mode::Byte => station
-                .memory
-                .write()
-                .unwrap()
-                .write_bytes(mem_addr, &(reg_val as u8).to_le_bytes())
-                .unwrap(),
+        let written = match self.mode {
+            Mode::Word => station.write_bytes(mem_addr, &reg_val.to_le_bytes()),
+            Mode::HalfWord => station.write_bytes(mem_addr, &(reg_val as u16).to_le_bytes()),
+            Mode::Byte => station.write_bytes(mem_addr, &(reg_val as u8).to_le_bytes()),
         };
-        PollResult::Complete(vec![])
+        match written {
+            Ok(_) => PollResult::Complete(vec![]),
+            Err(e) => PollResult::Fault(e.into()),
+        }
     }
 
     fn source_registers(&self) -> HashSet<RegId> {
@@ -76,4 +64,12 @@ impl Instruction for STR {
     fn dest_registers(&self) -> HashSet<RegId> {
         hashset![]
     }
+
+    fn class(&self) -> InstructionClass {
+        InstructionClass::LoadStore
+    }
+
+    fn writes_memory(&self) -> bool {
+        true
+    }
 }
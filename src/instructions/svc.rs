@@ -1,5 +1,6 @@
 use super::Instruction;
 use crate::cpu_state::station::ReservationStation;
+use crate::error::Fault;
 use crate::instructions::util::ArmOperandExt;
 use crate::instructions::PollResult;
 use crate::registers::ids::{R0, R1};
@@ -33,17 +34,18 @@ impl Instruction for SVC {
             2 => {
                 let buffer_addr = station.read_by_id(R0);
                 let buffer_len = station.read_by_id(R1);
-                let data = station
+                let data = match station
                     .memory
                     .read()
                     .unwrap()
                     .read_bytes(buffer_addr, buffer_len)
-                    .expect("SVC Tried to read from invalid memory address");
+                {
+                    Ok(data) => data,
+                    Err(e) => return PollResult::Fault(e.into()),
+                };
                 std::io::stdout().write_all(&data).expect("Failed to write");
             }
-            _ => {
-                panic!("\nUnknown SVC ID: {}", self.id);
-            }
+            _ => return PollResult::Fault(Fault::UnknownSvc(self.id as u32)),
         }
         PollResult::Complete(vec![])
     }
@@ -56,7 +58,11 @@ impl Instruction for SVC {
         hashset![]
     }
 
-    fn hazardous(&self) -> bool {
+    fn non_speculative(&self) -> bool {
+        true
+    }
+
+    fn is_supervisor_call(&self) -> bool {
         true
     }
 }
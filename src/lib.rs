@@ -0,0 +1,92 @@
+pub mod branch_predictor;
+pub mod builder;
+pub mod cache;
+pub mod config;
+pub mod cpu_state;
+pub mod debugger;
+pub mod error;
+pub mod gdb;
+pub mod instructions;
+pub mod line_table;
+pub mod memory;
+pub mod program;
+pub mod registers;
+pub mod report;
+pub mod simulators;
+pub mod sweep;
+pub mod symbols;
+
+#[macro_use]
+extern crate maplit;
+
+pub use branch_predictor::PredictorType;
+pub use builder::SimulationBuilder;
+pub use error::{Fault, SimulationError};
+pub use program::Program;
+pub use simulators::simulation::{Simulation, StopCondition, StopReason};
+pub use simulators::{SimulationStats, Simulator};
+
+use capstone::prelude::*;
+use num_derive::FromPrimitive;
+use serde::Serialize;
+use std::str::FromStr;
+
+#[derive(FromPrimitive, PartialEq, PartialOrd, Debug, Clone, Copy, Serialize)]
+pub enum DebugLevel {
+    Off = 0,
+    Minimal = 1,
+    Full = 2,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub enum SimulatorType {
+    Scalar,
+    Pipelined,
+    OutOfOrder,
+    CortexM0,
+    FiveStage,
+}
+
+impl FromStr for SimulatorType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "scalar" => Ok(Self::Scalar),
+            "pipelined" => Ok(Self::Pipelined),
+            "outoforder" => Ok(Self::OutOfOrder),
+            "m0" => Ok(Self::CortexM0),
+            "fivestage" => Ok(Self::FiveStage),
+            _ => Err("Couldn't match SimulatorType".to_string()),
+        }
+    }
+}
+
+/*
+The top of the stack as defined by the linker script
+" .stack 0x80000 : { _stack = .; *(.stack) } "
+`readelf -s basic.elf | grep _stack` = 00080000
+newlib will deal with the stack pointer automatically
+*/
+pub const DEFAULT_STACK_TOP: u32 = 0x80000;
+pub const DEFAULT_STACK_SIZE: u32 = 4096;
+
+thread_local! {
+    pub static CAPSTONE: Capstone = Capstone::new()
+                .arm()
+                .mode(arch::arm::ArchMode::Thumb)
+                .endian(capstone::Endian::Little)
+                .detail(true)
+                .build()
+                .unwrap()
+}
+
+// Hex with a 0x prefix or decimal, for the command line, debugger and memory regions
+pub fn parse_number(s: &str) -> Result<u32, String> {
+    let parsed = if s.starts_with("0x") || s.starts_with("0X") {
+        u32::from_str_radix(&s[2..], 16)
+    } else {
+        s.parse()
+    };
+    parsed.map_err(|_| format!("Invalid number {}", s))
+}
@@ -0,0 +1,391 @@
+use std::convert::TryInto;
+
+// DW_LNCT content types and DW_FORM forms used by DWARF 5 directory and file entries
+const DW_LNCT_PATH: u64 = 1;
+const DW_FORM_BLOCK: u64 = 0x09;
+const DW_FORM_DATA1: u64 = 0x0b;
+const DW_FORM_DATA2: u64 = 0x05;
+const DW_FORM_DATA4: u64 = 0x06;
+const DW_FORM_DATA8: u64 = 0x07;
+const DW_FORM_DATA16: u64 = 0x1e;
+const DW_FORM_LINE_STRP: u64 = 0x1f;
+const DW_FORM_STRING: u64 = 0x08;
+const DW_FORM_STRP: u64 = 0x0e;
+const DW_FORM_UDATA: u64 = 0x0f;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Row {
+    address: u32,
+    file: usize, // Index into LineTable::files
+    line: u32,
+    end_sequence: bool, // The first address after a sequence of instructions
+}
+
+// Maps addresses to source lines, from the line number programs in .debug_line
+#[derive(Debug, Clone, Default)]
+pub struct LineTable {
+    files: Vec<String>,
+    rows: Vec<Row>, // Sorted by address
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, n: usize) -> Option<&'a [u8]> {
+        let bytes = self.data.get(self.offset..self.offset.checked_add(n)?)?;
+        self.offset = self.offset + n;
+        Some(bytes)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        Some(u16::from_le_bytes(self.bytes(2)?.try_into().ok()?))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.bytes(4)?.try_into().ok()?))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.bytes(8)?.try_into().ok()?))
+    }
+
+    // Section offsets are 8 bytes in the 64-bit DWARF format
+    fn offset(&mut self, dwarf64: bool) -> Option<u64> {
+        if dwarf64 {
+            self.u64()
+        } else {
+            self.u32().map(|o| o as u64)
+        }
+    }
+
+    fn uleb(&mut self) -> Option<u64> {
+        let mut value = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.u8()?;
+            if shift < 64 {
+                value = value | ((byte & 0x7f) as u64) << shift;
+            }
+            shift = shift + 7;
+            if byte & 0x80 == 0 {
+                return Some(value);
+            }
+        }
+    }
+
+    fn sleb(&mut self) -> Option<i64> {
+        let mut value: i64 = 0;
+        let mut shift = 0;
+        loop {
+            let byte = self.u8()?;
+            if shift < 64 {
+                value = value | ((byte & 0x7f) as i64) << shift;
+            }
+            shift = shift + 7;
+            if byte & 0x80 == 0 {
+                if shift < 64 && byte & 0x40 != 0 {
+                    value = value | -1 << shift; // Sign extend
+                }
+                return Some(value);
+            }
+        }
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let rest = self.data.get(self.offset..)?;
+        let end = rest.iter().position(|b| *b == 0)?;
+        self.offset = self.offset + end + 1;
+        Some(String::from_utf8_lossy(&rest[..end]).to_string())
+    }
+}
+
+// A null terminated string at an offset into .debug_str or .debug_line_str
+fn string_at(section: &[u8], offset: u64) -> Option<String> {
+    let mut reader = Reader {
+        data: section,
+        offset: offset as usize,
+    };
+    reader.string()
+}
+
+impl LineTable {
+    // Sections which are missing can be empty, a program without debug info has no lines
+    pub fn parse(debug_line: &[u8], debug_str: &[u8], debug_line_str: &[u8]) -> Self {
+        let mut table = Self::default();
+        let mut reader = Reader {
+            data: debug_line,
+            offset: 0,
+        };
+        while reader.offset < debug_line.len() {
+            // Give up on the rest of the section if a unit can't be parsed
+            if table
+                .parse_unit(&mut reader, debug_str, debug_line_str)
+                .is_none()
+            {
+                break;
+            }
+        }
+        // Where one sequence ends at the start of another, the start must be found
+        table.rows.sort_by_key(|r| (r.address, !r.end_sequence));
+        table
+    }
+
+    fn parse_unit(&mut self, reader: &mut Reader, debug_str: &[u8], line_str: &[u8]) -> Option<()> {
+        let mut length = reader.u32()? as u64;
+        let dwarf64 = length == 0xFFFFFFFF;
+        if dwarf64 {
+            length = reader.u64()?;
+        }
+        let end = reader.offset.checked_add(length as usize)?;
+        let mut unit = Reader {
+            data: reader.data.get(..end)?,
+            offset: reader.offset,
+        };
+        reader.offset = end;
+
+        let version = unit.u16()?;
+        if !(2..=5).contains(&version) {
+            return Some(()); // Skip units we don't understand
+        }
+        let mut address_size = 4;
+        if version >= 5 {
+            address_size = unit.u8()?;
+            unit.u8()?; // Segment selector size
+        }
+        let header_length = unit.offset(dwarf64)?;
+        let program = unit.offset.checked_add(header_length as usize)?;
+        let minimum_instruction_length = unit.u8()? as u32;
+        if version >= 4 {
+            unit.u8()?; // Maximum operations per instruction, only for VLIW
+        }
+        unit.u8()?; // Default is_stmt
+        let line_base = unit.u8()? as i8 as i64;
+        let line_range = unit.u8()?;
+        let opcode_base = unit.u8()?;
+        if line_range == 0 || opcode_base == 0 {
+            return None;
+        }
+        let opcode_lengths = unit.bytes(opcode_base as usize - 1)?.to_vec();
+
+        // Index of this unit's first file in self.files, and how file numbers start
+        let first_file = self.files.len();
+        let file_base = if version >= 5 {
+            let read_entries = |unit: &mut Reader| -> Option<Vec<String>> {
+                let format_count = unit.u8()?;
+                let mut format = vec![];
+                for _ in 0..format_count {
+                    format.push((unit.uleb()?, unit.uleb()?));
+                }
+                let count = unit.uleb()?;
+                let mut paths = vec![];
+                for _ in 0..count {
+                    let mut path = String::new();
+                    for (content, form) in &format {
+                        let value = match *form {
+                            DW_FORM_STRING => Some(unit.string()?),
+                            DW_FORM_LINE_STRP => string_at(line_str, unit.offset(dwarf64)?),
+                            DW_FORM_STRP => string_at(debug_str, unit.offset(dwarf64)?),
+                            DW_FORM_UDATA => unit.uleb().map(|_| None)?,
+                            DW_FORM_DATA1 => unit.bytes(1).map(|_| None)?,
+                            DW_FORM_DATA2 => unit.bytes(2).map(|_| None)?,
+                            DW_FORM_DATA4 => unit.bytes(4).map(|_| None)?,
+                            DW_FORM_DATA8 => unit.bytes(8).map(|_| None)?,
+                            DW_FORM_DATA16 => unit.bytes(16).map(|_| None)?,
+                            DW_FORM_BLOCK => {
+                                let length = unit.uleb()? as usize;
+                                unit.bytes(length).map(|_| None)?
+                            }
+                            _ => return None,
+                        };
+                        if let (DW_LNCT_PATH, Some(value)) = (*content, value) {
+                            path = value;
+                        }
+                    }
+                    paths.push(path);
+                }
+                Some(paths)
+            };
+            read_entries(&mut unit)?; // Directories
+            let files = read_entries(&mut unit)?;
+            self.files.extend(files);
+            0
+        } else {
+            // Include directories
+            while !unit.string()?.is_empty() {}
+            loop {
+                let name = unit.string()?;
+                if name.is_empty() {
+                    break;
+                }
+                unit.uleb()?; // Directory
+                unit.uleb()?; // Modification time
+                unit.uleb()?; // Length
+                self.files.push(name);
+            }
+            1
+        };
+
+        // Run the line number program
+        unit.offset = program;
+        let mut address: u64 = 0;
+        let mut file: u64 = 1;
+        let mut line: i64 = 1;
+        let file_count = self.files.len() - first_file;
+        let emit = |rows: &mut Vec<Row>, address: u64, file: u64, line: i64, end: bool| {
+            let index = file.checked_sub(file_base).map(|f| f as usize);
+            if let Some(index) = index.filter(|i| *i < file_count) {
+                rows.push(Row {
+                    address: address as u32,
+                    file: first_file + index,
+                    line: line as u32,
+                    end_sequence: end,
+                });
+            }
+        };
+        while unit.offset < end {
+            let opcode = unit.u8()?;
+            if opcode >= opcode_base {
+                // Special opcode, advances both the address and line then adds a row
+                let adjusted = opcode - opcode_base;
+                address =
+                    address + (adjusted / line_range) as u64 * minimum_instruction_length as u64;
+                line = line + line_base + (adjusted % line_range) as i64;
+                emit(&mut self.rows, address, file, line, false);
+                continue;
+            }
+            match opcode {
+                0 => {
+                    let length = unit.uleb()? as usize;
+                    let next = unit.offset.checked_add(length)?;
+                    match unit.u8()? {
+                        // DW_LNE_end_sequence
+                        1 => {
+                            emit(&mut self.rows, address, file, line, true);
+                            address = 0;
+                            file = 1;
+                            line = 1;
+                        }
+                        // DW_LNE_set_address
+                        2 => {
+                            address = match address_size {
+                                8 => unit.u64()?,
+                                _ => unit.u32()? as u64,
+                            }
+                        }
+                        _ => {}
+                    }
+                    unit.offset = next;
+                }
+                1 => emit(&mut self.rows, address, file, line, false), // DW_LNS_copy
+                2 => address = address + unit.uleb()? * minimum_instruction_length as u64,
+                3 => line = line + unit.sleb()?,
+                4 => file = unit.uleb()?,
+                // DW_LNS_const_add_pc
+                8 => {
+                    let adjusted = 255 - opcode_base;
+                    address = address
+                        + (adjusted / line_range) as u64 * minimum_instruction_length as u64;
+                }
+                9 => address = address + unit.u16()? as u64, // DW_LNS_fixed_advance_pc
+                // Anything else only changes state we don't track
+                _ => {
+                    for _ in 0..opcode_lengths[opcode as usize - 1] {
+                        unit.uleb()?;
+                    }
+                }
+            }
+        }
+        Some(())
+    }
+
+    // The file name and line the instruction at this address was generated from
+    pub fn lookup(&self, address: u32) -> Option<(&str, u32)> {
+        let index = self.rows.partition_point(|r| r.address <= address);
+        let row = self.rows.get(index.checked_sub(1)?)?;
+        if row.end_sequence {
+            return None;
+        }
+        Some((&self.files[row.file], row.line))
+    }
+
+    // The lowest address generated from this line, if any
+    pub fn address_of(&self, file: &str, line: u32) -> Option<u32> {
+        self.rows
+            .iter()
+            .filter(|r| !r.end_sequence && r.line == line)
+            .filter(|r| {
+                let name = &self.files[r.file];
+                name == file || name.ends_with(&format!("/{}", file))
+            })
+            .map(|r| r.address)
+            .min()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A unit with the sequence main.c:3 at 0x1000, :4 at 0x1004, :6 at 0x1008, ending at 0x100C
+    fn unit(version: u16) -> Vec<u8> {
+        let mut header = vec![];
+        if version >= 5 {
+            header.extend(&[4, 0]); // Address and segment selector sizes
+        }
+        let mut rest = vec![1, 1, 1, 0xfb, 14, 13]; // -5 line base
+        rest.extend(&[0, 1, 1, 1, 1, 0, 0, 0, 1, 0, 0, 1]);
+        if version >= 5 {
+            // Directories: one path as a string, then files: path and directory index
+            rest.extend(&[1, 1, 0x08, 1]);
+            rest.extend(b"/src\0");
+            rest.extend(&[2, 1, 0x08, 2, 0x0b, 1]);
+            rest.extend(b"main.c\0");
+            rest.push(0);
+        } else {
+            rest.push(0); // No include directories
+            rest.extend(b"main.c\0");
+            rest.extend(&[0, 0, 0, 0]);
+        }
+        if version < 4 {
+            rest.remove(1); // Maximum operations per instruction
+        }
+        let file = if version >= 5 { 0 } else { 1 };
+        let mut program = vec![4, file]; // DW_LNS_set_file
+        program.extend(&[0, 5, 2, 0x00, 0x10, 0, 0]); // DW_LNE_set_address 0x1000
+        program.extend(&[3, 2, 1]); // Line 3, copy
+
+        // Special opcode: address + 4, line + 1
+        program.push((1 - (-5) + 14 * 4 + 13) as u8);
+        program.extend(&[2, 4, 3, 2, 1]); // Address + 4, line + 2, copy
+        program.extend(&[2, 4, 0, 1, 1]); // Address + 4, DW_LNE_end_sequence
+
+        header.extend(&(rest.len() as u32).to_le_bytes());
+        header.extend(rest);
+        header.extend(program);
+        let mut unit = (header.len() as u32 + 2).to_le_bytes().to_vec();
+        unit.extend(&version.to_le_bytes());
+        unit.extend(header);
+        unit
+    }
+
+    #[test]
+    fn line_programs() {
+        for version in &[3, 4, 5] {
+            let table = LineTable::parse(&unit(*version), &[], &[]);
+            assert_eq!(table.lookup(0xFFF), None, "{}", version);
+            assert_eq!(table.lookup(0x1000), Some(("main.c", 3)), "{}", version);
+            assert_eq!(table.lookup(0x1006), Some(("main.c", 4)), "{}", version);
+            assert_eq!(table.lookup(0x100A), Some(("main.c", 6)), "{}", version);
+            assert_eq!(table.lookup(0x100C), None, "{}", version);
+            assert_eq!(table.address_of("main.c", 6), Some(0x1008));
+        }
+        assert!(LineTable::parse(&[1, 2, 3], &[], &[]).lookup(0).is_none());
+    }
+}
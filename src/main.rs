@@ -1,71 +1,40 @@
-mod cpu_state;
-mod instructions;
-mod memory;
-mod registers;
-mod simulators;
-
-#[macro_use]
-extern crate maplit;
-
-use crate::simulators::non_pipelined::NonPipelinedSimulator;
-use crate::simulators::out_of_order::OutOfOrderSimulator;
-use crate::simulators::pipelined::PipelinedSimulator;
-use crate::simulators::Simulator;
 use anyhow::{anyhow, Context};
-use capstone::prelude::*;
-use clap::Clap;
-use elf::types::PT_LOAD;
-use memory::Memory;
-use num_derive::FromPrimitive;
+use clap::{Clap, FromArgMatches, IntoApp};
 use num_traits::FromPrimitive;
+use simulator::cache::main_memory::{MemoryRegion, MemoryTiming};
+use simulator::cache::CacheConfig;
+use simulator::config::MachineConfig;
+use simulator::cpu_state::execution_trace::ExecutionTrace;
+use simulator::cpu_state::functional_units::{FunctionalUnits, Multiplier};
+use simulator::cpu_state::pipeline_trace::PipelineTrace;
+use simulator::cpu_state::profiler::Profiler;
+use simulator::debugger::Debugger;
+use simulator::gdb::GdbStub;
+use simulator::report::{to_csv, Report, StatsFormat};
+use simulator::simulators::five_stage::{BranchStage, Forwarding};
+use simulator::sweep::{Sweep, SweepGrid};
+use simulator::{
+    parse_number, DebugLevel, PredictorType, Program, SimulationBuilder, SimulatorType,
+};
 use std::fs::File;
-use std::io::Read;
-use std::path::PathBuf;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::time::Instant;
 
-#[derive(FromPrimitive, PartialEq, PartialOrd, Debug)]
-pub enum DebugLevel {
-    Off = 0,
-    Minimal = 1,
-    Full = 2,
-}
-
-#[derive(Debug)]
-pub enum SimulatorType {
-    Scalar,
-    Pipelined,
-    OutOfOrder,
-}
-
-impl FromStr for SimulatorType {
-    type Err = String;
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s.to_lowercase().as_str() {
-            "scalar" => Ok(Self::Scalar),
-            "pipelined" => Ok(Self::Pipelined),
-            "outoforder" => Ok(Self::OutOfOrder),
-            _ => Err("Couldn't match SimulatorType".to_string()),
-        }
-    }
-}
-
-/*
-The top of the stack as defined by the linker script
-" .stack 0x80000 : { _stack = .; *(.stack) } "
-`readelf -s basic.elf | grep _stack` = 00080000
-newlib will deal with the stack pointer automatically
-*/
-const _STACK: u32 = 0x80000;
-
 #[derive(Clap)]
 #[clap(version = "1.0", author = "Jacob Halsey")]
 struct Opts {
     #[clap(about = "Choose the name of the program to run")]
-    program: PathBuf,
+    program: Option<PathBuf>,
     #[clap(long, about = "Set stack size in bytes", default_value = "4096")]
     stack: u32,
+    #[clap(
+        long,
+        about = "Reset from the vector table at this address, and enter its exception handlers",
+        parse(try_from_str = parse_number)
+    )]
+    vector_table: Option<u32>,
     #[clap(
         short,
         long,
@@ -82,73 +51,439 @@ struct Opts {
         default_value = "4"
     )]
     units: usize,
+    #[clap(
+        long,
+        about = "Stations bound to typed functional units as alu=N,mul=N,branch=N,lsu=N, replaces --units"
+    )]
+    functional_units: Option<FunctionalUnits>,
+    #[clap(long, about = "Multiplier [iterative, pipelined]")]
+    multiplier: Option<Multiplier>,
+    #[clap(
+        long,
+        about = "Forwarding paths of the five stage pipeline [none, ex, mem, full]"
+    )]
+    forwarding: Option<Forwarding>,
+    #[clap(
+        long,
+        about = "Stage the five stage pipeline resolves branches in [id, ex, mem]"
+    )]
+    branch_stage: Option<BranchStage>,
+    #[clap(
+        short,
+        long,
+        about = "Choose the branch predictor [nottaken, btfn, onebit, twobit, gshare, tournament]",
+        default_value = "nottaken"
+    )]
+    predictor: PredictorType,
+    #[clap(
+        long,
+        about = "Number of branch target buffer entries",
+        default_value = "64"
+    )]
+    btb: usize,
+    #[clap(
+        long,
+        about = "Number of return address stack entries, 0 to disable",
+        default_value = "8"
+    )]
+    ras: usize,
+    #[clap(long, about = "Number of reorder buffer entries", default_value = "32")]
+    rob: usize,
+    #[clap(long, about = "Number of physical registers", default_value = "64")]
+    prf: usize,
+    #[clap(
+        long,
+        about = "Instructions fetched per cycle, a power of two (out of order only)",
+        default_value = "1"
+    )]
+    fetch_width: usize,
+    #[clap(
+        long,
+        about = "Instructions decoded per cycle (out of order only)",
+        default_value = "1"
+    )]
+    decode_width: usize,
+    #[clap(
+        long,
+        about = "Instructions issued per cycle (out of order only)",
+        default_value = "1"
+    )]
+    issue_width: usize,
+    #[clap(
+        long,
+        about = "L1 instruction cache as size,ways,line_size[,lru|fifo|random][,hit=cycles]"
+    )]
+    icache: Option<CacheConfig>,
+    #[clap(
+        long,
+        about = "L1 data cache as size,ways,line_size[,lru|fifo|random][,wb|wt][,wa|nwa][,hit=cycles]"
+    )]
+    dcache: Option<CacheConfig>,
+    #[clap(long, about = "Unified L2 cache, in the same format as --dcache")]
+    l2: Option<CacheConfig>,
+    #[clap(
+        long,
+        about = "Main memory latency as cycles or banks,row_size,row_hit,row_miss",
+        default_value = "1"
+    )]
+    memory: MemoryTiming,
+    #[clap(
+        long,
+        about = "Latency of the program's executable segments, as --memory"
+    )]
+    flash: Option<MemoryTiming>,
+    #[clap(
+        long,
+        about = "Latency of an address range as start-end=timing, may be repeated",
+        multiple_occurrences = true
+    )]
+    region: Vec<MemoryRegion>,
+    #[clap(
+        long,
+        about = "Machine description in TOML, options given on the command line take priority"
+    )]
+    config: Option<PathBuf>,
+    #[clap(
+        long,
+        about = "Format of the statistics [text, json, csv]",
+        default_value = "text"
+    )]
+    stats_format: StatsFormat,
+    #[clap(long, about = "Write the statistics to a file instead of stdout")]
+    stats_out: Option<PathBuf>,
+    #[clap(
+        long,
+        about = "Write the lifecycle of every instruction to a file, for the Konata pipeline viewer"
+    )]
+    pipeline_trace: Option<PathBuf>,
+    #[clap(
+        long,
+        about = "Write a line of JSON to a file for every instruction retired"
+    )]
+    execution_trace: Option<PathBuf>,
+    #[clap(
+        long,
+        about = "Write a flat profile and call graph of the guest's functions to a file"
+    )]
+    profile: Option<PathBuf>,
+    #[clap(
+        long,
+        about = "Write the cycles spent in each call stack to a file, for flamegraph.pl"
+    )]
+    folded_stacks: Option<PathBuf>,
+    #[clap(long, about = "Wait for gdb to connect on this port of localhost")]
+    gdb: Option<u16>,
+    #[clap(
+        long,
+        about = "Start stopped at the entry point, with a prompt for debugger commands"
+    )]
+    interactive: bool,
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Clap)]
+enum Command {
+    #[clap(
+        about = "Run programs on every combination of the given parameters, options before `sweep` set the rest"
+    )]
+    Sweep(SweepOpts),
+}
+
+#[derive(Clap)]
+struct SweepOpts {
+    #[clap(about = "The programs to run", required = true)]
+    programs: Vec<PathBuf>,
+    #[clap(
+        short,
+        long,
+        about = "Simulator types to try",
+        use_delimiter = true,
+        number_of_values = 1
+    )]
+    sim: Vec<SimulatorType>,
+    #[clap(
+        short,
+        long,
+        about = "Numbers of stations / execution units to try",
+        use_delimiter = true,
+        number_of_values = 1
+    )]
+    units: Vec<usize>,
+    #[clap(
+        long,
+        about = "Numbers of reorder buffer entries to try",
+        use_delimiter = true,
+        number_of_values = 1
+    )]
+    rob: Vec<usize>,
+    #[clap(
+        long,
+        about = "Numbers of physical registers to try",
+        use_delimiter = true,
+        number_of_values = 1
+    )]
+    prf: Vec<usize>,
+    #[clap(
+        short,
+        long,
+        about = "Branch predictors to try",
+        use_delimiter = true,
+        number_of_values = 1
+    )]
+    predictor: Vec<PredictorType>,
+    #[clap(
+        long,
+        about = "L1 instruction cache to try (or none), may be repeated",
+        multiple_occurrences = true,
+        number_of_values = 1
+    )]
+    icache: Vec<SweepCache>,
+    #[clap(
+        long,
+        about = "L1 data cache to try (or none), may be repeated",
+        multiple_occurrences = true,
+        number_of_values = 1
+    )]
+    dcache: Vec<SweepCache>,
+    #[clap(
+        long,
+        about = "L2 cache to try (or none), may be repeated",
+        multiple_occurrences = true,
+        number_of_values = 1
+    )]
+    l2: Vec<SweepCache>,
+}
+
+// A cache configuration or "none"
+struct SweepCache(Option<CacheConfig>);
+
+impl FromStr for SweepCache {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Self(None)),
+            _ => Ok(Self(Some(s.parse()?))),
+        }
+    }
 }
 
 fn main() -> anyhow::Result<()> {
-    let matches = Opts::parse();
+    let app_matches = Opts::into_app().get_matches();
+    let matches = Opts::from_arg_matches(&app_matches);
 
     let debug_level: DebugLevel =
         FromPrimitive::from_u32(matches.debug).with_context(|| "Unsupported debug level")?;
 
-    let elf_file = elf::File::open_path(&matches.program)
-        .map_err(|e| anyhow!(format!("{:?}", e)))
-        .with_context(|| "Reading elf binary")?;
-
-    let mut elf_file_bytes = Vec::new();
-    File::open(&matches.program)
-        .unwrap()
-        .read_to_end(&mut elf_file_bytes)
-        .with_context(|| "Reading elf file contents")?;
-
-    let mut memory = Memory::default();
-    memory.mmap(
-        _STACK - matches.stack,
-        vec![0; matches.stack as usize],
-        true,
-    );
-
-    // https://wiki.osdev.org/ELF#Loading_ELF_Binaries
-    for header in elf_file.phdrs.iter() {
-        if header.progtype == PT_LOAD {
-            let mut data = vec![0; header.memsz as usize];
-            let elf_offset = header.offset as usize;
-            let end = elf_offset + header.filesz as usize;
-
-            data[0..header.filesz as usize].copy_from_slice(&elf_file_bytes[elf_offset..end]);
-
-            let write = (header.flags.0 & 0b10) > 0;
-            memory.mmap(header.vaddr as u32, data, write);
-        }
+    let mut builder = SimulationBuilder::new(SimulatorType::OutOfOrder).debug_level(debug_level);
+    if let Some(path) = &matches.config {
+        builder = MachineConfig::from_file(path)?
+            .apply(builder)
+            .map_err(|e| anyhow!("Invalid config {}: {}", path.display(), e))?;
+    }
+    // Without a config file the defaults are used, with one only options that were given
+    let given = |name: &str| matches.config.is_none() || app_matches.occurrences_of(name) > 0;
+    if let Some(sim) = matches.sim {
+        builder = builder.simulator(sim);
+    }
+    if given("units") {
+        builder = builder.units(matches.units);
+    }
+    if given("stack") {
+        builder = builder.stack_size(matches.stack);
+    }
+    if matches.vector_table.is_some() {
+        builder = builder.vector_table(matches.vector_table);
+    }
+    if given("predictor") {
+        builder = builder.predictor(matches.predictor);
+    }
+    if given("btb") {
+        builder = builder.btb_entries(matches.btb);
+    }
+    if given("ras") {
+        builder = builder.return_stack_depth(matches.ras);
+    }
+    if given("rob") {
+        builder = builder.rob_entries(matches.rob);
+    }
+    if given("prf") {
+        builder = builder.physical_registers(matches.prf);
+    }
+    if given("fetch-width") {
+        builder = builder.fetch_width(matches.fetch_width);
+    }
+    if given("decode-width") {
+        builder = builder.decode_width(matches.decode_width);
+    }
+    if given("issue-width") {
+        builder = builder.issue_width(matches.issue_width);
+    }
+    if given("memory") {
+        builder = builder.memory_timing(matches.memory);
+    }
+    if matches.icache.is_some() {
+        builder = builder.instruction_cache(matches.icache);
+    }
+    if matches.dcache.is_some() {
+        builder = builder.data_cache(matches.dcache);
+    }
+    if matches.l2.is_some() {
+        builder = builder.l2_cache(matches.l2);
+    }
+    if matches.flash.is_some() {
+        builder = builder.flash_timing(matches.flash);
+    }
+    if matches.functional_units.is_some() {
+        builder = builder.functional_units(matches.functional_units);
+    }
+    if let Some(multiplier) = matches.multiplier {
+        builder = builder.multiplier(multiplier);
+    }
+    if let Some(forwarding) = matches.forwarding {
+        builder = builder.forwarding(forwarding);
+    }
+    if let Some(branch_stage) = matches.branch_stage {
+        builder = builder.branch_stage(branch_stage);
+    }
+    let builder = matches
+        .region
+        .into_iter()
+        .fold(builder, |builder, region| builder.memory_region(region));
+    builder
+        .validate()
+        .map_err(|e| anyhow!("Invalid configuration: {}", e))?;
+
+    if let Some(Command::Sweep(sweep)) = matches.command {
+        return run_sweep(
+            sweep,
+            &builder,
+            matches.stats_format,
+            matches.stats_out.as_deref(),
+        );
     }
 
-    let entry = elf_file.ehdr.entry as u32;
+    let program_path = matches
+        .program
+        .with_context(|| "No program given, see --help")?;
+    let program = Program::from_elf(&program_path)?;
+
     if debug_level >= DebugLevel::Minimal {
         println!("DEBUG MODE: {:?}", debug_level);
-        println!("Entry point at {:#X}", entry & 0xFFFFFFFE);
+        println!("Entry point at {:#X}", program.entry & 0xFFFFFFFE);
     }
 
-    let sim: Box<dyn Simulator> = match matches.sim.unwrap_or(SimulatorType::OutOfOrder) {
-        SimulatorType::Scalar => Box::new(NonPipelinedSimulator {}),
-        SimulatorType::Pipelined => Box::new(PipelinedSimulator {}),
-        SimulatorType::OutOfOrder => Box::new(OutOfOrderSimulator::new(matches.units)),
-    };
-
-    println!("Using: {}\n", sim.name());
+    // Keep stdout machine-readable when that is where the statistics are going
+    let verbose = matches.stats_format == StatsFormat::Text || matches.stats_out.is_some();
+    if verbose {
+        println!("Using: {}", builder.build_simulator().name());
+        println!("Branch predictor: {:?}\n", builder.predictor_type());
+    }
     let start_time = Instant::now();
-    println!("{}", sim.run(memory, entry, &debug_level));
-    println!(
-        "Simulator ran for {} seconds",
-        start_time.elapsed().as_millis() as f64 / 1000.0
-    );
+    let mut simulation = builder.build(program)?;
+    if let Some(path) = &matches.pipeline_trace {
+        let file = File::create(path)
+            .with_context(|| format!("Failed to create pipeline trace {}", path.display()))?;
+        simulation.state.pipeline_trace = Some(PipelineTrace::new(Box::new(BufWriter::new(file))));
+    }
+    if let Some(path) = &matches.execution_trace {
+        let file = File::create(path)
+            .with_context(|| format!("Failed to create execution trace {}", path.display()))?;
+        simulation.state.execution_trace =
+            Some(ExecutionTrace::new(Box::new(BufWriter::new(file))));
+    }
+    if matches.profile.is_some() || matches.folded_stacks.is_some() {
+        simulation.state.profiler = Some(Profiler::new(simulation.pc()));
+    }
+    let simulation = match matches.gdb {
+        Some(port) => GdbStub::new(simulation).serve(port)?,
+        None if matches.interactive => Debugger::new(simulation).run(std::io::stdin().lock()),
+        None => {
+            simulation.run_until(&[]);
+            simulation
+        }
+    };
+    if let Some(profiler) = &simulation.state.profiler {
+        let symbols = &simulation.state.debug_info.symbols;
+        if let Some(path) = &matches.profile {
+            std::fs::write(path, profiler.report(symbols))
+                .with_context(|| format!("Failed to write profile {}", path.display()))?;
+        }
+        if let Some(path) = &matches.folded_stacks {
+            std::fs::write(path, profiler.folded_stacks(symbols))
+                .with_context(|| format!("Failed to write folded stacks {}", path.display()))?;
+        }
+    }
+    if let Some(error) = simulation.state.error {
+        return Err(error.into());
+    }
+    let stats = simulation.stats;
+    let output =
+        Report::new(&program_name(&program_path), &builder, &stats).render(matches.stats_format);
+    write_stats(output, matches.stats_out.as_deref())?;
+    if verbose {
+        println!(
+            "Simulator ran for {} seconds",
+            start_time.elapsed().as_millis() as f64 / 1000.0
+        );
+    }
     Ok(())
 }
 
-thread_local! {
-    pub static CAPSTONE: Capstone = Capstone::new()
-                .arm()
-                .mode(arch::arm::ArchMode::Thumb)
-                .endian(capstone::Endian::Little)
-                .detail(true)
-                .build()
-                .unwrap()
+fn run_sweep(
+    opts: SweepOpts,
+    base: &SimulationBuilder,
+    format: StatsFormat,
+    out: Option<&Path>,
+) -> anyhow::Result<()> {
+    let grid = SweepGrid {
+        simulators: opts.sim,
+        units: opts.units,
+        rob_entries: opts.rob,
+        physical_registers: opts.prf,
+        predictors: opts.predictor,
+        instruction_caches: opts.icache.into_iter().map(|c| c.0).collect(),
+        data_caches: opts.dcache.into_iter().map(|c| c.0).collect(),
+        l2_caches: opts.l2.into_iter().map(|c| c.0).collect(),
+    };
+    let programs = opts
+        .programs
+        .iter()
+        .map(|p| {
+            let program =
+                Program::from_elf(p).with_context(|| format!("Loading {}", p.display()))?;
+            Ok((program_name(p), program))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let configurations = grid.configurations(base);
+    for c in &configurations {
+        c.builder
+            .validate()
+            .map_err(|e| anyhow!("Invalid configuration {}: {}", c.label, e))?;
+    }
+    let sweep = Sweep::run(&programs, &configurations)?;
+    let output = match format {
+        StatsFormat::Text => sweep.to_string(),
+        StatsFormat::Json => serde_json::to_string_pretty(&sweep.reports())?,
+        StatsFormat::Csv => to_csv(&sweep.reports()),
+    };
+    write_stats(output, out)
+}
+
+fn program_name(path: &Path) -> String {
+    path.file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+fn write_stats(output: String, out: Option<&Path>) -> anyhow::Result<()> {
+    match out {
+        Some(path) => std::fs::write(path, output)
+            .with_context(|| format!("Failed to write statistics to {}", path.display())),
+        None => {
+            println!("{}", output);
+            Ok(())
+        }
+    }
 }
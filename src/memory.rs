@@ -1,13 +1,21 @@
 use std::convert::TryInto;
 use std::fmt::{Display, Formatter};
 
+#[derive(Clone)]
 struct Page {
     write: bool,
     data: Vec<u8>,
     vaddr: u32,
 }
 
-#[derive(Default)]
+impl Page {
+    // The last page may end at the very top of the address space
+    fn contains(&self, address: u32) -> bool {
+        address >= self.vaddr && (address as u64) < self.vaddr as u64 + self.data.len() as u64
+    }
+}
+
+#[derive(Default, Clone)]
 pub struct Memory {
     pages: Vec<Page>,
 }
@@ -16,18 +24,32 @@ pub struct Memory {
 pub enum MemoryAccessError {
     BadAddress(u32),
     ReadOnlyAddress(u32),
+    Overlap(u32), // Mapping a page over one that already exists
+}
+
+// The address of each byte of an access, which must not run past the end of the address space
+pub fn byte_addresses(
+    base_address: u32,
+    length: u32,
+) -> Result<impl Iterator<Item = u32>, MemoryAccessError> {
+    if base_address as u64 + length as u64 > 1 << 32 {
+        return Err(MemoryAccessError::BadAddress(base_address));
+    }
+    Ok((0..length).map(move |i| base_address + i))
 }
 
 impl Memory {
-    pub fn mmap(&mut self, address: u32, data: Vec<u8>, write: bool) {
+    pub fn mmap(
+        &mut self,
+        address: u32,
+        data: Vec<u8>,
+        write: bool,
+    ) -> Result<(), MemoryAccessError> {
+        let new_p_end = address as u64 + data.len() as u64;
         for existing_p in &self.pages {
-            let existing_p_end = existing_p.vaddr + existing_p.data.len() as u32;
-            let new_p_end = address + data.len() as u32;
-            if address >= existing_p.vaddr && address < existing_p_end {
-                panic!("Cannot create page here");
-            }
-            if new_p_end >= existing_p.vaddr && new_p_end < existing_p_end {
-                panic!("Cannot create page here");
+            let existing_p_end = existing_p.vaddr as u64 + existing_p.data.len() as u64;
+            if (address as u64) < existing_p_end && new_p_end > existing_p.vaddr as u64 {
+                return Err(MemoryAccessError::Overlap(address.max(existing_p.vaddr)));
             }
         }
         self.pages.push(Page {
@@ -35,12 +57,12 @@ impl Memory {
             data,
             vaddr: address,
         });
+        Ok(())
     }
 
     pub fn read_byte(&self, address: u32) -> Result<u8, MemoryAccessError> {
         for p in &self.pages {
-            let p_end_addr = p.vaddr + p.data.len() as u32;
-            if address >= p.vaddr && address < p_end_addr {
+            if p.contains(address) {
                 let adj_addr = address - p.vaddr;
                 return Ok(p.data[adj_addr as usize]);
             }
@@ -50,8 +72,7 @@ impl Memory {
 
     pub fn write_byte(&mut self, address: u32, byte: u8) -> Result<(), MemoryAccessError> {
         for p in &mut self.pages {
-            let p_end_addr = p.vaddr + p.data.len() as u32;
-            if address >= p.vaddr && address < p_end_addr {
+            if p.contains(address) {
                 let adj_addr = address - p.vaddr;
                 if p.write {
                     p.data[adj_addr as usize] = byte;
@@ -66,20 +87,28 @@ impl Memory {
 
     pub fn read_bytes(&self, base_address: u32, length: u32) -> Result<Vec<u8>, MemoryAccessError> {
         let mut ret = Vec::with_capacity(length as usize);
-        for i in 0..length {
-            ret.push(self.read_byte(base_address + i)?)
+        for address in byte_addresses(base_address, length)? {
+            ret.push(self.read_byte(address)?)
         }
         Ok(ret)
     }
 
     pub fn read_u32(&self, address: u32) -> Result<u32, MemoryAccessError> {
         let bytes = self.read_bytes(address, 4)?;
-        Ok(u32::from_le_bytes(bytes.as_slice().try_into().unwrap()))
+        let bytes = bytes
+            .as_slice()
+            .try_into()
+            .map_err(|_| MemoryAccessError::BadAddress(address))?;
+        Ok(u32::from_le_bytes(bytes))
     }
 
     pub fn read_u16(&self, address: u32) -> Result<u16, MemoryAccessError> {
         let bytes = self.read_bytes(address, 2)?;
-        Ok(u16::from_le_bytes(bytes.as_slice().try_into().unwrap()))
+        let bytes = bytes
+            .as_slice()
+            .try_into()
+            .map_err(|_| MemoryAccessError::BadAddress(address))?;
+        Ok(u16::from_le_bytes(bytes))
     }
 
     pub fn write_bytes(
@@ -87,8 +116,36 @@ impl Memory {
         base_address: u32,
         bytes: &[u8],
     ) -> Result<(), MemoryAccessError> {
-        for i in 0..bytes.len() {
-            self.write_byte(base_address + i as u32, bytes[i])?;
+        let addresses = byte_addresses(base_address, bytes.len() as u32)?;
+        for (address, byte) in addresses.zip(bytes) {
+            self.write_byte(address, *byte)?;
+        }
+        Ok(())
+    }
+
+    // Write regardless of whether the page is read only, as a debugger can
+    pub fn force_write_bytes(
+        &mut self,
+        base_address: u32,
+        bytes: &[u8],
+    ) -> Result<(), MemoryAccessError> {
+        self.read_bytes(base_address, bytes.len() as u32)?;
+        for (address, byte) in byte_addresses(base_address, bytes.len() as u32)?.zip(bytes) {
+            let p = self.pages.iter_mut().find(|p| p.contains(address)).unwrap();
+            p.data[(address - p.vaddr) as usize] = *byte;
+        }
+        Ok(())
+    }
+
+    // Check that a write would succeed, without changing anything
+    pub fn check_write(&self, base_address: u32, length: u32) -> Result<(), MemoryAccessError> {
+        for address in byte_addresses(base_address, length)? {
+            let page = self.pages.iter().find(|p| p.contains(address));
+            match page {
+                Some(p) if p.write => {}
+                Some(_) => return Err(MemoryAccessError::ReadOnlyAddress(address)),
+                None => return Err(MemoryAccessError::BadAddress(address)),
+            }
         }
         Ok(())
     }
@@ -107,6 +164,11 @@ impl Display for MemoryAccessError {
                 "Attempt to write to read only memory address: {:#X}",
                 address
             ),
+            MemoryAccessError::Overlap(address) => writeln!(
+                f,
+                "Attempt to map memory over an existing page at: {:#X}",
+                address
+            ),
         }
     }
 }
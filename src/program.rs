@@ -0,0 +1,66 @@
+use crate::memory::Memory;
+use crate::symbols::DebugInfo;
+use anyhow::{anyhow, Context};
+use elf::types::PT_LOAD;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+// A guest image that has been loaded into memory, ready to be simulated
+#[derive(Clone)]
+pub struct Program {
+    pub memory: Memory,
+    pub entry: u32,
+    pub code_segments: Vec<(u32, u32)>, // Start and end of each executable PT_LOAD segment
+    pub debug_info: DebugInfo,
+}
+
+impl Program {
+    pub fn new(memory: Memory, entry: u32) -> Self {
+        Self {
+            memory,
+            entry,
+            code_segments: vec![],
+            debug_info: DebugInfo::default(),
+        }
+    }
+
+    pub fn from_elf<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let elf_file = elf::File::open_path(path.as_ref())
+            .map_err(|e| anyhow!(format!("{:?}", e)))
+            .with_context(|| "Reading elf binary")?;
+
+        let mut elf_file_bytes = Vec::new();
+        File::open(path.as_ref())
+            .and_then(|mut f| f.read_to_end(&mut elf_file_bytes))
+            .with_context(|| "Reading elf file contents")?;
+
+        let mut memory = Memory::default();
+        let mut code_segments = vec![];
+
+        // https://wiki.osdev.org/ELF#Loading_ELF_Binaries
+        for header in elf_file.phdrs.iter() {
+            if header.progtype == PT_LOAD {
+                let mut data = vec![0; header.memsz as usize];
+                let elf_offset = header.offset as usize;
+                let end = elf_offset + header.filesz as usize;
+
+                data[0..header.filesz as usize].copy_from_slice(&elf_file_bytes[elf_offset..end]);
+
+                let write = (header.flags.0 & 0b10) > 0;
+                if (header.flags.0 & 0b1) > 0 {
+                    code_segments.push((header.vaddr as u32, (header.vaddr + header.memsz) as u32));
+                }
+                memory
+                    .mmap(header.vaddr as u32, data, write)
+                    .map_err(|e| anyhow!(e.to_string()))
+                    .with_context(|| "Loading elf segments")?;
+            }
+        }
+
+        let mut program = Self::new(memory, elf_file.ehdr.entry as u32);
+        program.code_segments = code_segments;
+        program.debug_info = DebugInfo::from_elf(&elf_file);
+        Ok(program)
+    }
+}
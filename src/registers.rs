@@ -71,9 +71,9 @@ pub struct RegisterFile {
 }
 
 impl RegisterFile {
-    pub fn new() -> Self {
+    pub fn new(stack_pointer: u32) -> Self {
         let mut vals = HashMap::new();
-        vals.insert(SP, crate::_STACK);
+        vals.insert(SP, stack_pointer);
         vals.insert(LR, 0);
         vals.insert(PC, 0);
         vals.insert(CPSR, 0);
@@ -90,6 +90,13 @@ impl RegisterFile {
             .expect(format!("{} not supported", RegisterFile::reg_name(id)).as_str())
     }
 
+    // Every supported register, in a consistent order
+    pub fn ids(&self) -> Vec<RegId> {
+        let mut ids: Vec<RegId> = self.vals.keys().copied().collect();
+        ids.sort_by_key(|r| r.0);
+        ids
+    }
+
     pub fn write_by_id(&mut self, id: RegId, value: u32) {
         self.vals
             .insert(id, value)
@@ -0,0 +1,190 @@
+use crate::simulators::SimulationStats;
+use crate::SimulationBuilder;
+use serde::Serialize;
+use serde_json::Value;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StatsFormat {
+    Text,
+    Json,
+    Csv,
+}
+
+impl FromStr for StatsFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            "csv" => Ok(Self::Csv),
+            _ => Err("Couldn't match StatsFormat".to_string()),
+        }
+    }
+}
+
+// Rates calculated from the raw counters, so that scripts don't need to
+#[derive(Debug, Serialize)]
+pub struct Metrics {
+    pub ipc: f64,
+    pub cpi: f64,
+    pub branch_taken_rate: f64,
+    pub branch_misprediction_rate: f64,
+    pub instruction_cache_miss_rate: f64,
+    pub data_cache_miss_rate: f64,
+    pub l2_cache_miss_rate: f64,
+}
+
+impl Metrics {
+    pub fn new(stats: &SimulationStats) -> Self {
+        let branches = (stats.branches_taken + stats.branches_not_taken).max(1) as f64;
+        Self {
+            ipc: stats.instructions_executed as f64 / stats.total_cycles.max(1) as f64,
+            cpi: stats.total_cycles as f64 / stats.instructions_executed.max(1) as f64,
+            branch_taken_rate: stats.branches_taken as f64 / branches,
+            branch_misprediction_rate: stats.branch_mispredictions as f64 / branches,
+            instruction_cache_miss_rate: stats.caches.instruction_cache.miss_rate(),
+            data_cache_miss_rate: stats.caches.data_cache.miss_rate(),
+            l2_cache_miss_rate: stats.caches.l2_cache.miss_rate(),
+        }
+    }
+}
+
+// Everything about a single run, in a form that can be serialized
+#[derive(Serialize)]
+pub struct Report<'a> {
+    pub program: String,
+    pub simulator: String,
+    pub config: &'a SimulationBuilder,
+    pub stats: &'a SimulationStats,
+    pub metrics: Metrics,
+}
+
+impl<'a> Report<'a> {
+    pub fn new(program: &str, config: &'a SimulationBuilder, stats: &'a SimulationStats) -> Self {
+        Self {
+            program: program.to_string(),
+            simulator: config.build_simulator().name(),
+            config,
+            stats,
+            metrics: Metrics::new(stats),
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    // A header line and a line of values
+    pub fn to_csv(&self) -> String {
+        to_csv(std::slice::from_ref(self))
+    }
+
+    pub fn render(&self, format: StatsFormat) -> String {
+        match format {
+            StatsFormat::Text => format!("{}", self.stats),
+            StatsFormat::Json => self.to_json(),
+            StatsFormat::Csv => self.to_csv(),
+        }
+    }
+}
+
+// One row per report, the columns are the nested fields joined with dots e.g. `stats.caches.l2_cache.hits`
+pub fn to_csv(reports: &[Report]) -> String {
+    let rows: Vec<Vec<(String, String)>> = reports
+        .iter()
+        .map(|r| {
+            let mut row = vec![];
+            flatten("", &serde_json::to_value(r).unwrap(), &mut row);
+            row
+        })
+        .collect();
+    // Optional fields may be missing from some rows
+    let mut columns: Vec<&String> = vec![];
+    for (key, _) in rows.iter().flatten() {
+        if !columns.contains(&key) {
+            columns.push(key);
+        }
+    }
+    let mut csv = columns
+        .iter()
+        .map(|c| escape(c))
+        .collect::<Vec<String>>()
+        .join(",");
+    csv.push('\n');
+    for row in &rows {
+        let values: Vec<String> = columns
+            .iter()
+            .map(|c| {
+                row.iter()
+                    .find(|(key, _)| key == *c)
+                    .map(|(_, value)| escape(value))
+                    .unwrap_or_default()
+            })
+            .collect();
+        csv.push_str(&values.join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+fn flatten(prefix: &str, value: &Value, row: &mut Vec<(String, String)>) {
+    let key = |k: &str| {
+        if prefix.is_empty() {
+            k.to_string()
+        } else {
+            format!("{}.{}", prefix, k)
+        }
+    };
+    match value {
+        Value::Object(map) => {
+            for (k, v) in map {
+                flatten(&key(k), v, row);
+            }
+        }
+        Value::Array(values) => {
+            for (i, v) in values.iter().enumerate() {
+                flatten(&key(&i.to_string()), v, row);
+            }
+        }
+        Value::Null => row.push((prefix.to_string(), String::new())),
+        Value::String(s) => row.push((prefix.to_string(), s.clone())),
+        _ => row.push((prefix.to_string(), value.to_string())),
+    }
+}
+
+fn escape(field: &str) -> String {
+    if field.contains(&[',', '"', '\n'][..]) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::tests::sum_program;
+    use crate::SimulatorType;
+
+    #[test]
+    fn json_and_csv() {
+        let builder = SimulationBuilder::new(SimulatorType::Pipelined)
+            .l2_cache(Some("1024,4,16,fifo".parse().unwrap()));
+        let stats = builder.run(sum_program()).unwrap();
+        let report = Report::new("sum", &builder, &stats);
+
+        let json: Value = serde_json::from_str(&report.to_json()).unwrap();
+        assert_eq!(json["program"], "sum");
+        assert_eq!(json["stats"]["total_cycles"], stats.total_cycles);
+        assert_eq!(json["config"]["l2_cache"]["size"], 1024);
+
+        let csv = report.to_csv();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].split(',').count(), lines[1].split(',').count());
+        assert!(lines[0].contains("metrics.ipc"));
+        assert!(lines[0].contains("config.data_cache"));
+    }
+}
@@ -0,0 +1,142 @@
+use crate::cpu_state::cpi::CpiComponent;
+use crate::cpu_state::decode::DecodedInstruction;
+use crate::cpu_state::functional_units::Multiplier;
+use crate::cpu_state::{CpuState, UpdateResult};
+use crate::instructions::InstructionClass;
+use crate::registers::ids::PC;
+use crate::simulators::{execute_to_completion, fetch_and_decode, Simulator};
+use crate::DebugLevel;
+use std::collections::VecDeque;
+
+/*
+Reproduces the cycle counts of the Cortex-M0's 3-stage pipeline, as listed in the instruction
+set summary of the Technical Reference Manual (DDI 0432C, table 3-1). These are for zero wait
+state memory, the wait states of instruction fetches are added on top, whereas data accesses
+are assumed to always hit single cycle SRAM.
+ */
+pub struct CortexM0Simulator {
+    multiplier: Multiplier,
+    stalls: VecDeque<CpiComponent>, // Remaining cycles of the current instruction
+}
+
+impl CortexM0Simulator {
+    // The M0 is built with either a single cycle multiplier or a small 32 cycle iterative one
+    pub fn new(multiplier: Multiplier) -> Self {
+        Self {
+            multiplier,
+            stalls: Default::default(),
+        }
+    }
+
+    // Must be called before the instruction starts executing
+    fn cycles(&self, instr: &DecodedInstruction, taken: bool) -> u32 {
+        let writes_pc = instr.imp.dest_registers().contains(&PC);
+        match instr.imp.class() {
+            InstructionClass::Multiply => match self.multiplier {
+                Multiplier::Pipelined => 1,
+                Multiplier::Iterative => 32,
+            },
+            // pop {..., pc} is 4 + N, where N doesn't include the pc
+            InstructionClass::LoadStore if writes_pc => 3 + instr.imp.transfers(),
+            InstructionClass::LoadStore => 1 + instr.imp.transfers(),
+            // bl is the only 32-bit branch
+            InstructionClass::Branch if instr.imp.is_call() && instr.length == 4 => 4,
+            InstructionClass::Branch if taken => 3,
+            InstructionClass::Branch => 1,
+            // mov / add to the pc also refill the pipeline
+            InstructionClass::Alu if writes_pc => 3,
+            InstructionClass::Alu => 1,
+        }
+    }
+}
+
+impl Simulator for CortexM0Simulator {
+    /*
+    The instruction is fetched, decoded and executed in the first of its cycles, and the
+    pipeline is stalled for the rest of them
+    */
+    fn cycle(&mut self, state: &mut CpuState, debug_level: &DebugLevel) -> UpdateResult {
+        if let Some(cpi) = self.stalls.pop_front() {
+            return UpdateResult {
+                cpi,
+                ..Default::default()
+            };
+        }
+        let (mut result, wait_states) = fetch_and_decode(state);
+
+        let (not_taken, taken, class) = match &state.reservation_stations[0].instruction {
+            Some(instr) => (
+                self.cycles(instr, false),
+                self.cycles(instr, true),
+                instr.imp.class(),
+            ),
+            None => return result,
+        };
+        let executed = execute_to_completion(state, debug_level);
+        let (cycles, cpi) = if executed.exceptions_taken + executed.exception_returns > 0 {
+            (taken, CpiComponent::Exception)
+        } else if executed.branches_taken > 0 {
+            (taken, CpiComponent::ControlHazard)
+        } else {
+            match class {
+                InstructionClass::LoadStore => (not_taken, CpiComponent::MemoryLatency),
+                _ => (not_taken, CpiComponent::Execution),
+            }
+        };
+        self.stalls
+            .extend(vec![CpiComponent::MemoryLatency; wait_states as usize]);
+        self.stalls.extend(vec![cpi; cycles as usize - 1]);
+        result.merge(executed);
+
+        if result.pc_changed {
+            state.flush_pipeline();
+        }
+        result
+    }
+
+    fn stations(&self) -> usize {
+        1
+    }
+
+    fn name(&self) -> String {
+        "Cortex-M0 cycle timing simulator".to_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::tests::{call_program, sum_program};
+    use crate::registers::ids::R5;
+    use crate::{SimulationBuilder, SimulatorType};
+
+    #[test]
+    fn cortex_m0_timing() {
+        let builder = SimulationBuilder::new(SimulatorType::CortexM0);
+        // 2 movs, 10 x (adds, subs) and svc are single cycle, bne is 3 cycles when taken (9 times)
+        let stats = builder.run(sum_program()).unwrap();
+        assert_eq!(stats.instructions_executed, 32);
+        assert_eq!(stats.total_cycles, 2 + 10 * 2 + 9 * 3 + 1 + 1);
+
+        /*
+        Each iteration is 5 single cycle instructions, 2 x bl (4) and 2 calls of
+        push {lr} (1 + 1), muls, pop {pc} (4 + 0), followed by bne
+        */
+        for (multiplier, muls) in &[(Multiplier::Pipelined, 1), (Multiplier::Iterative, 32)] {
+            let mut simulation = builder
+                .clone()
+                .multiplier(*multiplier)
+                .build(call_program())
+                .unwrap();
+            simulation.run_until(&[]);
+            assert_eq!(simulation.state.registers.read_by_id(R5), 60);
+            let iteration = 5 + 2 * 4 + 2 * (2 + muls + 4);
+            assert_eq!(
+                simulation.stats.total_cycles,
+                2 + 5 * iteration + 4 * 3 + 1 + 2,
+                "{:?}",
+                multiplier
+            );
+        }
+    }
+}
@@ -0,0 +1,308 @@
+use crate::cpu_state::cpi::CpiComponent;
+use crate::cpu_state::decode::DecodedInstruction;
+use crate::cpu_state::{CpuState, HazardStats, UpdateResult};
+use crate::instructions::{InstructionClass, Latencies};
+use crate::registers::ids::{CPSR, PC};
+use crate::simulators::{execute_to_completion, fetch_and_decode, Simulator};
+use crate::DebugLevel;
+use capstone::arch::arm::ArmCC;
+use capstone::RegId;
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::str::FromStr;
+
+// Paths results can take to the start of EX, otherwise they are read from the register file in ID
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Forwarding {
+    pub ex_to_ex: bool,  // From the EX/MEM latch, so not loaded values
+    pub mem_to_ex: bool, // From the MEM/WB latch
+}
+
+// Format: none, ex, mem or full (ex,mem)
+impl FromStr for Forwarding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut forwarding = Self {
+            ex_to_ex: false,
+            mem_to_ex: false,
+        };
+        for path in s.to_lowercase().split(',') {
+            match path.trim() {
+                "none" => {}
+                "ex" => forwarding.ex_to_ex = true,
+                "mem" => forwarding.mem_to_ex = true,
+                "full" => {
+                    forwarding.ex_to_ex = true;
+                    forwarding.mem_to_ex = true;
+                }
+                _ => return Err(format!("Unknown forwarding path {}", path)),
+            }
+        }
+        Ok(forwarding)
+    }
+}
+
+// Where branches are resolved, the earlier the fewer instructions are fetched down the wrong path
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum BranchStage {
+    Decode,
+    Execute,
+    Memory,
+}
+
+impl FromStr for BranchStage {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "id" => Ok(Self::Decode),
+            "ex" => Ok(Self::Execute),
+            "mem" => Ok(Self::Memory),
+            _ => Err("Couldn't match BranchStage".to_string()),
+        }
+    }
+}
+
+// The cycle an instruction entered each stage, and how long it spent in the multi-cycle ones
+#[derive(Default, Debug, Clone, Copy)]
+struct Timing {
+    fetch: u64,
+    fetch_cycles: u64, // Including wait states
+    decode: u64,
+    execute: u64,
+    execute_cycles: u64,
+    memory: u64,
+    memory_cycles: u64, // One per register transferred
+    writeback: u64,
+    is_load: bool,
+}
+
+impl Timing {
+    // First cycle an instruction in EX can use the result
+    fn available(&self, forwarding: Forwarding) -> u64 {
+        // The register file is written in the first half of WB and read in the second half of ID
+        let mut available = self.writeback + 1;
+        if forwarding.mem_to_ex {
+            available = available.min(self.memory + self.memory_cycles);
+        }
+        if forwarding.ex_to_ex && !self.is_load {
+            available = available.min(self.execute + self.execute_cycles);
+        }
+        available
+    }
+
+    // First cycle the correct target of a mispredicted branch can be fetched
+    fn resolved(&self, stage: BranchStage) -> u64 {
+        match stage {
+            _ if self.is_load => self.memory + self.memory_cycles, // pop {pc}
+            BranchStage::Decode => self.decode + 1,
+            BranchStage::Execute => self.execute + self.execute_cycles,
+            BranchStage::Memory => self.memory + self.memory_cycles,
+        }
+    }
+}
+
+/*
+The classic IF / ID / EX / MEM / WB pipeline. Each instruction is executed when it reaches WB,
+the cycles it spends in each stage are worked out when it is fetched from the hazards with
+the instructions ahead of it. Branches are predicted by the branch predictor, and operands are
+checked when entering EX, including those of branches resolved in ID.
+ */
+pub struct FiveStageSimulator {
+    forwarding: Forwarding,
+    branch_stage: BranchStage,
+    cycle: u64,
+    previous: Timing,
+    producers: HashMap<RegId, Timing>, // The last instruction to write each register
+    redirect: u64,                     // Fetch must wait for the last mispredicted branch
+    redirect_exception: bool,          // Or exception entry or return, rather than a branch
+    pending: Option<Timing>,           // The instruction waiting to reach WB
+    stalls: VecDeque<CpiComponent>,    // What each cycle until then is attributed to
+}
+
+impl FiveStageSimulator {
+    pub fn new(forwarding: Forwarding, branch_stage: BranchStage) -> Self {
+        Self {
+            forwarding,
+            branch_stage,
+            cycle: 0,
+            previous: Timing::default(),
+            producers: Default::default(),
+            redirect: 0,
+            redirect_exception: false,
+            pending: None,
+            stalls: Default::default(),
+        }
+    }
+
+    // Must be called before the instruction starts executing
+    fn schedule(
+        &mut self,
+        instr: &DecodedInstruction,
+        wait_states: u32,
+        latencies: Latencies,
+        hazards: &mut HazardStats,
+    ) -> Timing {
+        let previous = self.previous;
+        let class = instr.imp.class();
+        let mut timing = Timing {
+            fetch_cycles: wait_states as u64 + 1,
+            execute_cycles: latencies.of(class) as u64,
+            memory_cycles: match class {
+                InstructionClass::LoadStore => instr.imp.transfers() as u64,
+                _ => 1,
+            },
+            is_load: instr.imp.reads_memory(),
+            ..Default::default()
+        };
+
+        // Each stage holds one instruction, which can only move on once the next is free
+        let fetch = (previous.fetch + previous.fetch_cycles).max(previous.decode);
+        timing.fetch = fetch.max(self.redirect);
+        if self.redirect_exception {
+            hazards.exception = hazards.exception + (timing.fetch - fetch);
+        } else {
+            hazards.control = hazards.control + (timing.fetch - fetch);
+        }
+        timing.decode = (timing.fetch + timing.fetch_cycles).max(previous.execute);
+
+        let execute = (timing.decode + 1).max(previous.memory);
+        hazards.structural = hazards.structural + (execute - (timing.decode + 1));
+        let mut sources = instr.imp.source_registers();
+        if !matches!(instr.cc, ArmCC::ARM_CC_AL) {
+            sources.insert(CPSR);
+        }
+        let (ready, load) = sources
+            .iter()
+            .filter(|r| **r != PC)
+            .filter_map(|r| self.producers.get(r))
+            .map(|p| (p.available(self.forwarding), p.is_load))
+            .max_by_key(|(available, _)| *available)
+            .unwrap_or((0, false));
+        timing.execute = execute.max(ready);
+        if load {
+            hazards.load_use = hazards.load_use + (timing.execute - execute);
+        } else {
+            hazards.data = hazards.data + (timing.execute - execute);
+        }
+
+        let memory = timing.execute + timing.execute_cycles;
+        timing.memory = memory.max(previous.writeback);
+        hazards.structural = hazards.structural + (timing.memory - memory);
+        timing.writeback = timing.memory + timing.memory_cycles;
+
+        for r in instr.imp.dest_registers() {
+            if r != PC {
+                self.producers.insert(r, timing);
+            }
+        }
+        self.previous = timing;
+        timing
+    }
+}
+
+impl Simulator for FiveStageSimulator {
+    fn cycle(&mut self, state: &mut CpuState, debug_level: &DebugLevel) -> UpdateResult {
+        let now = self.cycle;
+        self.cycle = self.cycle + 1;
+        let mut result = UpdateResult::default();
+        if self.pending.is_none() {
+            let (fetched, wait_states) = fetch_and_decode(state);
+            result = fetched;
+            let latencies = state.config.latencies;
+            match &state.reservation_stations[0].instruction {
+                Some(instr) => {
+                    let timing = self.schedule(instr, wait_states, latencies, &mut result.hazards);
+                    self.pending = Some(timing);
+                    // The cycles since the last writeback, anything left over is filling the pipeline
+                    let hazards = result.hazards;
+                    let causes = [
+                        (CpiComponent::ControlHazard, hazards.control),
+                        (CpiComponent::Exception, hazards.exception),
+                        (CpiComponent::MemoryLatency, wait_states as u64),
+                        (CpiComponent::Execution, hazards.structural),
+                        (CpiComponent::OperandWait, hazards.data + hazards.load_use),
+                    ];
+                    self.stalls = causes
+                        .iter()
+                        .flat_map(|(cpi, cycles)| vec![*cpi; *cycles as usize])
+                        .chain(std::iter::repeat(CpiComponent::FetchStarved))
+                        .take((timing.writeback - now) as usize)
+                        .collect();
+                }
+                None => return result,
+            }
+        }
+        let timing = self.pending.unwrap();
+        if now < timing.writeback {
+            result.cpi = self.stalls.pop_front().unwrap_or_default();
+            return result;
+        }
+
+        result.merge(execute_to_completion(state, debug_level));
+        self.pending = None;
+        if result.pc_changed {
+            self.redirect = timing.resolved(self.branch_stage);
+            self.redirect_exception = result.exceptions_taken + result.exception_returns > 0;
+            state.flush_pipeline();
+        }
+        result
+    }
+
+    fn stations(&self) -> usize {
+        1
+    }
+
+    fn name(&self) -> String {
+        format!(
+            "Five stage pipelined simulator (forwarding {:?}, branches resolved in {:?})",
+            self.forwarding, self.branch_stage
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::tests::{memory_program, sum_program};
+    use crate::registers::ids::R0;
+    use crate::{SimulationBuilder, SimulatorType};
+
+    #[test]
+    fn five_stage_hazards() {
+        let builder = |forwarding: &str, branch_stage: &str| {
+            SimulationBuilder::new(SimulatorType::FiveStage)
+                .forwarding(forwarding.parse().unwrap())
+                .branch_stage(branch_stage.parse().unwrap())
+        };
+        let run = |builder: SimulationBuilder, program| {
+            let mut simulation = builder.build(program).unwrap();
+            simulation.run_until(&[]);
+            assert_eq!(simulation.state.registers.read_by_id(R0), 55);
+            simulation.stats
+        };
+        assert!("ex,fpu".parse::<Forwarding>().is_err());
+
+        // 33 instructions and 4 cycles to fill the pipeline, plus 2 bubbles for each taken bne
+        let full = run(builder("full", "ex"), sum_program());
+        assert_eq!(full.total_cycles, 33 + 4 + 9 * 2);
+        assert_eq!(full.hazards.control, 9 * 2);
+        assert_eq!(full.hazards.data, 0);
+        let mem = run(builder("mem", "ex"), sum_program());
+        let none = run(builder("none", "ex"), sum_program());
+        assert!(mem.hazards.data > 0);
+        assert!(none.hazards.data > mem.hazards.data);
+        assert!(none.total_cycles > mem.total_cycles);
+
+        let id = run(builder("full", "id"), sum_program());
+        let memory = run(builder("full", "mem"), sum_program());
+        assert_eq!(id.hazards.control, 9);
+        assert_eq!(memory.hazards.control, 9 * 3);
+
+        // ldr r3, [r2] then adds r0, r0, r3 each iteration
+        let loads = run(builder("full", "ex"), memory_program());
+        assert_eq!(loads.hazards.load_use, 10);
+        assert_eq!(loads.hazards.structural, 0);
+    }
+}
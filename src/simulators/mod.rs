@@ -1,24 +1,76 @@
+pub mod cortex_m0;
+pub mod five_stage;
 pub mod non_pipelined;
 pub mod out_of_order;
 pub mod pipelined;
+pub mod simulation;
 
-use crate::cpu_state::UpdateResult;
-use crate::memory::Memory;
+use crate::cache::hierarchy::HierarchyStats;
+use crate::cpu_state::cpi::CpiStack;
+use crate::cpu_state::{CpuState, HazardStats, UpdateResult};
 use crate::DebugLevel;
+use serde::Serialize;
 use std::fmt::{Display, Formatter};
 
 pub trait Simulator {
-    fn run(&self, memory: Memory, entry: u32, debug_level: &DebugLevel) -> SimulationStats;
+    // Advance the CPU state by a single clock cycle
+    fn cycle(&mut self, state: &mut CpuState, debug_level: &DebugLevel) -> UpdateResult;
+    // Number of reservation stations the CPU state must be created with
+    fn stations(&self) -> usize;
     fn name(&self) -> String;
 }
 
-#[derive(Default, Debug)]
+/*
+For simulators which time each instruction themselves, fetch and decode the next instruction
+into the only station, and return the wait states of the fetch
+ */
+pub(crate) fn fetch_and_decode(state: &mut CpuState) -> (UpdateResult, u32) {
+    let fetch = state.fetch();
+    let mut result = state.apply_stages(fetch, None, vec![None]);
+    let wait_states = state.fetch_stall;
+    state.fetch_stall = 0;
+    let decode = state.decode();
+    result.merge(state.apply_stages(None, decode, vec![None]));
+    (result, wait_states)
+}
+
+// Execute the instruction in the only station until it has been committed
+pub(crate) fn execute_to_completion(
+    state: &mut CpuState,
+    debug_level: &DebugLevel,
+) -> UpdateResult {
+    let mut result = UpdateResult::default();
+    loop {
+        let station = &state.reservation_stations[0];
+        if station.instruction.is_none() && station.busy == 0 && state.in_flight.is_empty() {
+            return result;
+        }
+        let execute = state.execute_station(debug_level, station);
+        result.merge(state.apply_stages(None, None, vec![execute]));
+    }
+}
+
+#[derive(Default, Debug, Serialize)]
 pub struct SimulationStats {
-    instructions_executed: u64,
-    instructions_skipped: u64,
-    total_cycles: u64,
-    branches_not_taken: u64,
-    branches_taken: u64,
+    pub instructions_executed: u64,
+    pub instructions_skipped: u64,
+    pub total_cycles: u64,
+    pub branches_not_taken: u64,
+    pub branches_taken: u64,
+    pub branch_mispredictions: u64,
+    pub return_stack_hits: u64,
+    pub return_stack_misses: u64,
+    pub instructions_squashed: u64,
+    pub rename_stalls: u64,
+    pub front_end_stalls: u64,
+    pub unit_stalls: u64,
+    pub loads_forwarded: u64,
+    pub memory_order_violations: u64,
+    pub exceptions_taken: u64,
+    pub exception_returns: u64,
+    pub caches: HierarchyStats,
+    pub hazards: HazardStats,
+    pub cpi_stack: CpiStack,
 }
 
 impl SimulationStats {
@@ -27,6 +79,21 @@ impl SimulationStats {
         self.instructions_skipped = self.instructions_skipped + from.instructions_skipped as u64;
         self.branches_taken = self.branches_taken + from.branches_taken as u64;
         self.branches_not_taken = self.branches_not_taken + from.branches_not_taken as u64;
+        self.branch_mispredictions = self.branch_mispredictions + from.branch_mispredictions as u64;
+        self.return_stack_hits = self.return_stack_hits + from.return_stack_hits as u64;
+        self.return_stack_misses = self.return_stack_misses + from.return_stack_misses as u64;
+        self.instructions_squashed = self.instructions_squashed + from.instructions_squashed as u64;
+        self.rename_stalls = self.rename_stalls + from.rename_stalls as u64;
+        self.front_end_stalls = self.front_end_stalls + from.front_end_stalls as u64;
+        self.unit_stalls = self.unit_stalls + from.unit_stalls as u64;
+        self.loads_forwarded = self.loads_forwarded + from.loads_forwarded as u64;
+        self.memory_order_violations =
+            self.memory_order_violations + from.memory_order_violations as u64;
+        self.exceptions_taken = self.exceptions_taken + from.exceptions_taken as u64;
+        self.exception_returns = self.exception_returns + from.exception_returns as u64;
+        self.caches.add(&from.caches);
+        self.hazards.add(&from.hazards);
+        self.cpi_stack.add(from.cpi);
     }
 }
 
@@ -39,18 +106,113 @@ impl Display for SimulationStats {
             self.instructions_skipped,
             self.instructions_executed + self.instructions_skipped
         )?;
+        writeln!(
+            f,
+            "Number of instructions squashed: {}",
+            self.instructions_squashed
+        )?;
         writeln!(f, "Number of cycles: {}", self.total_cycles)?;
         writeln!(
             f,
             "Number of instructions per cycle: {:.3}",
             self.instructions_executed as f64 / self.total_cycles as f64
         )?;
+        writeln!(f, "CPI stack:")?;
+        for (name, cycles) in self.cpi_stack.components().iter() {
+            if *cycles > 0 {
+                writeln!(
+                    f,
+                    "    {:<18} {:.3} ({:.1}% of cycles)",
+                    name,
+                    *cycles as f64 / self.instructions_executed.max(1) as f64,
+                    100.0 * *cycles as f64 / self.total_cycles.max(1) as f64
+                )?;
+            }
+        }
         writeln!(f, "Number of branches taken: {}", self.branches_taken)?;
         writeln!(
             f,
             "Number of branches not taken: {}",
             self.branches_not_taken
         )?;
+        let branches = self.branches_taken + self.branches_not_taken;
+        writeln!(
+            f,
+            "Number of branch mispredictions: {} ({:.1}% accuracy)",
+            self.branch_mispredictions,
+            100.0 * (branches - self.branch_mispredictions.min(branches)) as f64
+                / branches.max(1) as f64
+        )?;
+        writeln!(
+            f,
+            "Number of return address stack hits: {} (+ {} misses)",
+            self.return_stack_hits, self.return_stack_misses
+        )?;
+        writeln!(
+            f,
+            "Number of cycles stalled waiting for a physical register: {}",
+            self.rename_stalls
+        )?;
+        writeln!(
+            f,
+            "Number of cycles stalled waiting for the front end: {}",
+            self.front_end_stalls
+        )?;
+        writeln!(
+            f,
+            "Number of cycles stalled waiting for a functional unit: {}",
+            self.unit_stalls
+        )?;
+        writeln!(
+            f,
+            "Number of loads forwarded from a store: {}",
+            self.loads_forwarded
+        )?;
+        writeln!(
+            f,
+            "Number of memory order violations: {}",
+            self.memory_order_violations
+        )?;
+        if self.exceptions_taken > 0 {
+            writeln!(
+                f,
+                "Number of exceptions taken: {} ({} returned from)",
+                self.exceptions_taken, self.exception_returns
+            )?;
+        }
+        if self.hazards.total() > 0 {
+            writeln!(
+                f,
+                "Number of pipeline stall cycles: {} data, {} load-use, {} structural, {} control, {} exception",
+                self.hazards.data,
+                self.hazards.load_use,
+                self.hazards.structural,
+                self.hazards.control,
+                self.hazards.exception
+            )?;
+        }
+        for (name, cache) in &[
+            ("L1 instruction", &self.caches.instruction_cache),
+            ("L1 data", &self.caches.data_cache),
+            ("L2", &self.caches.l2_cache),
+        ] {
+            if cache.hits + cache.misses > 0 {
+                writeln!(
+                    f,
+                    "{} cache: {} hits, {} misses ({:.1}% miss rate), {} writebacks",
+                    name,
+                    cache.hits,
+                    cache.misses,
+                    100.0 * cache.miss_rate(),
+                    cache.writebacks
+                )?;
+            }
+        }
+        writeln!(
+            f,
+            "Number of main memory accesses: {} ({} row buffer hits)",
+            self.caches.memory_accesses, self.caches.row_buffer_hits
+        )?;
         Ok(())
     }
 }
@@ -1,42 +1,69 @@
-use crate::cpu_state::CpuState;
-use crate::memory::Memory;
-use crate::simulators::{SimulationStats, Simulator};
+use crate::cpu_state::{CpuState, UpdateResult};
+use crate::simulators::Simulator;
 use crate::DebugLevel;
 
-pub struct NonPipelinedSimulator {}
+enum Stage {
+    Fetch,
+    Decode,
+    Execute,
+}
 
-impl Simulator for NonPipelinedSimulator {
-    fn run(&self, memory: Memory, entry: u32, debug_level: &DebugLevel) -> SimulationStats {
-        let mut state = CpuState::new(memory, entry, 1);
-        let mut stats = SimulationStats::default();
-        loop {
-            stats.total_cycles = stats.total_cycles + 1;
-            let fetch = state.fetch();
-            state.apply_stages(fetch, None, vec![None]);
+// Each instruction is fetched, decoded and then executed to completion before the next is fetched
+pub struct NonPipelinedSimulator {
+    stage: Stage,
+}
 
-            stats.total_cycles = stats.total_cycles + 1;
-            let decode = state.decode();
-            state.apply_stages(None, decode, vec![None]);
+impl NonPipelinedSimulator {
+    pub fn new() -> Self {
+        Self {
+            stage: Stage::Fetch,
+        }
+    }
+}
 
-            while state
-                .reservation_stations
-                .first()
-                .unwrap()
-                .instruction
-                .is_some()
-            {
-                stats.total_cycles = stats.total_cycles + 1;
-                let execute = state
-                    .execute_station(&debug_level, state.reservation_stations.first().unwrap());
-                let result = state.apply_stages(None, None, vec![execute]);
-                stats.update(&result);
-            }
+impl Default for NonPipelinedSimulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
-            if state.should_terminate {
-                break;
+impl Simulator for NonPipelinedSimulator {
+    fn cycle(&mut self, state: &mut CpuState, debug_level: &DebugLevel) -> UpdateResult {
+        let result = match self.stage {
+            Stage::Fetch => {
+                let fetch = state.fetch();
+                self.stage = Stage::Decode;
+                state.apply_stages(fetch, None, vec![None])
+            }
+            Stage::Decode => {
+                let decode = state.decode();
+                // Otherwise still waiting for the instruction cache
+                if decode.is_some() {
+                    self.stage = Stage::Execute;
+                }
+                state.apply_stages(None, decode, vec![None])
+            }
+            Stage::Execute => {
+                let execute =
+                    state.execute_station(debug_level, state.reservation_stations.first().unwrap());
+                state.apply_stages(None, None, vec![execute])
             }
+        };
+        if result.pc_changed {
+            state.flush_pipeline();
         }
-        stats
+        // Only fetch the next instruction once the station has finished executing
+        if let Stage::Execute = self.stage {
+            let station = state.reservation_stations.first().unwrap();
+            if station.instruction.is_none() && station.busy == 0 && state.in_flight.is_empty() {
+                self.stage = Stage::Fetch;
+            }
+        }
+        result
+    }
+
+    fn stations(&self) -> usize {
+        1
     }
 
     fn name(&self) -> String {
@@ -0,0 +1,187 @@
+use crate::cache::hierarchy::AccessKind;
+use crate::cpu_state::{CpuState, UpdateResult};
+use crate::error::SimulationError;
+use crate::registers::ids::PC;
+use crate::simulators::{SimulationStats, Simulator};
+use crate::DebugLevel;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StopCondition {
+    // The next instruction to be executed is at this address
+    Address(u32),
+    // The total number of cycles has reached this value
+    Cycle(u64),
+    // The number of instructions retired (executed + skipped) has reached this value
+    InstructionCount(u64),
+    // A retired instruction accessed memory in this range
+    Watch {
+        kind: WatchKind,
+        address: u32,
+        length: u32,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WatchKind {
+    Write,
+    Read,
+    Access, // Either
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StopReason {
+    Condition(StopCondition),
+    Terminated,
+}
+
+// A simulator together with the CPU state it is running, which can be advanced step by step
+pub struct Simulation {
+    simulator: Box<dyn Simulator>,
+    pub state: CpuState,
+    pub stats: SimulationStats,
+    debug_level: DebugLevel,
+}
+
+impl Simulation {
+    // The state must have been created with the number of stations the simulator requires
+    pub fn new(simulator: Box<dyn Simulator>, state: CpuState, debug_level: DebugLevel) -> Self {
+        assert_eq!(state.reservation_stations.len(), simulator.stations());
+        Self {
+            simulator,
+            state,
+            stats: SimulationStats::default(),
+            debug_level,
+        }
+    }
+
+    pub fn name(&self) -> String {
+        self.simulator.name()
+    }
+
+    pub fn terminated(&self) -> bool {
+        self.state.should_terminate
+    }
+
+    pub fn instructions_retired(&self) -> u64 {
+        self.stats.instructions_executed + self.stats.instructions_skipped
+    }
+
+    // The address of the next instruction to be committed in program order
+    pub fn pc(&self) -> u32 {
+        self.state.registers.read_by_id(PC) & 0xFFFFFFFE
+    }
+
+    // Advance the simulation by a single clock cycle
+    pub fn step_cycle(&mut self) -> UpdateResult {
+        if self.terminated() {
+            return UpdateResult::default();
+        }
+        self.stats.total_cycles = self.stats.total_cycles + 1;
+        if let Some(profiler) = &mut self.state.profiler {
+            profiler.next_cycle();
+        }
+        let result = self.simulator.cycle(&mut self.state, &self.debug_level);
+        self.stats.update(&result);
+        if let Some(trace) = &mut self.state.pipeline_trace {
+            trace.next_cycle();
+        }
+        if let Some(trace) = &mut self.state.execution_trace {
+            trace.next_cycle();
+        }
+        result
+    }
+
+    // Advance the simulation until at least one more instruction has been retired
+    pub fn step_instruction(&mut self) -> StopReason {
+        let target = self.instructions_retired() + 1;
+        self.run_until(&[StopCondition::InstructionCount(target)])
+    }
+
+    // Run until any of the conditions are met, or the program terminates
+    pub fn run_until(&mut self, conditions: &[StopCondition]) -> StopReason {
+        loop {
+            if self.terminated() {
+                return StopReason::Terminated;
+            }
+            let result = self.step_cycle();
+            let retired = result.instructions_executed + result.instructions_skipped > 0;
+            for condition in conditions {
+                let met = match *condition {
+                    // Only checked when an instruction retires, so that a simulation
+                    // stopped at an address is able to continue past it
+                    StopCondition::Address(address) => retired && self.pc() == address & 0xFFFFFFFE,
+                    StopCondition::Cycle(cycle) => self.stats.total_cycles >= cycle,
+                    StopCondition::InstructionCount(count) => self.instructions_retired() >= count,
+                    StopCondition::Watch {
+                        kind,
+                        address,
+                        length,
+                    } => result.accesses.iter().any(|(k, a, l)| {
+                        let matches = match kind {
+                            WatchKind::Write => *k == AccessKind::Store,
+                            WatchKind::Read => *k == AccessKind::Load,
+                            WatchKind::Access => true,
+                        };
+                        // In 64 bits, as the range may end at the top of the address space
+                        let (a, l) = (*a as u64, *l as u64);
+                        let (address, length) = (address as u64, length as u64);
+                        matches && a < address + length && address < a + l
+                    }),
+                };
+                if met {
+                    return StopReason::Condition(*condition);
+                }
+            }
+        }
+    }
+
+    // Run the program to completion, or until it faults
+    pub fn run(mut self) -> Result<SimulationStats, SimulationError> {
+        self.run_until(&[]);
+        match self.state.error {
+            Some(error) => Err(error),
+            None => Ok(self.stats),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::tests::sum_program;
+    use crate::registers::ids::{R0, R1};
+    use crate::SimulationBuilder;
+
+    #[test]
+    fn stepping() {
+        for sim in &["scalar", "pipelined", "outoforder"] {
+            let mut simulation = SimulationBuilder::new(sim.parse().unwrap())
+                .build(sum_program())
+                .unwrap();
+            assert_eq!(simulation.pc(), 0x1000);
+
+            // movs r0, #0
+            simulation.step_instruction();
+            assert_eq!(simulation.instructions_retired(), 1, "{}", sim);
+
+            // Stop each time the loop body is about to be executed
+            let bne = StopCondition::Address(0x1004);
+            simulation.run_until(&[bne]);
+            assert_eq!(simulation.state.registers.read_by_id(R1), 10, "{}", sim);
+            assert_eq!(
+                simulation.run_until(&[bne]),
+                StopReason::Condition(bne),
+                "{}",
+                sim
+            );
+            assert_eq!(simulation.state.registers.read_by_id(R0), 10, "{}", sim);
+            assert_eq!(simulation.state.registers.read_by_id(R1), 9, "{}", sim);
+
+            let cycle = StopCondition::Cycle(simulation.stats.total_cycles + 3);
+            assert_eq!(simulation.run_until(&[cycle]), StopReason::Condition(cycle));
+
+            assert_eq!(simulation.run_until(&[]), StopReason::Terminated);
+            assert_eq!(simulation.state.registers.read_by_id(R0), 55, "{}", sim);
+        }
+    }
+}
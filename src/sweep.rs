@@ -0,0 +1,216 @@
+use crate::branch_predictor::PredictorType;
+use crate::cache::CacheConfig;
+use crate::error::SimulationError;
+use crate::program::Program;
+use crate::report::Report;
+use crate::simulators::SimulationStats;
+use crate::{SimulationBuilder, SimulatorType};
+use rayon::prelude::*;
+use std::fmt::{Display, Formatter};
+
+// The values to try for each parameter, an empty list keeps the value from the base configuration
+#[derive(Default, Clone)]
+pub struct SweepGrid {
+    pub simulators: Vec<SimulatorType>,
+    pub units: Vec<usize>,
+    pub rob_entries: Vec<usize>,
+    pub physical_registers: Vec<usize>,
+    pub predictors: Vec<PredictorType>,
+    pub instruction_caches: Vec<Option<CacheConfig>>,
+    pub data_caches: Vec<Option<CacheConfig>>,
+    pub l2_caches: Vec<Option<CacheConfig>>,
+}
+
+#[derive(Clone)]
+pub struct Configuration {
+    pub label: String, // Only the swept parameters, e.g. "sim=OutOfOrder units=2"
+    pub builder: SimulationBuilder,
+}
+
+impl SweepGrid {
+    // Every combination of the parameters, the first (the baseline) uses the first value of each
+    pub fn configurations(&self, base: &SimulationBuilder) -> Vec<Configuration> {
+        let mut configurations = vec![Configuration {
+            label: String::new(),
+            builder: base.clone(),
+        }];
+        expand(&mut configurations, "sim", &self.simulators, |b, v| {
+            b.simulator(v)
+        });
+        expand(&mut configurations, "units", &self.units, |b, v| b.units(v));
+        expand(&mut configurations, "rob", &self.rob_entries, |b, v| {
+            b.rob_entries(v)
+        });
+        expand(
+            &mut configurations,
+            "prf",
+            &self.physical_registers,
+            |b, v| b.physical_registers(v),
+        );
+        expand(
+            &mut configurations,
+            "predictor",
+            &self.predictors,
+            |b, v| b.predictor(v),
+        );
+        let caches = [
+            ("icache", &self.instruction_caches),
+            ("dcache", &self.data_caches),
+            ("l2", &self.l2_caches),
+        ];
+        for (i, (name, values)) in caches.iter().enumerate() {
+            let values: Vec<CacheLabel> = values.iter().map(|c| CacheLabel(*c)).collect();
+            expand(&mut configurations, name, &values, |b, v| match i {
+                0 => b.instruction_cache(v.0),
+                1 => b.data_cache(v.0),
+                _ => b.l2_cache(v.0),
+            });
+        }
+        if configurations.len() == 1 {
+            configurations[0].label = "base".to_string();
+        }
+        configurations
+    }
+}
+
+fn expand<T, F>(configurations: &mut Vec<Configuration>, name: &str, values: &[T], apply: F)
+where
+    T: std::fmt::Debug + Clone,
+    F: Fn(SimulationBuilder, T) -> SimulationBuilder,
+{
+    if values.is_empty() {
+        return;
+    }
+    let previous = std::mem::take(configurations);
+    for c in previous {
+        for v in values {
+            let label = format!("{}={:?}", name, v);
+            configurations.push(Configuration {
+                label: if c.label.is_empty() {
+                    label
+                } else {
+                    format!("{} {}", c.label, label)
+                },
+                builder: apply(c.builder.clone(), v.clone()),
+            });
+        }
+    }
+}
+
+// Caches are labelled in the same format they are given on the command line
+#[derive(Clone)]
+struct CacheLabel(Option<CacheConfig>);
+
+impl std::fmt::Debug for CacheLabel {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match &self.0 {
+            Some(c) => write!(f, "{},{},{}", c.size, c.associativity, c.line_size),
+            None => write!(f, "none"),
+        }
+    }
+}
+
+pub struct SweepResult {
+    pub program: String,
+    pub configuration: Configuration,
+    pub stats: SimulationStats,
+    pub speedup: f64, // Relative to the baseline configuration on the same program
+}
+
+pub struct Sweep {
+    pub results: Vec<SweepResult>,
+}
+
+impl Sweep {
+    // Runs every program on every configuration in parallel, stopping at the first fault
+    pub fn run(
+        programs: &[(String, Program)],
+        configurations: &[Configuration],
+    ) -> Result<Self, SimulationError> {
+        let runs: Vec<(usize, usize)> = (0..programs.len())
+            .flat_map(|p| (0..configurations.len()).map(move |c| (p, c)))
+            .collect();
+        let stats: Vec<SimulationStats> = runs
+            .par_iter()
+            .map(|(p, c)| configurations[*c].builder.run(programs[*p].1.clone()))
+            .collect::<Result<_, _>>()?;
+        let mut results: Vec<SweepResult> = vec![];
+        for ((p, c), stats) in runs.into_iter().zip(stats) {
+            let baseline = if c == 0 {
+                stats.total_cycles
+            } else {
+                results[results.len() - c].stats.total_cycles
+            };
+            results.push(SweepResult {
+                program: programs[p].0.clone(),
+                configuration: configurations[c].clone(),
+                speedup: baseline as f64 / stats.total_cycles.max(1) as f64,
+                stats,
+            });
+        }
+        Ok(Self { results })
+    }
+
+    pub fn reports(&self) -> Vec<Report<'_>> {
+        self.results
+            .iter()
+            .map(|r| Report::new(&r.program, &r.configuration.builder, &r.stats))
+            .collect()
+    }
+}
+
+// A markdown table, ready to be pasted into experiments.md
+impl Display for Sweep {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "| Program | Configuration | Cycles | IPC | Speedup |")?;
+        writeln!(f, "|---------|---------------|--------|-----|---------|")?;
+        for r in &self.results {
+            writeln!(
+                f,
+                "| {} | {} | {} | {:.3} | {:.3} |",
+                r.program,
+                r.configuration.label,
+                r.stats.total_cycles,
+                r.stats.instructions_executed as f64 / r.stats.total_cycles.max(1) as f64,
+                r.speedup
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::tests::sum_program;
+
+    #[test]
+    fn sweep() {
+        let grid = SweepGrid {
+            simulators: vec![SimulatorType::Scalar, SimulatorType::Pipelined],
+            data_caches: vec![None, Some("64,1,16".parse().unwrap())],
+            ..Default::default()
+        };
+        let configurations = grid.configurations(&SimulationBuilder::default());
+        assert_eq!(configurations.len(), 4);
+        assert_eq!(configurations[0].label, "sim=Scalar dcache=none");
+        assert_eq!(configurations[3].label, "sim=Pipelined dcache=64,1,16");
+
+        let programs = vec![
+            ("a".to_string(), sum_program()),
+            ("b".to_string(), sum_program()),
+        ];
+        let sweep = Sweep::run(&programs, &configurations).unwrap();
+        assert_eq!(sweep.results.len(), 8);
+        for r in &sweep.results[4..] {
+            assert_eq!(r.program, "b");
+        }
+        assert_eq!(sweep.results[4].speedup, 1.0);
+        assert!(sweep.results[6].speedup > 1.0);
+        assert_eq!(
+            sweep.results[2].stats.total_cycles,
+            sweep.results[6].stats.total_cycles
+        );
+        assert_eq!(sweep.to_string().lines().count(), 10);
+    }
+}
@@ -0,0 +1,165 @@
+use crate::line_table::LineTable;
+use elf::types::{STT_FUNC, STT_NOTYPE, STT_OBJECT};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub address: u32, // Without the Thumb bit
+    pub size: u32,
+}
+
+// The functions and objects named in the ELF .symtab, sorted by address
+#[derive(Debug, Clone, Default)]
+pub struct SymbolTable {
+    symbols: Vec<Symbol>,
+}
+
+impl SymbolTable {
+    pub fn new(mut symbols: Vec<Symbol>) -> Self {
+        symbols.sort_by_key(|s| s.address);
+        Self { symbols }
+    }
+
+    // A stripped binary has no symbols, which is not an error
+    pub fn from_elf(file: &elf::File) -> Self {
+        let section = match file.get_section(".symtab") {
+            Some(section) => section,
+            None => return Self::default(),
+        };
+        let symbols = file
+            .get_symbols(section)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|s| !s.name.is_empty() && s.shndx != 0)
+            // Mapping symbols ($t, $d) only mark where code and data start
+            .filter(|s| !s.name.starts_with('$'))
+            .filter(|s| [STT_FUNC, STT_OBJECT, STT_NOTYPE].contains(&s.symtype))
+            .map(|s| Symbol {
+                name: s.name,
+                address: s.value as u32 & 0xFFFFFFFE,
+                size: s.size as u32,
+            })
+            .collect();
+        Self::new(symbols)
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<u32> {
+        self.symbols
+            .iter()
+            .find(|s| s.name == name)
+            .map(|s| s.address)
+    }
+
+    pub fn symbols(&self) -> &[Symbol] {
+        &self.symbols
+    }
+
+    // The closest symbol at or before the address, as name+0xoffset
+    pub fn describe(&self, address: u32) -> Option<String> {
+        let index = self.symbols.partition_point(|s| s.address <= address);
+        let symbol = self.symbols[..index]
+            .iter()
+            .rev()
+            // Labels have no size, so are assumed to cover everything up to the next symbol
+            .find(|s| s.size == 0 || address < s.address + s.size)?;
+        match address - symbol.address {
+            0 => Some(symbol.name.clone()),
+            offset => Some(format!("{}+{:#x}", symbol.name, offset)),
+        }
+    }
+}
+
+// Everything known about where an address came from, so it can be shown to the user
+#[derive(Debug, Clone, Default)]
+pub struct DebugInfo {
+    pub symbols: SymbolTable,
+    pub lines: LineTable,
+}
+
+impl DebugInfo {
+    pub fn from_elf(file: &elf::File) -> Self {
+        let section = |name| match file.get_section(name) {
+            Some(section) => &section.data[..],
+            None => &[],
+        };
+        Self {
+            symbols: SymbolTable::from_elf(file),
+            lines: LineTable::parse(
+                section(".debug_line"),
+                section(".debug_str"),
+                section(".debug_line_str"),
+            ),
+        }
+    }
+
+    // e.g. main+0x4 (main.c:12)
+    pub fn describe(&self, address: u32) -> Option<String> {
+        let symbol = self.symbols.describe(address);
+        let line = self
+            .lines
+            .lookup(address)
+            .map(|(file, line)| format!("{}:{}", file, line));
+        match (symbol, line) {
+            (Some(symbol), Some(line)) => Some(format!("{} ({})", symbol, line)),
+            (symbol, line) => symbol.or(line),
+        }
+    }
+
+    // The address of a symbol, symbol+offset or file:line
+    pub fn location(&self, location: &str) -> Option<u32> {
+        if let Some((file, line)) = location.rsplit_once(':') {
+            return self.lines.address_of(file, line.parse().ok()?);
+        }
+        let (name, offset) = match location.split_once('+') {
+            Some((name, offset)) => {
+                let offset = match offset.strip_prefix("0x") {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                    None => offset.parse().ok()?,
+                };
+                (name, offset)
+            }
+            None => (location, 0),
+        };
+        Some(self.symbols.lookup(name)? + offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn describe_addresses() {
+        let symbols = SymbolTable::new(vec![
+            Symbol {
+                name: "main".to_string(),
+                address: 0x1000,
+                size: 8,
+            },
+            Symbol {
+                name: "data".to_string(),
+                address: 0x2000,
+                size: 4,
+            },
+            Symbol {
+                name: "loop".to_string(),
+                address: 0x1004,
+                size: 0,
+            },
+        ]);
+        assert_eq!(symbols.describe(0x1000).as_deref(), Some("main"));
+        assert_eq!(symbols.describe(0x1002).as_deref(), Some("main+0x2"));
+        assert_eq!(symbols.describe(0x1006).as_deref(), Some("loop+0x2"));
+        assert_eq!(symbols.describe(0xFFF), None);
+
+        let info = DebugInfo {
+            symbols,
+            lines: Default::default(),
+        };
+        assert_eq!(info.describe(0x2002).as_deref(), Some("data+0x2"));
+        assert_eq!(info.location("main+0x6"), Some(0x1006));
+        assert_eq!(info.location("loop+6"), Some(0x100A));
+        assert_eq!(info.location("main.c:3"), None);
+        assert_eq!(info.location("nowhere"), None);
+    }
+}